@@ -0,0 +1,45 @@
+use chrono::{Local, NaiveDateTime};
+use tauri::State;
+
+use crate::core::{parse_time, DatabaseManager, DbPool, DATE_EXAMPLES};
+
+// Commands for the app's own windows. Anything that changes or deletes history goes here rather
+// than on the HTTP server, which other hosts and any web page in a browser can reach.
+
+// Reads a time given as unix millis or as a date in the user's time zone, like the server does
+fn read_time(value: &str) -> Result<NaiveDateTime, String> {
+    parse_time(value, &Local::now())
+        .ok_or_else(|| format!("\"{}\" isn't a time, try {}", value, DATE_EXAMPLES))
+}
+
+// Runs `f` on the writer off the async runtime, with any error as a message for the window
+async fn write_db<F, R>(db: &DbPool, f: F) -> Result<R, String>
+where
+    F: FnOnce(&mut DatabaseManager) -> rusqlite::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || db.write(f))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+// Re-runs OCR for every frame between `from` and `to`, returns how many were queued
+#[tauri::command]
+pub async fn requeue_ocr(db: State<'_, DbPool>, from: String, to: String) -> Result<usize, String> {
+    let (from, to) = (read_time(&from)?, read_time(&to)?);
+    write_db(&db, move |db| db.requeue_ocr_range(from, to)).await
+}
+
+// Deletes every frame between `from` and `to` with its text, OCR results and embeddings,
+// returns how many frames were deleted
+#[tauri::command]
+pub async fn forget_frames(
+    db: State<'_, DbPool>,
+    from: String,
+    to: String,
+) -> Result<usize, String> {
+    let (from, to) = (read_time(&from)?, read_time(&to)?);
+    write_db(&db, move |db| db.forget_range(from, to)).await
+}
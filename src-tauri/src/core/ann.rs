@@ -0,0 +1,555 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::vector::{dot, norm};
+
+const MAGIC: &[u8; 8] = b"XREMHNSW";
+const FORMAT_VERSION: u32 = 1;
+// Links per node on the upper layers, layer 0 gets twice as many
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 100;
+const MIN_EF_SEARCH: usize = 64;
+
+// Approximate nearest neighbour index over normalized vectors (HNSW), keyed by the ID of
+// the vector's row in the database. Removed vectors stay in the graph to keep it connected
+// but are never returned, `compact` rebuilds without them.
+pub struct HnswIndex {
+    dimensions: usize,
+    nodes: Vec<Node>,
+    ids: HashMap<i64, usize>,
+    entry_point: Option<usize>,
+    deleted_count: usize,
+    // Highest row ID inserted, rows after it still need to be added
+    watermark: i64,
+    rng_state: u64,
+}
+
+struct Node {
+    id: i64,
+    vector: Vec<f32>,
+    // Neighbours on each layer the node is part of
+    neighbors: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    similarity: f32,
+    node: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl HnswIndex {
+    pub fn new(dimensions: usize) -> HnswIndex {
+        HnswIndex {
+            dimensions,
+            nodes: Vec::new(),
+            ids: HashMap::new(),
+            entry_point: None,
+            deleted_count: 0,
+            watermark: 0,
+            rng_state: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.deleted_count
+    }
+
+    pub fn watermark(&self) -> i64 {
+        self.watermark
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        self.ids
+            .get(&id)
+            .map(|node| !self.nodes[*node].deleted)
+            .unwrap_or(false)
+    }
+
+    // IDs of every vector that hasn't been removed
+    pub fn ids(&self) -> Vec<i64> {
+        self.nodes
+            .iter()
+            .filter(|node| !node.deleted)
+            .map(|node| node.id)
+            .collect()
+    }
+
+    pub fn insert(&mut self, id: i64, vector: &[f32]) {
+        self.watermark = self.watermark.max(id);
+        if vector.len() != self.dimensions || self.ids.contains_key(&id) {
+            return;
+        }
+
+        let vector = normalized(vector);
+        let level = self.random_level();
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            id,
+            vector,
+            neighbors: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.ids.insert(id, node);
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                self.entry_point = Some(node);
+                return;
+            }
+        };
+
+        let query = self.nodes[node].vector.clone();
+        let top_level = self.nodes[entry_point].neighbors.len() - 1;
+        let mut closest = entry_point;
+        for layer in (level + 1..=top_level).rev() {
+            closest = self.greedy_closest(&query, closest, layer);
+        }
+
+        for layer in (0..=level.min(top_level)).rev() {
+            let candidates = self.search_layer(&query, &[closest], EF_CONSTRUCTION, layer);
+            let selected = self.select_neighbors(&candidates, M);
+            for &neighbor in &selected {
+                self.nodes[neighbor].neighbors[layer].push(node);
+                self.prune(neighbor, layer);
+            }
+            self.nodes[node].neighbors[layer] = selected;
+            closest = candidates[0].node;
+        }
+
+        if level > top_level {
+            self.entry_point = Some(node);
+        }
+    }
+
+    // Returns whether the ID was in the index
+    pub fn remove(&mut self, id: i64) -> bool {
+        match self.ids.get(&id) {
+            Some(&node) if !self.nodes[node].deleted => {
+                self.nodes[node].deleted = true;
+                self.deleted_count += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Returns up to `k` (id, cosine similarity) pairs, most similar first
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(i64, f32)> {
+        let entry_point = match self.entry_point {
+            Some(entry_point) if query.len() == self.dimensions => entry_point,
+            _ => return vec![],
+        };
+
+        let query = normalized(query);
+        let mut closest = entry_point;
+        for layer in (1..self.nodes[entry_point].neighbors.len()).rev() {
+            closest = self.greedy_closest(&query, closest, layer);
+        }
+
+        // Removed nodes take up room in the candidate list, so widen it to make up for them
+        let deleted_share = self.deleted_count as f32 / self.nodes.len() as f32;
+        let ef = ((k.max(MIN_EF_SEARCH) as f32) / (1.0 - deleted_share).max(0.1)) as usize;
+        self.search_layer(&query, &[closest], ef, 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.node].deleted)
+            .take(k)
+            .map(|c| (self.nodes[c.node].id, c.similarity))
+            .collect()
+    }
+
+    // Whether enough has been removed that rebuilding is worth it
+    pub fn needs_compaction(&self) -> bool {
+        self.deleted_count > 0 && self.deleted_count * 4 > self.nodes.len()
+    }
+
+    pub fn compact(&self) -> HnswIndex {
+        let mut index = HnswIndex::new(self.dimensions);
+        for node in self.nodes.iter().filter(|node| !node.deleted) {
+            index.insert(node.id, &node.vector);
+        }
+        index.watermark = self.watermark;
+        index
+    }
+
+    // Writes to a temporary file first, so a crash mid-save leaves the previous index intact
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(MAGIC)?;
+            write_u32(&mut writer, FORMAT_VERSION)?;
+            write_u32(&mut writer, self.dimensions as u32)?;
+            write_i64(&mut writer, self.watermark)?;
+            write_i64(
+                &mut writer,
+                self.entry_point.map(|e| e as i64).unwrap_or(-1),
+            )?;
+            write_u32(&mut writer, self.nodes.len() as u32)?;
+            for node in &self.nodes {
+                write_i64(&mut writer, node.id)?;
+                writer.write_all(&[node.deleted as u8])?;
+                for value in &node.vector {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                write_u32(&mut writer, node.neighbors.len() as u32)?;
+                for layer in &node.neighbors {
+                    write_u32(&mut writer, layer.len() as u32)?;
+                    for neighbor in layer {
+                        write_u32(&mut writer, *neighbor as u32)?;
+                    }
+                }
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(temp_path, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<HnswIndex> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut reader)? != FORMAT_VERSION {
+            return Err(invalid_data(
+                "Not a vector index, or from an unsupported version",
+            ));
+        }
+
+        let dimensions = read_u32(&mut reader)? as usize;
+        let mut index = HnswIndex::new(dimensions);
+        index.watermark = read_i64(&mut reader)?;
+        let entry_point = read_i64(&mut reader)?;
+        let node_count = read_u32(&mut reader)? as usize;
+
+        for node in 0..node_count {
+            let id = read_i64(&mut reader)?;
+            let mut deleted = [0u8; 1];
+            reader.read_exact(&mut deleted)?;
+            let mut vector = Vec::with_capacity(dimensions);
+            for _ in 0..dimensions {
+                let mut bytes = [0u8; 4];
+                reader.read_exact(&mut bytes)?;
+                vector.push(f32::from_le_bytes(bytes));
+            }
+            let layer_count = read_u32(&mut reader)? as usize;
+            let mut neighbors = Vec::with_capacity(layer_count);
+            for _ in 0..layer_count {
+                let count = read_u32(&mut reader)? as usize;
+                let mut layer = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let neighbor = read_u32(&mut reader)? as usize;
+                    if neighbor >= node_count {
+                        return Err(invalid_data("Neighbour out of range"));
+                    }
+                    layer.push(neighbor);
+                }
+                neighbors.push(layer);
+            }
+            if neighbors.is_empty() {
+                return Err(invalid_data("Node without layers"));
+            }
+
+            let deleted = deleted[0] != 0;
+            index.deleted_count += deleted as usize;
+            index.ids.insert(id, node);
+            index.nodes.push(Node {
+                id,
+                vector,
+                neighbors,
+                deleted,
+            });
+        }
+
+        index.entry_point = match entry_point {
+            -1 if node_count == 0 => None,
+            e if e >= 0 && (e as usize) < node_count => Some(e as usize),
+            _ => return Err(invalid_data("Entry point out of range")),
+        };
+        Ok(index)
+    }
+
+    fn similarity(&self, query: &[f32], node: usize) -> f32 {
+        dot(query, &self.nodes[node].vector)
+    }
+
+    fn greedy_closest(&self, query: &[f32], start: usize, layer: usize) -> usize {
+        let mut closest = start;
+        let mut best = self.similarity(query, closest);
+        loop {
+            let mut improved = false;
+            for &neighbor in &self.nodes[closest].neighbors[layer] {
+                let similarity = self.similarity(query, neighbor);
+                if similarity > best {
+                    best = similarity;
+                    closest = neighbor;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return closest;
+            }
+        }
+    }
+
+    // Best-first search of one layer, returns up to `ef` candidates most similar first
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited = HashSet::new();
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+
+        for &node in entry_points {
+            visited.insert(node);
+            let candidate = Candidate {
+                similarity: self.similarity(query, node),
+                node,
+            };
+            candidates.push(candidate);
+            results.push(Reverse(candidate));
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = results.peek().map(|r| r.0.similarity).unwrap_or(f32::MIN);
+            if candidate.similarity < worst && results.len() >= ef {
+                break;
+            }
+            let neighbors = match self.nodes[candidate.node].neighbors.get(layer) {
+                Some(neighbors) => neighbors,
+                None => continue,
+            };
+            for &neighbor in neighbors {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let similarity = self.similarity(query, neighbor);
+                let worst = results.peek().map(|r| r.0.similarity).unwrap_or(f32::MIN);
+                if results.len() < ef || similarity > worst {
+                    let next = Candidate {
+                        similarity,
+                        node: neighbor,
+                    };
+                    candidates.push(next);
+                    results.push(Reverse(next));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results = results.into_iter().map(|r| r.0).collect::<Vec<_>>();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    // Keeps candidates that are closer to the base than to an already selected neighbour,
+    // which spreads links out across clusters, then tops up with the closest of the rest
+    fn select_neighbors(&self, candidates: &[Candidate], m: usize) -> Vec<usize> {
+        let mut selected: Vec<usize> = Vec::with_capacity(m);
+        let mut skipped = Vec::new();
+        for candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            let diverse = selected.iter().all(|&s| {
+                dot(&self.nodes[candidate.node].vector, &self.nodes[s].vector)
+                    < candidate.similarity
+            });
+            if diverse {
+                selected.push(candidate.node);
+            } else {
+                skipped.push(candidate.node);
+            }
+        }
+        for node in skipped {
+            if selected.len() >= m {
+                break;
+            }
+            selected.push(node);
+        }
+        selected
+    }
+
+    fn prune(&mut self, node: usize, layer: usize) {
+        let max_links = if layer == 0 { M * 2 } else { M };
+        if self.nodes[node].neighbors[layer].len() <= max_links {
+            return;
+        }
+        let base = &self.nodes[node].vector;
+        let mut candidates = self.nodes[node].neighbors[layer]
+            .iter()
+            .map(|&neighbor| Candidate {
+                similarity: dot(base, &self.nodes[neighbor].vector),
+                node: neighbor,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.cmp(a));
+        self.nodes[node].neighbors[layer] = self.select_neighbors(&candidates, max_links);
+    }
+
+    // Layer for a new node, each layer up holds roughly 1/M of the one below
+    fn random_level(&mut self) -> usize {
+        // xorshift64*
+        self.rng_state ^= self.rng_state >> 12;
+        self.rng_state ^= self.rng_state << 25;
+        self.rng_state ^= self.rng_state >> 27;
+        let random = self.rng_state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        let uniform = ((random >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-uniform.ln() / (M as f64).ln()) as usize
+    }
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let length = norm(vector);
+    if length == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|v| v / length).collect()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_i64(writer: &mut impl Write, value: i64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i64(reader: &mut impl Read) -> io::Result<i64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::vector::cosine_similarity;
+
+    fn random_vectors(count: usize, dimensions: usize) -> Vec<Vec<f32>> {
+        let mut state = 42u64;
+        (0..count)
+            .map(|_| {
+                (0..dimensions)
+                    .map(|_| {
+                        state = state
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        ((state >> 33) as f32 / (1u64 << 31) as f32) - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn exact_top(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<i64> {
+        let mut scored = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i as i64 + 1, cosine_similarity(query, v)))
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.into_iter().take(k).map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn test_search_recall_against_exact() {
+        let vectors = random_vectors(2000, 32);
+        let mut index = HnswIndex::new(32);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(i as i64 + 1, vector);
+        }
+
+        let queries = random_vectors(20, 32);
+        let mut found = 0;
+        for query in &queries {
+            let expected = exact_top(&vectors, query, 10);
+            let results = index.search(query, 10);
+            found += results
+                .iter()
+                .filter(|(id, _)| expected.contains(id))
+                .count();
+        }
+        let recall = found as f32 / (queries.len() * 10) as f32;
+        assert!(recall > 0.9, "recall {}", recall);
+    }
+
+    #[test]
+    fn test_removed_vectors_are_not_returned() {
+        let vectors = random_vectors(200, 8);
+        let mut index = HnswIndex::new(8);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(i as i64 + 1, vector);
+        }
+
+        assert_eq!(5, index.search(&vectors[4], 1)[0].0);
+        assert!(index.remove(5));
+        assert!(!index.remove(5));
+        assert!(index.search(&vectors[4], 10).iter().all(|(id, _)| *id != 5));
+        assert_eq!(199, index.len());
+        assert_eq!(199, index.compact().len());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let vectors = random_vectors(100, 8);
+        let mut index = HnswIndex::new(8);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.insert(i as i64 + 1, vector);
+        }
+        index.remove(3);
+
+        let path = std::env::temp_dir().join(format!("xrem-ann-{}.hnsw", std::process::id()));
+        index.save(&path).unwrap();
+        let loaded = HnswIndex::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(index.len(), loaded.len());
+        assert_eq!(100, loaded.watermark());
+        assert!(!loaded.contains(3));
+        assert_eq!(
+            index.search(&vectors[10], 5),
+            loaded.search(&vectors[10], 5)
+        );
+    }
+}
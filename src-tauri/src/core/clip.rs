@@ -0,0 +1,431 @@
+// CLIP, an image encoder and a text encoder trained so an image and a text describing it get
+// similar vectors. Weight names follow Hugging Face's CLIPModel, so its checkpoints (e.g.
+// openai/clip-vit-base-patch32) load as they are.
+use candle::{Device, Module, Result, Tensor, D};
+use candle_nn::{Conv2dConfig, Embedding, LayerNorm, Linear, VarBuilder};
+use image::{imageops::FilterType, DynamicImage};
+use serde::Deserialize;
+
+// Per channel mean and standard deviation of the images CLIP was trained on
+const IMAGE_MEAN: [f32; 3] = [0.481_454_66, 0.457_827_5, 0.408_210_73];
+const IMAGE_STD: [f32; 3] = [0.268_629_54, 0.261_302_6, 0.275_777_1];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    #[default]
+    QuickGelu,
+    Gelu,
+}
+
+impl Module for Activation {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Activation::QuickGelu => xs * candle_nn::ops::sigmoid(&(xs * 1.702f64)?)?,
+            Activation::Gelu => xs.gelu_erf(),
+        }
+    }
+}
+
+// The parts of a Hugging Face CLIP config.json that are used, defaults are those of
+// clip-vit-base-patch32
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClipConfig {
+    #[serde(default)]
+    pub text_config: ClipTextConfig,
+    #[serde(default)]
+    pub vision_config: ClipVisionConfig,
+    #[serde(default = "default_projection_dim")]
+    pub projection_dim: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClipTextConfig {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub max_position_embeddings: usize,
+    pub hidden_act: Activation,
+    pub layer_norm_eps: f64,
+}
+
+impl Default for ClipTextConfig {
+    fn default() -> Self {
+        ClipTextConfig {
+            vocab_size: 49408,
+            hidden_size: 512,
+            intermediate_size: 2048,
+            num_hidden_layers: 12,
+            num_attention_heads: 8,
+            max_position_embeddings: 77,
+            hidden_act: Activation::QuickGelu,
+            layer_norm_eps: 1e-5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClipVisionConfig {
+    pub hidden_size: usize,
+    pub intermediate_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub image_size: usize,
+    pub patch_size: usize,
+    pub hidden_act: Activation,
+    pub layer_norm_eps: f64,
+}
+
+impl Default for ClipVisionConfig {
+    fn default() -> Self {
+        ClipVisionConfig {
+            hidden_size: 768,
+            intermediate_size: 3072,
+            num_hidden_layers: 12,
+            num_attention_heads: 12,
+            image_size: 224,
+            patch_size: 32,
+            hidden_act: Activation::QuickGelu,
+            layer_norm_eps: 1e-5,
+        }
+    }
+}
+
+fn default_projection_dim() -> usize {
+    512
+}
+
+// What the text and vision encoders have in common
+struct EncoderConfig {
+    hidden_size: usize,
+    intermediate_size: usize,
+    num_hidden_layers: usize,
+    num_attention_heads: usize,
+    hidden_act: Activation,
+    layer_norm_eps: f64,
+}
+
+impl From<&ClipTextConfig> for EncoderConfig {
+    fn from(c: &ClipTextConfig) -> Self {
+        EncoderConfig {
+            hidden_size: c.hidden_size,
+            intermediate_size: c.intermediate_size,
+            num_hidden_layers: c.num_hidden_layers,
+            num_attention_heads: c.num_attention_heads,
+            hidden_act: c.hidden_act,
+            layer_norm_eps: c.layer_norm_eps,
+        }
+    }
+}
+
+impl From<&ClipVisionConfig> for EncoderConfig {
+    fn from(c: &ClipVisionConfig) -> Self {
+        EncoderConfig {
+            hidden_size: c.hidden_size,
+            intermediate_size: c.intermediate_size,
+            num_hidden_layers: c.num_hidden_layers,
+            num_attention_heads: c.num_attention_heads,
+            hidden_act: c.hidden_act,
+            layer_norm_eps: c.layer_norm_eps,
+        }
+    }
+}
+
+struct Attention {
+    q_proj: Linear,
+    k_proj: Linear,
+    v_proj: Linear,
+    out_proj: Linear,
+    num_heads: usize,
+    head_dim: usize,
+}
+
+impl Attention {
+    fn new(vb: VarBuilder, c: &EncoderConfig) -> Result<Self> {
+        let size = c.hidden_size;
+        Ok(Attention {
+            q_proj: candle_nn::linear(size, size, vb.pp("q_proj"))?,
+            k_proj: candle_nn::linear(size, size, vb.pp("k_proj"))?,
+            v_proj: candle_nn::linear(size, size, vb.pp("v_proj"))?,
+            out_proj: candle_nn::linear(size, size, vb.pp("out_proj"))?,
+            num_heads: c.num_attention_heads,
+            head_dim: size / c.num_attention_heads,
+        })
+    }
+
+    // `mask` is added to the attention scores, (seq_len, seq_len)
+    fn forward(&self, xs: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let (batch, seq_len, size) = xs.dims3()?;
+        let heads = |t: Tensor| {
+            t.reshape((batch, seq_len, self.num_heads, self.head_dim))?
+                .transpose(1, 2)?
+                .contiguous()
+        };
+        let q = heads((self.q_proj.forward(xs)? * (self.head_dim as f64).powf(-0.5))?)?;
+        let k = heads(self.k_proj.forward(xs)?)?;
+        let v = heads(self.v_proj.forward(xs)?)?;
+
+        let mut scores = q.matmul(&k.t()?)?;
+        if let Some(mask) = mask {
+            scores = scores.broadcast_add(mask)?;
+        }
+        let weights = candle_nn::ops::softmax(&scores, D::Minus1)?;
+        let output = weights
+            .matmul(&v)?
+            .transpose(1, 2)?
+            .reshape((batch, seq_len, size))?;
+        self.out_proj.forward(&output)
+    }
+}
+
+struct EncoderLayer {
+    self_attn: Attention,
+    layer_norm1: LayerNorm,
+    fc1: Linear,
+    fc2: Linear,
+    activation: Activation,
+    layer_norm2: LayerNorm,
+}
+
+impl EncoderLayer {
+    fn new(vb: VarBuilder, c: &EncoderConfig) -> Result<Self> {
+        Ok(EncoderLayer {
+            self_attn: Attention::new(vb.pp("self_attn"), c)?,
+            layer_norm1: candle_nn::layer_norm(
+                c.hidden_size,
+                c.layer_norm_eps,
+                vb.pp("layer_norm1"),
+            )?,
+            fc1: candle_nn::linear(c.hidden_size, c.intermediate_size, vb.pp("mlp.fc1"))?,
+            fc2: candle_nn::linear(c.intermediate_size, c.hidden_size, vb.pp("mlp.fc2"))?,
+            activation: c.hidden_act,
+            layer_norm2: candle_nn::layer_norm(
+                c.hidden_size,
+                c.layer_norm_eps,
+                vb.pp("layer_norm2"),
+            )?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let attended = self
+            .self_attn
+            .forward(&self.layer_norm1.forward(xs)?, mask)?;
+        let xs = (xs + attended)?;
+        let hidden = self
+            .activation
+            .forward(&self.fc1.forward(&self.layer_norm2.forward(&xs)?)?)?;
+        xs + self.fc2.forward(&hidden)?
+    }
+}
+
+struct Encoder {
+    layers: Vec<EncoderLayer>,
+}
+
+impl Encoder {
+    fn new(vb: VarBuilder, c: &EncoderConfig) -> Result<Self> {
+        let layers = (0..c.num_hidden_layers)
+            .map(|i| EncoderLayer::new(vb.pp(format!("layers.{}", i)), c))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Encoder { layers })
+    }
+
+    fn forward(&self, xs: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let mut xs = xs.clone();
+        for layer in &self.layers {
+            xs = layer.forward(&xs, mask)?;
+        }
+        Ok(xs)
+    }
+}
+
+struct TextTransformer {
+    token_embedding: Embedding,
+    position_embedding: Embedding,
+    encoder: Encoder,
+    final_layer_norm: LayerNorm,
+}
+
+impl TextTransformer {
+    fn new(vb: VarBuilder, c: &ClipTextConfig) -> Result<Self> {
+        Ok(TextTransformer {
+            token_embedding: candle_nn::embedding(
+                c.vocab_size,
+                c.hidden_size,
+                vb.pp("embeddings.token_embedding"),
+            )?,
+            position_embedding: candle_nn::embedding(
+                c.max_position_embeddings,
+                c.hidden_size,
+                vb.pp("embeddings.position_embedding"),
+            )?,
+            encoder: Encoder::new(vb.pp("encoder"), &c.into())?,
+            final_layer_norm: candle_nn::layer_norm(
+                c.hidden_size,
+                c.layer_norm_eps,
+                vb.pp("final_layer_norm"),
+            )?,
+        })
+    }
+
+    // The hidden state of the last token (the end of text token), for a single text
+    fn forward(&self, token_ids: &[u32]) -> Result<Tensor> {
+        let device = self.token_embedding.embeddings().device();
+        let seq_len = token_ids.len();
+        let ids = Tensor::new(token_ids, device)?.unsqueeze(0)?;
+        let positions = Tensor::arange(0u32, seq_len as u32, device)?.unsqueeze(0)?;
+        let xs = self
+            .token_embedding
+            .forward(&ids)?
+            .broadcast_add(&self.position_embedding.forward(&positions)?)?;
+
+        // Each token only sees the ones before it
+        let mask = (0..seq_len)
+            .flat_map(|i| (0..seq_len).map(move |j| if j > i { f32::MIN } else { 0.0 }))
+            .collect::<Vec<_>>();
+        let mask = Tensor::from_vec(mask, (seq_len, seq_len), device)?;
+        let xs = self.encoder.forward(&xs, Some(&mask))?;
+        self.final_layer_norm.forward(&xs)?.get(0)?.get(seq_len - 1)
+    }
+}
+
+struct VisionTransformer {
+    class_embedding: Tensor,
+    patch_embedding: candle_nn::Conv2d,
+    position_embedding: Embedding,
+    num_positions: usize,
+    pre_layernorm: LayerNorm,
+    encoder: Encoder,
+    post_layernorm: LayerNorm,
+}
+
+impl VisionTransformer {
+    fn new(vb: VarBuilder, c: &ClipVisionConfig) -> Result<Self> {
+        let num_positions = (c.image_size / c.patch_size).pow(2) + 1;
+        let conv = Conv2dConfig {
+            stride: c.patch_size,
+            ..Default::default()
+        };
+        Ok(VisionTransformer {
+            class_embedding: vb.get(c.hidden_size, "embeddings.class_embedding")?,
+            patch_embedding: candle_nn::conv2d_no_bias(
+                3,
+                c.hidden_size,
+                c.patch_size,
+                conv,
+                vb.pp("embeddings.patch_embedding"),
+            )?,
+            position_embedding: candle_nn::embedding(
+                num_positions,
+                c.hidden_size,
+                vb.pp("embeddings.position_embedding"),
+            )?,
+            num_positions,
+            // Spelled this way in the checkpoints
+            pre_layernorm: candle_nn::layer_norm(
+                c.hidden_size,
+                c.layer_norm_eps,
+                vb.pp("pre_layrnorm"),
+            )?,
+            encoder: Encoder::new(vb.pp("encoder"), &c.into())?,
+            post_layernorm: candle_nn::layer_norm(
+                c.hidden_size,
+                c.layer_norm_eps,
+                vb.pp("post_layernorm"),
+            )?,
+        })
+    }
+
+    // The hidden state of the class token for each image, `pixels` is (batch, 3, size, size)
+    fn forward(&self, pixels: &Tensor) -> Result<Tensor> {
+        let batch = pixels.dim(0)?;
+        // (batch, hidden, grid, grid) to (batch, patches, hidden)
+        let patches = self
+            .patch_embedding
+            .forward(pixels)?
+            .flatten_from(2)?
+            .transpose(1, 2)?;
+        let hidden_size = patches.dim(2)?;
+        let class = self
+            .class_embedding
+            .reshape((1, 1, hidden_size))?
+            .broadcast_as((batch, 1, hidden_size))?;
+        let positions =
+            Tensor::arange(0u32, self.num_positions as u32, pixels.device())?.unsqueeze(0)?;
+        let xs = Tensor::cat(&[&class, &patches], 1)?
+            .broadcast_add(&self.position_embedding.forward(&positions)?)?;
+        let xs = self
+            .encoder
+            .forward(&self.pre_layernorm.forward(&xs)?, None)?;
+        self.post_layernorm
+            .forward(&xs.narrow(1, 0, 1)?.squeeze(1)?)
+    }
+}
+
+pub struct ClipModel {
+    text_model: TextTransformer,
+    vision_model: VisionTransformer,
+    text_projection: Linear,
+    visual_projection: Linear,
+    image_size: usize,
+}
+
+impl ClipModel {
+    pub fn new(vb: VarBuilder, c: &ClipConfig) -> Result<Self> {
+        Ok(ClipModel {
+            text_model: TextTransformer::new(vb.pp("text_model"), &c.text_config)?,
+            vision_model: VisionTransformer::new(vb.pp("vision_model"), &c.vision_config)?,
+            text_projection: candle_nn::linear_no_bias(
+                c.text_config.hidden_size,
+                c.projection_dim,
+                vb.pp("text_projection"),
+            )?,
+            visual_projection: candle_nn::linear_no_bias(
+                c.vision_config.hidden_size,
+                c.projection_dim,
+                vb.pp("visual_projection"),
+            )?,
+            image_size: c.vision_config.image_size,
+        })
+    }
+
+    // The vector of each image, (batch, projection_dim)
+    pub fn embed_images(&self, images: &[DynamicImage]) -> Result<Tensor> {
+        let device = self.text_projection.weight().device();
+        let pixels = images
+            .iter()
+            .map(|image| image_to_tensor(image, self.image_size, device))
+            .collect::<Result<Vec<_>>>()?;
+        let pixels = Tensor::stack(&pixels, 0)?;
+        self.visual_projection
+            .forward(&self.vision_model.forward(&pixels)?)
+    }
+
+    // The vector of a tokenized text, (1, projection_dim)
+    pub fn embed_text(&self, token_ids: &[u32]) -> Result<Tensor> {
+        let pooled = self.text_model.forward(token_ids)?.unsqueeze(0)?;
+        self.text_projection.forward(&pooled)
+    }
+}
+
+// Scales an image to the model's input size and normalizes it, (3, size, size). Screenshots
+// are squashed rather than cropped to a square so nothing at the edges is lost.
+fn image_to_tensor(image: &DynamicImage, size: usize, device: &Device) -> Result<Tensor> {
+    let rgb = image
+        .resize_exact(size as u32, size as u32, FilterType::Triangle)
+        .to_rgb8();
+    let mut values = vec![0f32; 3 * size * size];
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        for channel in 0..3 {
+            let value = pixel[channel] as f32 / 255.0;
+            values[channel * size * size + y as usize * size + x as usize] =
+                (value - IMAGE_MEAN[channel]) / IMAGE_STD[channel];
+        }
+    }
+    Tensor::from_vec(values, (3, size, size), device)
+}
@@ -1,7 +1,6 @@
-use crate::core::DatabaseManager;
+use crate::core::{DatabaseManager, DbPool, Governor, OcrWorker};
 use chrono::Utc;
 use image::DynamicImage;
-use rusty_tesseract::{image_to_string, Args, Image};
 use screenshots::Screen;
 use std::io::Cursor;
 use std::io::Write;
@@ -13,12 +12,11 @@ use std::thread;
 use std::time::Duration;
 use threadpool::ThreadPool;
 
-use super::embed;
-
 const FRAME_BUFFER_SIZE: usize = 30;
-const SCREENSHOT_INTERVAL: Duration = Duration::from_secs(2);
-const OCR_THREAD_POOL_SIZE: usize = 4;
-const IMAGE_ENCODE_THREADS: usize = 4;
+pub const SCREENSHOT_INTERVAL: Duration = Duration::from_secs(2);
+// A video chunk's rows say where its frames are, so writing them is retried before giving up
+const CHUNK_WRITE_ATTEMPTS: u32 = 3;
+const CHUNK_WRITE_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 enum ControlMessage {
     Pause,
@@ -44,19 +42,13 @@ impl CaptureHandles {
 
 pub fn start_recording(
     local_data_dir: String,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
+    db: DbPool,
+    ocr_worker: Arc<OcrWorker>,
+    governor: Arc<Governor>,
 ) -> CaptureHandles {
-    let config_path = "models/gte-small/config.json";
-    let tokenizer_path = "models/gte-small/tokenizer.json";
-    let weights_path = "models/gte-small/model.safetensors";
-
     let (control_sender, control_receiver) = mpsc::channel();
 
-    // Initialize the model first
-    embed::init_model(config_path, tokenizer_path, weights_path, false);
-
     let frame_buffer = Arc::new((Mutex::new(Vec::new()), Condvar::new()));
-    let ocr_pool = ThreadPool::new(OCR_THREAD_POOL_SIZE);
 
     // Capture thread
     let buffer_clone = frame_buffer.clone();
@@ -64,10 +56,12 @@ pub fn start_recording(
     let local_data_dir_capture_handle = local_data_dir.clone();
 
     let db_capture_ref = db.clone();
+    let governor_capture_ref = governor.clone();
     let capture_handle = thread::spawn(move || {
         capture_screenshots(
             buffer_clone,
-            &ocr_pool,
+            ocr_worker,
+            governor_capture_ref,
             control_receiver,
             local_data_dir_capture_handle,
             db_capture_ref,
@@ -93,6 +87,7 @@ pub fn start_recording(
                 frames_to_process,
                 local_data_dir_stream_handle.clone(),
                 db_stream_ref.clone(),
+                governor.encode_workers(),
             );
         }
     });
@@ -106,10 +101,11 @@ pub fn start_recording(
 
 fn capture_screenshots(
     frame_buffer: Arc<(Mutex<Vec<DynamicImage>>, Condvar)>,
-    ocr_pool: &ThreadPool,
+    ocr_worker: Arc<OcrWorker>,
+    governor: Arc<Governor>,
     control_receiver: mpsc::Receiver<ControlMessage>,
     local_data_dir: String,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
+    db: DbPool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let screens = Screen::all()?;
     let screen = screens.first().unwrap();
@@ -129,6 +125,7 @@ fn capture_screenshots(
                         &frame_buffer,
                         local_data_dir_clone,
                         db_process_ref.clone(),
+                        governor.encode_workers(),
                     );
                     return Ok(());
                 }
@@ -143,39 +140,22 @@ fn capture_screenshots(
 
         let buffer = screen.capture()?;
         let image = DynamicImage::ImageRgba8(buffer.clone());
-
-        let db_frame_id_ref = db.clone();
-        let frame_id = {
-            let mut db_clone = db_frame_id_ref.lock().unwrap();
-            db_clone.as_mut().unwrap().insert_frame(None)?
+        let (app_name, window_title) = active_window();
+
+        // A frame that can't be stored is dropped, its image would be at the wrong place in
+        // the video otherwise
+        let frame_id = match db.write(move |db| db.insert_frame(app_name, window_title)) {
+            Ok(frame_id) => frame_id,
+            Err(e) => {
+                println!("Failed to store frame, skipping it: {}", e);
+                thread::sleep(SCREENSHOT_INTERVAL);
+                continue;
+            }
         };
 
-        let db_ocr_ref = db.clone();
-        // Send image to OCR thread pool
-        let image_clone = image.clone();
-        ocr_pool.execute(move || {
-            let _ocr_result = match perform_ocr(&image_clone) {
-                Ok(result) => {
-                    // Embed the recognized text!
-                    // let embeddings = embed::generate_embeddings(&result);
-                    // println!("Embeddings length: {}", embeddings.len);
-                    db_ocr_ref
-                        .lock()
-                        .unwrap()
-                        .as_mut()
-                        .unwrap()
-                        .insert_text_for_frame(frame_id, &result)
-                        .expect(&format!("Failed to insert text for frame: {}", frame_id));
-                    result
-                }
-                Err(e) => {
-                    println!("OCR Failed! {:?}", e);
-                    return;
-                }
-            };
-
-            // Here's where we'll write to the DB
-        });
+        // Hand the image to the OCR worker, the frame is already queued in the database so
+        // it's picked up from its video chunk if this doesn't get to it
+        ocr_worker.submit(frame_id, image.clone());
 
         let (lock, cvar) = &*frame_buffer;
         let mut frames = lock.lock().unwrap();
@@ -190,27 +170,38 @@ fn capture_screenshots(
     }
 }
 
-fn perform_ocr(dynamic_image: &DynamicImage) -> Result<String, Box<dyn std::error::Error>> {
-    let args = Args::default();
-    let image = Image::from_dynamic_image(dynamic_image).unwrap();
-
-    // OCR
-    let text = image_to_string(&image, &args)?;
-    println!("OCR: {}", text);
-
-    Ok(text)
+// The app and window title in the foreground, either is None if it can't be read (e.g. on the
+// desktop, or without permission to see other apps' windows)
+fn active_window() -> (Option<String>, Option<String>) {
+    let non_empty = |s: String| Some(s).filter(|s| !s.trim().is_empty());
+    match active_win_pos_rs::get_active_window() {
+        Ok(window) => (non_empty(window.app_name), non_empty(window.title)),
+        Err(()) => (None, None),
+    }
 }
 
 fn stream_to_ffmpeg(
     frames: Vec<DynamicImage>,
     local_data_dir: String,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
+    db: DbPool,
+    encode_threads: usize,
 ) {
-    let encode_pool = ThreadPool::new(IMAGE_ENCODE_THREADS); // Define NUM_ENCODE_THREADS based on your CPU
+    let encode_pool = ThreadPool::new(encode_threads);
+    let ffmpeg_threads = encode_threads.to_string();
     print!("getting ready to stream..");
     let time = Utc::now();
     let local_data_dir_clone = local_data_dir.clone();
     let output_name = format!("{}/output-{}.mp4", local_data_dir_clone, time);
+
+    let chunk_path = output_name.clone();
+    let chunk_id = match write_chunk(&db, move |db| db.start_new_video_chunk(&chunk_path)) {
+        Some(chunk_id) => chunk_id,
+        None => {
+            println!("Dropping {} frames without a video chunk", frames.len());
+            return;
+        }
+    };
+
     let mut child = Command::new("ffmpeg")
         .args([
             "-f",
@@ -229,21 +220,14 @@ fn stream_to_ffmpeg(
             "yuv420p",
             "-crf",
             "25",
+            "-threads",
+            &ffmpeg_threads,
             &output_name,
         ])
         .stdin(Stdio::piped())
         .spawn()
         .expect("Failed to start FFmpeg");
 
-    let _ = {
-        db.lock()
-            .unwrap()
-            .as_mut()
-            .unwrap()
-            .start_new_video_chunk(&output_name)
-            .expect("Failed to start a new video chunk")
-    };
-
     print!("opened stdin...");
     let mut stdin = child.stdin.take().expect("Failed to open stdin");
     let (sender, receiver) = channel();
@@ -284,12 +268,38 @@ fn stream_to_ffmpeg(
     println!("dropped");
     let _ = child.wait().expect("FFmpeg process wasn't running");
     println!("waited?");
+
+    // Only now can the chunk's frames be decoded for OCR. If this never gets through, the
+    // chunk is marked finished the next time the app starts.
+    write_chunk(&db, move |db| db.finish_video_chunk(chunk_id));
+}
+
+// Runs `f` on the writer, retrying it a few times, and logs why if it never succeeds
+fn write_chunk<F, R>(db: &DbPool, f: F) -> Option<R>
+where
+    F: Fn(&mut DatabaseManager) -> rusqlite::Result<R> + Clone + Send + 'static,
+    R: Send + 'static,
+{
+    for attempt in 1..=CHUNK_WRITE_ATTEMPTS {
+        match db.write(f.clone()) {
+            Ok(value) => return Some(value),
+            Err(e) => {
+                println!(
+                    "Failed to update video chunk (attempt {} of {}): {}",
+                    attempt, CHUNK_WRITE_ATTEMPTS, e
+                );
+                thread::sleep(CHUNK_WRITE_RETRY_DELAY);
+            }
+        }
+    }
+    None
 }
 
 fn process_remaining_frames(
     frame_buffer: &Arc<(Mutex<Vec<DynamicImage>>, Condvar)>,
     local_data_dir: String,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
+    db: DbPool,
+    encode_threads: usize,
 ) {
     let local_data_dir_clone = local_data_dir.clone();
     let (mutex, _) = &**frame_buffer;
@@ -297,6 +307,11 @@ fn process_remaining_frames(
 
     if !frames.is_empty() {
         let frames_to_process = frames.drain(..).collect::<Vec<_>>();
-        stream_to_ffmpeg(frames_to_process, local_data_dir_clone, db.clone());
+        stream_to_ffmpeg(
+            frames_to_process,
+            local_data_dir_clone,
+            db.clone(),
+            encode_threads,
+        );
     }
 }
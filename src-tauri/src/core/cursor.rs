@@ -0,0 +1,99 @@
+use chrono::NaiveDateTime;
+
+use super::db::PageKey;
+
+// Fractions of a second are kept so keys compare exactly like the stored timestamps
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.f";
+// Ranked pages are found by ranking every result before them, so none go this deep and a larger
+// offset can't have come from a cursor given out
+const MAX_OFFSET: i64 = 1_000_000;
+
+// Where the next page of results starts, passed to clients as an opaque string. Results
+// listed newest first continue after the last one shown, so frames recorded meanwhile don't
+// shift the pages. Ranked results continue after a number of them, counting only frames up to
+// the newest one when the first page was listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cursor {
+    After(PageKey),
+    Offset { offset: i64, up_to_frame: i64 },
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        match self {
+            Cursor::After(key) => format!(
+                "k{}_{}_{}",
+                key.timestamp.format(TIME_FORMAT),
+                key.frame_id,
+                key.span_id
+            ),
+            Cursor::Offset {
+                offset,
+                up_to_frame,
+            } => format!("o{}_{}", offset, up_to_frame),
+        }
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        if let Some(offset) = cursor.strip_prefix('o') {
+            let (offset, up_to_frame) = offset.split_once('_')?;
+            return Some(Cursor::Offset {
+                offset: offset
+                    .parse()
+                    .ok()
+                    .filter(|offset| (0..=MAX_OFFSET).contains(offset))?,
+                up_to_frame: up_to_frame.parse().ok()?,
+            });
+        }
+        let mut parts = cursor.strip_prefix('k')?.split('_');
+        let key = PageKey {
+            timestamp: NaiveDateTime::parse_from_str(parts.next()?, TIME_FORMAT).ok()?,
+            frame_id: parts.next()?.parse().ok()?,
+            span_id: parts.next()?.parse().ok()?,
+        };
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Cursor::After(key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_cursors_survive_encoding() {
+        let date = NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+        for timestamp in [
+            date.and_hms_nano_opt(9, 30, 5, 123_456_789).unwrap(),
+            date.and_hms_opt(9, 30, 5).unwrap(),
+        ] {
+            let cursor = Cursor::After(PageKey {
+                timestamp,
+                frame_id: 42,
+                span_id: 7,
+            });
+            assert_eq!(Some(cursor), Cursor::decode(&cursor.encode()));
+        }
+        let cursor = Cursor::Offset {
+            offset: 60,
+            up_to_frame: 1200,
+        };
+        assert_eq!(Some(cursor), Cursor::decode(&cursor.encode()));
+
+        for malformed in [
+            "",
+            "o-5_1200",
+            "o9223372036854775807_1",
+            "o60",
+            "o60_1200_1",
+            "x12",
+            "k20261014T093005_42",
+            "k20261014T093005_42_7_1",
+        ] {
+            assert_eq!(None, Cursor::decode(malformed));
+        }
+    }
+}
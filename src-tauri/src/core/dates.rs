@@ -0,0 +1,272 @@
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+// Shown when a date can't be read
+pub const DATE_EXAMPLES: &str =
+    "2026-10-01, \"2026-10-01 14:30\", \"yesterday afternoon\", \"last friday\" or \"2h ago\"";
+
+// When each part of the day starts
+const PARTS_OF_DAY: [(&str, u32); 7] = [
+    ("midnight", 0),
+    ("morning", 6),
+    ("noon", 12),
+    ("midday", 12),
+    ("afternoon", 12),
+    ("evening", 18),
+    ("night", 21),
+];
+
+// Reads a date typed by the user, relative to `now` and in its time zone, and returns it as
+// UTC like the frame timestamps. Understands a day (2026-10-01, today, yesterday, "friday" or
+// "last friday" for the most recent one before today), a time of day (14:30, 3:15pm, 3pm,
+// afternoon) or both in either order, e.g. "yesterday afternoon" or "3:15pm yesterday", as
+// well as now and "2h ago" or "3 days ago" with minutes, hours, days or weeks. Days start at
+// midnight and a time alone is today.
+pub fn parse_date<Tz: TimeZone>(text: &str, now: &DateTime<Tz>) -> Option<NaiveDateTime> {
+    let text = text.trim().to_lowercase();
+    let words = text
+        .split_whitespace()
+        .filter(|&word| word != "at")
+        .collect::<Vec<_>>();
+    let today = now.date_naive();
+
+    let local = match words.as_slice() {
+        ["now"] => return Some(now.naive_utc()),
+        [amount, "ago"] => return now.naive_utc().checked_sub_signed(duration(amount, None)?),
+        [count, unit, "ago"] => {
+            return now
+                .naive_utc()
+                .checked_sub_signed(duration(count, Some(unit))?)
+        }
+        [date_time] if date_time.contains('t') && date_time.contains(':') => {
+            NaiveDateTime::parse_from_str(date_time, "%Y-%m-%dt%H:%M").ok()?
+        }
+        _ => {
+            let mut day = None;
+            let mut time = None;
+            let mut i = 0;
+            while i < words.len() {
+                if words[i] == "last" && day.is_none() {
+                    day = Some(last_weekday(today, weekday(words.get(i + 1)?)?));
+                    i += 2;
+                    continue;
+                }
+                match (day_of(words[i], today), time_of(words[i])) {
+                    (Some(date), _) if day.is_none() => day = Some(date),
+                    (_, Some(clock)) if time.is_none() => time = Some(clock),
+                    _ => return None,
+                }
+                i += 1;
+            }
+            if day.is_none() && time.is_none() {
+                return None;
+            }
+            day.unwrap_or(today)
+                .and_time(time.unwrap_or(NaiveTime::MIN))
+        }
+    };
+    // Times skipped by a daylight saving change don't exist, the hour after them is used
+    let zone = now.timezone();
+    zone.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            zone.from_local_datetime(&local.checked_add_signed(Duration::hours(1))?)
+                .earliest()
+        })
+        .map(|time| time.naive_utc())
+}
+
+// Reads a time given as unix millis, or as a date like `parse_date` does
+pub fn parse_time<Tz: TimeZone>(text: &str, now: &DateTime<Tz>) -> Option<NaiveDateTime> {
+    match text.trim().parse::<i64>() {
+        Ok(millis) => Utc
+            .timestamp_millis_opt(millis)
+            .single()
+            .map(|time| time.naive_utc()),
+        Err(_) => parse_date(text, now),
+    }
+}
+
+// A count and unit, together ("2h") or apart ("2", "hours"). None when it's too long for a
+// Duration, which would panic.
+fn duration(count: &str, unit: Option<&str>) -> Option<Duration> {
+    let (count, unit) = match unit {
+        Some(unit) => (count, unit),
+        None => count.split_at(count.find(|c: char| !c.is_ascii_digit())?),
+    };
+    let count = count.parse::<i64>().ok()?;
+    let minutes = match unit.strip_suffix('s').unwrap_or(unit) {
+        "m" | "min" | "minute" => 1,
+        "h" | "hr" | "hour" => 60,
+        "d" | "day" => 24 * 60,
+        "w" | "wk" | "week" => 7 * 24 * 60,
+        _ => return None,
+    };
+    Some(Duration::milliseconds(
+        count.checked_mul(minutes)?.checked_mul(60 * 1000)?,
+    ))
+}
+
+fn day_of(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match word {
+        "today" | "this" => Some(today),
+        "yesterday" => today.pred_opt(),
+        _ => match weekday(word) {
+            Some(weekday) => Some(last_weekday(today, weekday)),
+            None => NaiveDate::parse_from_str(word, "%Y-%m-%d").ok(),
+        },
+    }
+}
+
+fn time_of(word: &str) -> Option<NaiveTime> {
+    if let Some((_, hour)) = PARTS_OF_DAY.iter().find(|(part, _)| *part == word) {
+        return NaiveTime::from_hms_opt(*hour, 0, 0);
+    }
+    let (clock, afternoon) = match (word.strip_suffix("am"), word.strip_suffix("pm")) {
+        (Some(clock), _) => (clock, Some(false)),
+        (_, Some(clock)) => (clock, Some(true)),
+        _ => (word, None),
+    };
+    let (hour, minute) = clock.split_once(':').unwrap_or((clock, "0"));
+    let (mut hour, minute) = (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?);
+    if let Some(afternoon) = afternoon {
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+        hour = hour % 12 + if afternoon { 12 } else { 0 };
+    } else if !clock.contains(':') {
+        // A bare number is more likely a year or a count than an hour
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+// The most recent `weekday` before `today`, a week ago if today is one
+fn last_weekday(today: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days_back =
+        (today.weekday().num_days_from_monday() + 6 - weekday.num_days_from_monday()) % 7 + 1;
+    today - Duration::days(days_back as i64)
+}
+
+fn weekday(name: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(day, _)| *day == name || day[..3] == *name)
+        .map(|(_, weekday)| *weekday)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn test_dates_are_read_in_the_local_time_zone() {
+        // Wednesday 2026-10-14 09:30 at UTC+2
+        let zone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = zone.with_ymd_and_hms(2026, 10, 14, 9, 30, 0).unwrap();
+        let utc = |text: &str| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+
+        assert_eq!(
+            Some(utc("2026-09-30 22:00")),
+            parse_date("2026-10-01", &now)
+        );
+        assert_eq!(
+            Some(utc("2026-10-01 12:30")),
+            parse_date("2026-10-01 14:30", &now)
+        );
+        assert_eq!(
+            Some(utc("2026-10-01 12:30")),
+            parse_date("2026-10-01T14:30", &now)
+        );
+        assert_eq!(Some(utc("2026-10-13 22:00")), parse_date("Today", &now));
+        assert_eq!(Some(utc("2026-10-12 22:00")), parse_date("yesterday", &now));
+        assert_eq!(
+            Some(utc("2026-10-08 22:00")),
+            parse_date("last friday", &now)
+        );
+        assert_eq!(Some(utc("2026-10-08 22:00")), parse_date("fri", &now));
+        // The same weekday as today is a week ago
+        assert_eq!(Some(utc("2026-10-06 22:00")), parse_date("wednesday", &now));
+        assert_eq!(
+            Some(utc("2026-10-11 07:30")),
+            parse_date("3 days ago", &now)
+        );
+        assert_eq!(
+            Some(utc("2026-10-14 06:30")),
+            parse_date("1 hour ago", &now)
+        );
+        assert_eq!(Some(utc("2026-10-14 05:30")), parse_date("2h ago", &now));
+
+        assert_eq!(None, parse_date("2026-13-01", &now));
+        assert_eq!(None, parse_date("next friday", &now));
+        assert_eq!(None, parse_date("3 fortnights ago", &now));
+    }
+
+    #[test]
+    fn test_durations_too_long_are_not_dates() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 9, 30, 0).unwrap();
+        assert_eq!(None, parse_date("99999999 weeks ago", &now));
+        assert_eq!(None, parse_date("9223372036854775807m ago", &now));
+        assert_eq!(None, parse_date("999999999999 days ago", &now));
+    }
+
+    #[test]
+    fn test_times_are_millis_or_dates() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 14, 9, 30, 0).unwrap();
+        assert_eq!(Some(now.naive_utc()), parse_time("1791970200000", &now));
+        assert_eq!(
+            Some(now.naive_utc() - Duration::days(1)),
+            parse_time("1 day ago", &now)
+        );
+        assert_eq!(None, parse_time("9223372036854775807", &now));
+    }
+
+    #[test]
+    fn test_days_and_times_of_day_combine() {
+        let zone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = zone.with_ymd_and_hms(2026, 10, 14, 9, 30, 0).unwrap();
+        let utc = |text: &str| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+
+        assert_eq!(
+            Some(utc("2026-10-13 10:00")),
+            parse_date("yesterday afternoon", &now)
+        );
+        assert_eq!(
+            Some(utc("2026-10-13 13:15")),
+            parse_date("3:15pm yesterday", &now)
+        );
+        assert_eq!(
+            Some(utc("2026-10-13 13:15")),
+            parse_date("yesterday at 3:15pm", &now)
+        );
+        assert_eq!(
+            Some(utc("2026-10-09 16:00")),
+            parse_date("last friday evening", &now)
+        );
+        // A time alone is today
+        assert_eq!(Some(utc("2026-10-14 13:15")), parse_date("15:15", &now));
+        assert_eq!(Some(utc("2026-10-13 22:00")), parse_date("12am", &now));
+        assert_eq!(Some(utc("2026-10-14 10:00")), parse_date("noon", &now));
+        assert_eq!(
+            Some(utc("2026-10-14 04:00")),
+            parse_date("this morning", &now)
+        );
+
+        assert_eq!(None, parse_date("3pm 4pm", &now));
+        assert_eq!(None, parse_date("13pm", &now));
+        assert_eq!(None, parse_date("yesterday today", &now));
+        assert_eq!(None, parse_date("15", &now));
+    }
+}
@@ -1,5 +1,132 @@
-use chrono::{NaiveDateTime, Utc};
-use rusqlite::{params, params_from_iter, Connection, Result};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use super::ann::HnswIndex;
+use super::core::SCREENSHOT_INTERVAL;
+use super::dhash;
+use super::embed_cache::{self, EmbeddingCache};
+use super::passages::{passage_region, Passage};
+use super::topics::TopicCluster;
+use super::vector::{self, Quantization, QuantizedQuery};
+
+// Below this many embeddings for a model, semantic search scans them all instead of using the index
+const ANN_MIN_EMBEDDINGS: i64 = 5000;
+// Entries kept in embedding_cache, the least recently used are deleted past this
+const EMBEDDING_CACHE_MAX_ENTRIES: i64 = 20000;
+// Candidate frames rescored per result needed, when vectors are quantized. Rescoring uses the
+// float query against each vector's float32 copy, or the decoded vector for ones quantized
+// before copies were kept.
+const RESCORE_FACTOR: usize = 4;
+// A stored vector and its quantization, the float32 copy where a quantized one has it. Needs
+// frame_embeddings as `e` and FULL_VECTOR_JOIN.
+const FULL_VECTOR: &str = "IFNULL(fv.vector, e.vector),
+     CASE WHEN fv.vector IS NULL THEN e.quantization ELSE 'float32' END";
+const FULL_VECTOR_JOIN: &str = "LEFT JOIN float_vectors fv ON fv.embedding_id = e.id";
+// How long a connection waits for another one's lock, e.g. during a WAL checkpoint
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+// A gap in capture longer than this ends a session
+const SESSION_GAP: Duration = Duration::from_secs(300);
+// A frame counts as on screen until the next one for usage stats, up to this many capture
+// intervals. A longer gap is a pause, and the frame before it counts one interval.
+const MAX_FRAME_INTERVALS: u32 = 3;
+// Frames whose screenshots' hashes differ in more bits than this don't look alike
+const MAX_HASH_DISTANCE: u32 = 10;
+// Share of a related frame's score from its text rank, the rest is from its hash rank
+const RELATED_TEXT_WEIGHT: f32 = 0.7;
+// k in the reciprocal rank fusion of related frames' text and hash ranks
+const RELATED_RRF_K: f32 = 60.0;
+// Screenshots compared by hash for related frames, this long either side of the frame
+const RELATED_HASH_WINDOW: Duration = Duration::from_secs(30 * 86400);
+// Frames (as `f`) with text that model ?1, or any model if it's NULL, hasn't embedded or failed
+// to embed. It looks at every frame, so it's only used to fill embedding_queue.
+const MISSING_EMBEDDINGS_FILTER: &str = "EXISTS (SELECT 1 FROM text_spans s
+                  WHERE s.last_frame_id >= f.id AND s.first_frame_id <= f.id)
+     AND NOT EXISTS (SELECT 1 FROM frame_embeddings e
+                     WHERE e.frame_id = f.id AND e.modality = 'text'
+                     AND (?1 IS NULL OR e.model_id = ?1))
+     AND NOT EXISTS (SELECT 1 FROM embedding_errors ee
+                     WHERE ee.frame_id = f.id AND (?1 IS NULL OR ee.model_id = ?1))";
+// Each frame (as `usage`) with how long it was on screen: the time until the next frame, or one
+// capture interval (?2 seconds) if capture was paused or stopped for longer than ?5 seconds after
+// it. `local_time` is its timestamp shifted by ?1 seconds from UTC. Frames from ?3 to ?4 (None
+// for no limit) when filtered by `USAGE_RANGE`.
+const FRAME_USAGE: &str = "WITH gaps AS (
+         SELECT timestamp, active_application_name AS app, window_title,
+                unixepoch(LEAD(timestamp) OVER (ORDER BY id), 'subsec')
+                    - unixepoch(timestamp, 'subsec') AS gap
+         FROM frames WHERE ?3 IS NULL OR timestamp >= ?3
+     ),
+     usage AS (
+         SELECT timestamp, app, window_title,
+                datetime(timestamp, printf('%+d seconds', ?1)) AS local_time,
+                CASE WHEN gap <= ?5 THEN gap ELSE ?2 END AS seconds
+         FROM gaps
+     )";
+const USAGE_RANGE: &str = "?4 IS NULL OR timestamp <= ?4";
+
+// Schema changes in the order they were made. A database's `PRAGMA user_version` is how many
+// of them it has had, so new changes go at the end and old ones are never edited.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Tables and columns of databases from before schema versions",
+        apply: DatabaseManager::migrate_unversioned,
+    },
+    Migration {
+        description: "Move text search to FTS5",
+        apply: DatabaseManager::migrate_text_to_fts5,
+    },
+    Migration {
+        description: "Sessions of frames by application, window title and capture gaps",
+        apply: DatabaseManager::migrate_sessions,
+    },
+    Migration {
+        description: "Mark video chunks finished once ffmpeg has written them",
+        apply: DatabaseManager::migrate_finished_chunks,
+    },
+    Migration {
+        description: "Queue frames for embedding instead of scanning for them",
+        apply: DatabaseManager::migrate_embedding_queue,
+    },
+    Migration {
+        description: "Keep float32 copies of quantized vectors to rescore with",
+        apply: DatabaseManager::migrate_float_vectors,
+    },
+];
+
+#[derive(Clone, Copy)]
+struct Migration {
+    description: &'static str,
+    apply: fn(&DatabaseManager) -> Result<()>,
+}
+
+// OCR confidence of a text span `s`, that of its best read frame since the text was read well
+// at least once. Text without a recorded confidence predates confidence scoring, so it's kept
+// as is.
+const SPAN_CONFIDENCE: &str = "(SELECT IFNULL(MAX(o.confidence), 100) FROM ocr_results o
+                                WHERE o.frame_id BETWEEN s.first_frame_id AND s.last_frame_id)";
+
+// Counts of results stop here
+const COUNT_LIMIT: i64 = 1000;
+// Text search terms shorter than this can't use the trigram index and are matched with LIKE
+const MIN_MATCH_TERM_CHARS: usize = 3;
+// Roughly the characters around a match in a text search snippet, the index's tokens are
+// trigrams so a token is about a character
+const SNIPPET_TOKENS: i64 = 48;
+// What matches are wrapped in for `snippet` and `highlight`
+const MATCH_OPEN: &str = "<mark>";
+const MATCH_CLOSE: &str = "</mark>";
+// Private use characters marking matches for `match_offsets`, not expected in OCR text
+const OFFSET_OPEN: char = '\u{e000}';
+const OFFSET_CLOSE: char = '\u{e001}';
 
 // Structs representing the database tables
 #[derive(Debug)]
@@ -23,12 +150,245 @@ struct UniqueAppName {
     active_application_name: String,
 }
 
+// A run of consecutive frames that all contain the same text block
+#[derive(Debug)]
+struct TextSpan {
+    id: i64,
+    block_id: i64,
+    first_frame_id: i64,
+    last_frame_id: i64,
+    position: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OcrStatus {
+    Pending,
+    Processing,
+    Done,
+    Failed,
+}
+
+impl OcrStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OcrStatus::Pending => "pending",
+            OcrStatus::Processing => "processing",
+            OcrStatus::Done => "done",
+            OcrStatus::Failed => "failed",
+        }
+    }
+}
+
+// Status of a re-index job or a topic run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(value: &str) -> Option<JobStatus> {
+        [
+            JobStatus::Running,
+            JobStatus::Done,
+            JobStatus::Failed,
+            JobStatus::Cancelled,
+        ]
+        .into_iter()
+        .find(|status| status.as_str() == value)
+    }
+}
+
 #[derive(Debug)]
-struct AllText {
-    frame_id: i64,
-    text: String,
+pub struct OcrJob {
+    pub frame_id: i64,
+    pub attempts: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OcrWord {
+    pub text: String,
+    pub confidence: f32,
+    pub block_num: i32,
+    pub par_num: i32,
+    pub line_num: i32,
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+// A re-embedding of every frame with `model_id`, started when the active model changes.
+// Until it's done, searches use the vectors of `previous_model_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReindexJob {
+    pub id: i64,
+    pub model_id: String,
+    pub previous_model_id: Option<String>,
+    pub started_at: NaiveDateTime,
+}
+
+// A clustering of the frames captured between `from` and `to` into topics, status is
+// running, done or failed (with `error`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicRun {
+    pub id: i64,
+    pub model_id: String,
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+// A topic of a run, `spans` are the stretches of time its frames were captured in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topic {
+    pub id: i64,
+    pub keywords: Vec<String>,
+    pub representative_frame_id: i64,
+    pub frame_count: i64,
+    pub spans: Vec<(NaiveDateTime, NaiveDateTime)>,
+}
+
+// A run of consecutive frames of the same application and window title, ended by a switch to
+// another one or a capture gap longer than SESSION_GAP
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: i64,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub application_name: Option<String>,
+    pub window_title: Option<String>,
+    pub first_frame_id: i64,
+    pub last_frame_id: i64,
+    pub frame_count: i64,
+}
+
+// What `get_app_usage` adds up time over, days by date and weeks from Monday
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsagePeriod {
+    #[default]
+    Day,
+    Week,
+}
+
+// Time an application was on screen in the day or week starting on `period`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppUsage {
+    pub period: NaiveDate,
+    pub application_name: Option<String>,
+    pub seconds: f64,
+    pub frame_count: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowTitleUsage {
+    pub application_name: Option<String>,
+    pub window_title: String,
+    pub seconds: f64,
+    pub frame_count: i64,
+}
+
+// Time on screen in an hour of a day of the week, `weekday` 0 is Monday
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HourUsage {
+    pub weekday: u32,
+    pub hour: u32,
+    pub seconds: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReindexProgress {
+    // Frames with text
+    pub total: i64,
+    // Frames the model hasn't embedded (or failed to) yet
+    pub remaining: i64,
+}
+
+// The space a model's vectors take. `stored_bytes` is the vector data in frame_embeddings and
+// the float32 copies of quantized vectors as it is now, `estimated_bytes` what it would be
+// with every vector in each quantization. The saved index (`index_bytes`) holds float32
+// vectors, so it's the same size whatever the quantization.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingStorage {
+    pub model_id: String,
+    pub dimensions: usize,
+    pub embeddings: i64,
+    pub stored_bytes: i64,
+    pub index_bytes: u64,
+    pub estimated_bytes: Vec<(Quantization, i64)>,
+}
+
+// What to do with text whose OCR confidence is below `SearchFilters::min_confidence`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LowConfidence {
+    #[default]
+    Ignore,
+    DownRank,
+}
+
+// Text search results newest first, or best bm25 match first
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOrder {
+    #[default]
+    Recent,
+    Relevance,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SearchFilters {
+    // A LIKE pattern the app name must match, `\` escapes wildcards (see `escape_like`)
+    pub app_name: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub min_confidence: Option<f32>,
+    pub low_confidence: LowConfidence,
+    // How text search results are ordered, semantic search is always by similarity
+    pub order: SearchOrder,
+    // Frame IDs (inclusive) left out of semantic search results
+    pub exclude_frames: Option<(i64, i64)>,
+    // Words and phrases the text of results must not contain
+    pub exclude_terms: Vec<String>,
+    // Only results after this one, for paging through text search and recent frames newest
+    // first. Not counted by the `count_` methods.
+    pub older_than: Option<PageKey>,
+    // Only results first seen by this frame, so frames recorded while paging through ranked
+    // results don't shift the pages
+    pub up_to_frame: Option<i64>,
+}
+
+// Where a result is in the newest first order: its last frame's timestamp and ID, and for text
+// search its span's ID since a frame can have several
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageKey {
+    pub timestamp: NaiveDateTime,
+    pub frame_id: i64,
+    pub span_id: i64,
+}
+
+// How many results a search has, `exact` is false if there are more than `count`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResultCount {
+    pub count: i64,
+    pub exact: bool,
 }
 
+// For text search a result covers the span of frames a block was seen in: `frame_id` and
+// `timestamp` are where it was first seen, `last_frame_id` and `last_seen` where it was last seen.
+// Other results cover a single frame.
 #[derive(Debug)]
 pub struct SearchResult {
     pub frame_id: i64,
@@ -37,8 +397,82 @@ pub struct SearchResult {
     pub timestamp: NaiveDateTime,
     pub file_path: String,
     pub offset_index: i64,
+    pub last_frame_id: i64,
+    pub last_seen: NaiveDateTime,
+    // For text search: an excerpt around the matches and the whole text with the matches
+    // wrapped in <mark> tags, and the (start, end) character offsets of the matches in
+    // `full_text`
+    pub snippet: Option<String>,
+    pub highlight: Option<String>,
+    pub match_offsets: Vec<(usize, usize)>,
+    // The text span of a text search result
+    pub span_id: Option<i64>,
 }
 
+impl SearchResult {
+    pub fn page_key(&self) -> PageKey {
+        PageKey {
+            timestamp: self.last_seen,
+            frame_id: self.last_frame_id,
+            span_id: self.span_id.unwrap_or(0),
+        }
+    }
+}
+
+// `passage` is the part of the frame's text that matched, None for embeddings of a whole frame
+#[derive(Debug)]
+pub struct SemanticResult {
+    pub result: SearchResult,
+    pub similarity: f32,
+    pub passage: Option<Passage>,
+}
+
+// Reciprocal rank fusion settings for `hybrid_search`, each signal contributes
+// weight / (rrf_k + rank) for every result it ranks
+#[derive(Debug, Clone, Copy)]
+pub struct HybridWeights {
+    pub keyword: f32,
+    pub semantic: f32,
+    pub rrf_k: f32,
+    // How many results to take from each signal before fusing
+    pub candidates: i64,
+}
+
+impl Default for HybridWeights {
+    fn default() -> Self {
+        HybridWeights {
+            keyword: 1.0,
+            semantic: 1.0,
+            rrf_k: 60.0,
+            candidates: 100,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HybridResult {
+    pub result: SearchResult,
+    pub score: f32,
+    pub keyword_rank: Option<usize>,
+    pub semantic_rank: Option<usize>,
+    pub similarity: Option<f32>,
+    pub passage: Option<Passage>,
+}
+
+// A frame like another one from a different session. `similarity` is how close their text
+// embeddings are and `hash_distance` how many bits their screenshots' hashes differ in,
+// each None when it wasn't what matched or either frame lacks it.
+#[derive(Debug)]
+pub struct RelatedFrame {
+    pub result: SearchResult,
+    pub score: f32,
+    pub similarity: Option<f32>,
+    pub hash_distance: Option<u32>,
+}
+
+// A related frame's text rank and similarity, and its hash rank and distance
+type RelatedCandidate = (Option<(usize, f32)>, Option<(Option<usize>, u32)>);
+
 // DatabaseManager struct to encapsulate database operations
 pub struct DatabaseManager {
     conn: Connection,
@@ -47,12 +481,56 @@ pub struct DatabaseManager {
     current_frame_offset: i64,
     recent_frames_threshold: i64,
     fps: i32,
+    // Where vector indexes are saved, next to the database. None for in-memory databases.
+    index_dir: Option<PathBuf>,
+    // Loaded on first use for each model and shared with the read-only connections. They catch
+    // up on frame_embeddings each time they're searched, and each has its own lock so searches
+    // of a model only wait for one another while it catches up.
+    vector_indexes: Arc<Mutex<HashMap<String, SharedVectorIndex>>>,
+    ann_min_embeddings: i64,
+    // How new vectors are encoded, existing ones are converted by `requantize_embeddings`
+    quantization: Quantization,
+    // Text vectors kept in memory, shared with the read-only connections like the indexes
+    embedding_cache: Arc<EmbeddingCache>,
+    // Changes to the indexes and cache waiting for the transaction they follow to commit
+    pending_changes: RefCell<Vec<AfterCommit>>,
+}
+
+// A change to what's kept in memory that follows deleted rows. Readers share it and a rollback
+// wouldn't undo it, so it's only made once the rows are gone for good.
+enum AfterCommit {
+    Unindex(Vec<(i64, String)>),
+    ClearEmbeddingCache,
+}
+
+struct VectorIndex {
+    index: HnswIndex,
+    dirty: bool,
+    last_saved: Instant,
 }
 
+// None until the model's index has been loaded
+type SharedVectorIndex = Arc<RwLock<Option<VectorIndex>>>;
+
 impl DatabaseManager {
     // Initialize a new DatabaseManager instance
     pub fn new(database_path: &str) -> Result<DatabaseManager> {
         let conn = Connection::open(database_path)?;
+        rusqlite::vtab::array::load_module(&conn)?;
+        // Readers don't block the writer or each other in WAL mode, in-memory databases stay
+        // in "memory" mode
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        let index_dir = match database_path {
+            ":memory:" => None,
+            path => Some(
+                Path::new(path)
+                    .parent()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+            ),
+        };
         let mut db_manager = DatabaseManager {
             conn,
             current_chunk_id: 0,
@@ -60,14 +538,295 @@ impl DatabaseManager {
             current_frame_offset: 0,
             recent_frames_threshold: 15,
             fps: 25,
+            index_dir,
+            vector_indexes: Arc::new(Mutex::new(HashMap::new())),
+            ann_min_embeddings: ANN_MIN_EMBEDDINGS,
+            quantization: Quantization::default(),
+            embedding_cache: Arc::new(EmbeddingCache::default()),
+            pending_changes: RefCell::default(),
         };
-        db_manager.create_tables()?;
+        let backup_path = match database_path {
+            ":memory:" => None,
+            path => Some(PathBuf::from(path)),
+        };
+        db_manager.migrate(MIGRATIONS, backup_path.as_deref())?;
         db_manager.current_chunk_id = db_manager.get_current_chunk_id()?;
         db_manager.last_frame_id = db_manager.get_last_frame_id()?;
+        db_manager.reset_stale_ocr_jobs()?;
+        db_manager.finish_interrupted_video_chunks()?;
+        db_manager.fail_interrupted_topic_runs()?;
         Ok(db_manager)
     }
 
-    // Function to create the necessary tables
+    // Method to open a read-only connection to the same database, sharing the vector indexes.
+    // It's for queries only, anything that writes fails on it.
+    pub fn open_reader(&self) -> Result<DatabaseManager> {
+        let path = self.conn.path().unwrap_or_default();
+        let conn = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        rusqlite::vtab::array::load_module(&conn)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(DatabaseManager {
+            conn,
+            current_chunk_id: self.current_chunk_id,
+            last_frame_id: self.last_frame_id,
+            current_frame_offset: self.current_frame_offset,
+            recent_frames_threshold: self.recent_frames_threshold,
+            fps: self.fps,
+            index_dir: self.index_dir.clone(),
+            vector_indexes: self.vector_indexes.clone(),
+            ann_min_embeddings: self.ann_min_embeddings,
+            quantization: self.quantization,
+            embedding_cache: self.embedding_cache.clone(),
+            pending_changes: RefCell::default(),
+        })
+    }
+
+    // Method to run `f` in one transaction, so a batch of writes is synced to disk once. It's
+    // committed whatever `f` returns, methods whose writes must all happen or none use a
+    // savepoint, which nests inside it. If the commit fails the frame and chunk counters are
+    // put back and changes waiting for it are dropped.
+    pub fn in_transaction<F, R>(&mut self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut DatabaseManager) -> R,
+    {
+        let counters = (
+            self.current_chunk_id,
+            self.current_frame_offset,
+            self.last_frame_id,
+        );
+        self.conn.execute_batch("BEGIN IMMEDIATE")?;
+        let result = f(self);
+        let changes = self.pending_changes.take();
+        match self.conn.execute_batch("COMMIT") {
+            Ok(()) => {
+                for change in changes {
+                    self.apply(change);
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                let _ = self.conn.execute_batch("ROLLBACK");
+                (
+                    self.current_chunk_id,
+                    self.current_frame_offset,
+                    self.last_frame_id,
+                ) = counters;
+                Err(e)
+            }
+        }
+    }
+
+    // Method to make a change to the indexes or cache once the current transaction commits,
+    // or now if there isn't one
+    fn after_commit(&self, change: AfterCommit) {
+        if self.conn.is_autocommit() {
+            self.apply(change);
+        } else {
+            self.pending_changes.borrow_mut().push(change);
+        }
+    }
+
+    fn apply(&self, change: AfterCommit) {
+        match change {
+            AfterCommit::Unindex(embeddings) => self.unindex_embeddings(embeddings),
+            AfterCommit::ClearEmbeddingCache => self.embedding_cache.clear(),
+        }
+    }
+
+    // Method to run `f` in a savepoint of the current transaction and keep its writes only if
+    // it returns true, so one of several writes batched together can fail on its own. The
+    // frame and chunk counters are put back with the rows, and changes waiting for the commit
+    // are dropped.
+    pub fn in_nested_transaction<F>(&mut self, f: F) -> Result<bool>
+    where
+        F: FnOnce(&mut DatabaseManager) -> bool,
+    {
+        let counters = (
+            self.current_chunk_id,
+            self.current_frame_offset,
+            self.last_frame_id,
+        );
+        let pending = self.pending_changes.borrow().len();
+        self.conn.execute_batch("SAVEPOINT nested_transaction")?;
+        if f(self) {
+            self.conn.execute_batch("RELEASE nested_transaction")?;
+            return Ok(true);
+        }
+        (
+            self.current_chunk_id,
+            self.current_frame_offset,
+            self.last_frame_id,
+        ) = counters;
+        self.pending_changes.borrow_mut().truncate(pending);
+        self.conn
+            .execute_batch("ROLLBACK TO nested_transaction; RELEASE nested_transaction")?;
+        Ok(false)
+    }
+
+    // Method to run `f` in a savepoint, so its writes all happen or none do if it fails
+    fn in_savepoint<F, R>(&self, name: &str, f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        self.conn.execute_batch(&format!("SAVEPOINT {}", name))?;
+        match f() {
+            Ok(result) => {
+                self.conn.execute_batch(&format!("RELEASE {}", name))?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.conn
+                    .execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0}", name))?;
+                Err(e)
+            }
+        }
+    }
+
+    // Method to bring the schema up to date, one migration at a time. A database that already
+    // has tables is copied to `<path>.v<version>.bak` first, and one from a newer version of
+    // the app is refused rather than risk changing data this version doesn't understand.
+    fn migrate(&self, migrations: &[Migration], database_path: Option<&Path>) -> Result<()> {
+        let version = self.schema_version()?;
+        let latest = migrations.len() as i64;
+        if version > latest {
+            return Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+                Some(format!(
+                    "Database schema version {} is newer than this app supports ({})",
+                    version, latest
+                )),
+            ));
+        }
+        if version == latest {
+            return Ok(());
+        }
+
+        let table_count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+            [],
+            |row| row.get(0),
+        )?;
+        if let (Some(path), true) = (database_path, table_count > 0) {
+            let backup = PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+            let _ = fs::remove_file(&backup);
+            self.conn.execute(
+                "VACUUM INTO ?1",
+                params![backup.to_string_lossy().to_string()],
+            )?;
+        }
+
+        for (index, migration) in migrations.iter().enumerate().skip(version as usize) {
+            self.conn.execute_batch("BEGIN")?;
+            let result = (migration.apply)(self).and_then(|_| {
+                self.conn
+                    .execute_batch(&format!("PRAGMA user_version = {}", index + 1))
+            });
+            match result {
+                Ok(()) => self.conn.execute_batch("COMMIT")?,
+                Err(e) => {
+                    println!("Migration \"{}\" failed: {:?}", migration.description, e);
+                    self.conn.execute_batch("ROLLBACK")?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Function to get how many migrations the database has had
+    fn schema_version(&self) -> Result<i64> {
+        self.conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+    }
+
+    // Migration 1. Databases from before schema versions may have any of the tables and
+    // columns added over time, so this only adds what's missing.
+    fn migrate_unversioned(&self) -> Result<()> {
+        self.create_tables()?;
+        self.migrate_legacy_text()?;
+        self.add_column_if_missing("frame_embeddings", "span_start", "INTEGER")?;
+        self.add_column_if_missing("frame_embeddings", "span_end", "INTEGER")?;
+        self.add_column_if_missing(
+            "frame_embeddings",
+            "quantization",
+            "TEXT NOT NULL DEFAULT 'float32'",
+        )?;
+        self.add_column_if_missing(
+            "frame_embeddings",
+            "modality",
+            "TEXT NOT NULL DEFAULT 'text'",
+        )?;
+        self.add_column_if_missing("frames", "dhash", "INTEGER")
+    }
+
+    // Migration 2. FTS5 ranks matches with bm25 and has snippets and highlights, and its
+    // trigram tokenizer matches inside code identifiers and text without spaces between words
+    fn migrate_text_to_fts5(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE VIRTUAL TABLE all_text_fts5 USING fts5(text, tokenize = 'trigram');
+             INSERT INTO all_text_fts5 (rowid, text) SELECT docid, text FROM all_text;
+             DROP TABLE all_text;
+             ALTER TABLE all_text_fts5 RENAME TO all_text;",
+        )
+    }
+
+    // Migration 3. Frames get the title of the window they showed, and existing frames are
+    // grouped into sessions.
+    fn migrate_sessions(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "ALTER TABLE frames ADD COLUMN window_title TEXT;
+             CREATE TABLE sessions (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT,
+                 start_time TIMESTAMP NOT NULL,
+                 end_time TIMESTAMP NOT NULL,
+                 application_name TEXT,
+                 window_title TEXT,
+                 first_frame_id INTEGER NOT NULL,
+                 last_frame_id INTEGER NOT NULL,
+                 frame_count INTEGER NOT NULL
+             );
+             CREATE INDEX sessions_last_frame_id ON sessions (last_frame_id);
+             CREATE INDEX sessions_time ON sessions (start_time, end_time);",
+        )?;
+        self.sessionize_frames(1, i64::MAX)
+    }
+
+    // Migration 4. Chunks from before it were already written.
+    fn migrate_finished_chunks(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "ALTER TABLE video_chunks ADD COLUMN finished INTEGER NOT NULL DEFAULT 1",
+        )
+    }
+
+    // Migration 5. Frames are queued for the model a re-index is embedding with, or if there's
+    // no re-index, when no model has embedded them.
+    fn migrate_embedding_queue(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE embedding_queue (
+                 frame_id INTEGER NOT NULL,
+                 modality TEXT NOT NULL,
+                 PRIMARY KEY (frame_id, modality)
+             )",
+        )?;
+        let model_id = self.get_reindex_job()?.map(|job| job.model_id);
+        self.queue_missing_embeddings(model_id.as_deref())
+    }
+
+    // Migration 6. Quantized vectors are scanned and the best of them rescored against their
+    // float32 copy here. Vectors quantized before it have none and are rescored decoded.
+    fn migrate_float_vectors(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "CREATE TABLE float_vectors (
+                 embedding_id INTEGER PRIMARY KEY,
+                 vector BLOB NOT NULL
+             )",
+        )
+    }
+
+    // Function to create the tables as of migration 1
     fn create_tables(&self) -> Result<()> {
         // Create the video_chunks table
         self.conn.execute(
@@ -85,7 +844,8 @@ impl DatabaseManager {
             chunk_id INTEGER NOT NULL,
             offset_index INTEGER NOT NULL,
             timestamp TIMESTAMP NOT NULL,
-            active_application_name TEXT
+            active_application_name TEXT,
+            dhash INTEGER
         )",
             [],
         )?;
@@ -99,14 +859,172 @@ impl DatabaseManager {
             [],
         )?;
 
-        // Create the all_text virtual table
+        // all_text used to hold a row per frame, move it aside so it can be converted to blocks
+        if self.has_legacy_text_table()? {
+            self.conn
+                .execute("ALTER TABLE all_text RENAME TO all_text_legacy", [])?;
+        }
+
+        // Create the text_blocks table, one row per distinct block of text keyed by its hash
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS text_blocks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            hash TEXT UNIQUE NOT NULL
+        )",
+            [],
+        )?;
+
+        // Create the all_text virtual table, indexing each text block with docid = text_blocks.id
         self.conn.execute(
             "CREATE VIRTUAL TABLE IF NOT EXISTS all_text USING fts4(
-            frame_id INTEGER NOT NULL,
             text TEXT NOTNULL
         )",
             [],
         )?;
+
+        // Create the text_spans table, linking a block to the consecutive frames it appears in
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS text_spans (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            block_id INTEGER NOT NULL,
+            first_frame_id INTEGER NOT NULL,
+            last_frame_id INTEGER NOT NULL,
+            position INTEGER NOT NULL
+        )",
+            [],
+        )?;
+
+        // Create the ocr_results table, holding the mean word confidence for each frame
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS ocr_results (
+            frame_id INTEGER PRIMARY KEY,
+            confidence REAL NOT NULL,
+            word_count INTEGER NOT NULL
+        )",
+            [],
+        )?;
+
+        // Create the ocr_words table, the words kept for each frame with their confidence and position
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS ocr_words (
+            frame_id INTEGER NOT NULL,
+            word_index INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            confidence REAL NOT NULL,
+            block_num INTEGER NOT NULL,
+            par_num INTEGER NOT NULL,
+            line_num INTEGER NOT NULL,
+            left INTEGER NOT NULL,
+            top INTEGER NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            PRIMARY KEY (frame_id, word_index)
+        )",
+            [],
+        )?;
+
+        // Create the frame_embeddings table, text embeddings of each passage of a frame for each
+        // model. span_start and span_end are character offsets into the frame's text, NULL
+        // when the whole text was embedded at once. quantization is how vector is encoded.
+        // modality is text, or image for vectors of the frame's screenshot.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS frame_embeddings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            frame_id INTEGER NOT NULL,
+            model_id TEXT NOT NULL,
+            dimensions INTEGER NOT NULL,
+            vector BLOB NOT NULL,
+            created_at TIMESTAMP NOT NULL,
+            span_start INTEGER,
+            span_end INTEGER,
+            quantization TEXT NOT NULL DEFAULT 'float32',
+            modality TEXT NOT NULL DEFAULT 'text'
+        )",
+            [],
+        )?;
+
+        // Create the embedding_errors table, frames whose text a model couldn't embed
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS embedding_errors (
+            frame_id INTEGER NOT NULL,
+            model_id TEXT NOT NULL,
+            error TEXT NOT NULL,
+            PRIMARY KEY (frame_id, model_id)
+        )",
+            [],
+        )?;
+
+        // Create the embedding_cache table, vectors of passage texts by a hash of the text so
+        // text seen before isn't embedded again
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS embedding_cache (
+            model_id TEXT NOT NULL,
+            text_hash TEXT NOT NULL,
+            dimensions INTEGER NOT NULL,
+            vector BLOB NOT NULL,
+            quantization TEXT NOT NULL,
+            last_used TIMESTAMP NOT NULL,
+            PRIMARY KEY (model_id, text_hash)
+        )",
+            [],
+        )?;
+
+        // Create the topic tables, each run clusters a time range into topics and
+        // topic_frames assigns the range's frames to them. keywords are space separated.
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS topic_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            model_id TEXT NOT NULL,
+            range_start TIMESTAMP NOT NULL,
+            range_end TIMESTAMP NOT NULL,
+            status TEXT NOT NULL,
+            error TEXT,
+            created_at TIMESTAMP NOT NULL
+        )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS topics (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            run_id INTEGER NOT NULL,
+            keywords TEXT NOT NULL,
+            representative_frame_id INTEGER NOT NULL
+        )",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS topic_frames (
+            topic_id INTEGER NOT NULL,
+            frame_id INTEGER NOT NULL,
+            PRIMARY KEY (topic_id, frame_id)
+        )",
+            [],
+        )?;
+
+        // Create the reindex_jobs table, status is running, done or cancelled
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS reindex_jobs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            model_id TEXT NOT NULL,
+            previous_model_id TEXT,
+            status TEXT NOT NULL,
+            started_at TIMESTAMP NOT NULL,
+            finished_at TIMESTAMP
+        )",
+            [],
+        )?;
+
+        // Create the ocr_queue table, one row per frame tracking its OCR status
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS ocr_queue (
+            frame_id INTEGER PRIMARY KEY,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            updated_at TIMESTAMP NOT NULL
+        )",
+            [],
+        )?;
         // Create indices and seed data as necessary
         self.create_indices()?;
 
@@ -120,13 +1038,53 @@ impl DatabaseManager {
         self.conn
             .execute("DROP TABLE IF EXISTS unique_app_names", [])?;
         self.conn.execute("DROP TABLE IF EXISTS all_text", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS text_blocks", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS text_spans", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS ocr_queue", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS ocr_results", [])?;
+        self.conn
+            .execute("DROP TABLE IF EXISTS frame_embeddings", [])?;
+        self.conn
+            .execute("DROP TABLE IF EXISTS embedding_errors", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS reindex_jobs", [])?;
+        self.conn
+            .execute("DROP TABLE IF EXISTS embedding_cache", [])?;
+        self.embedding_cache.clear();
+        self.conn.execute("DROP TABLE IF EXISTS ocr_words", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS topic_runs", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS topics", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS topic_frames", [])?;
+        self.conn.execute("DROP TABLE IF EXISTS sessions", [])?;
+        self.conn
+            .execute("DROP TABLE IF EXISTS embedding_queue", [])?;
+        self.conn
+            .execute("DROP TABLE IF EXISTS float_vectors", [])?;
+        self.remove_vector_indexes();
 
-        self.create_tables()?;
+        self.conn.execute_batch("PRAGMA user_version = 0")?;
+        self.migrate(MIGRATIONS, None)?;
         self.current_chunk_id = self.get_current_chunk_id()?;
         self.last_frame_id = self.get_last_frame_id()?;
         Ok(())
     }
 
+    // Function to add a column to tables created before it existed, for unversioned databases
+    fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+        let columns = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        if !columns.iter().any(|c| c == column) {
+            self.conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
     // Function to create indices for optimization
     fn create_indices(&self) -> Result<()> {
         self.conn.execute(
@@ -137,13 +1095,74 @@ impl DatabaseManager {
             "CREATE INDEX IF NOT EXISTS idx_timestamp ON frames (timestamp)",
             [],
         )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_ocr_queue_status ON ocr_queue (status, frame_id)",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_frame_embeddings_frame ON frame_embeddings (frame_id, model_id)",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_text_spans_block_last ON text_spans (block_id, last_frame_id)",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_text_spans_block_first ON text_spans (block_id, first_frame_id)",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_text_spans_frames ON text_spans (last_frame_id, first_frame_id)",
+            [],
+        )?;
         Ok(())
     }
 
-    // Function to get the current chunk ID
-    fn get_current_chunk_id(&self) -> Result<i64> {
-        self.conn.query_row(
-            "SELECT IFNULL(MAX(id), 0) + 1 FROM video_chunks",
+    fn has_legacy_text_table(&self) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('all_text') WHERE name = 'frame_id'",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    // Convert the per-frame text of older databases into blocks and spans
+    fn migrate_legacy_text(&self) -> Result<()> {
+        let exists: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'all_text_legacy'",
+            [],
+            |row| row.get(0),
+        )?;
+        if exists == 0 {
+            return Ok(());
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT frame_id, text FROM all_text_legacy ORDER BY frame_id")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        // The embedding queue is filled from the spans by a later migration
+        for (frame_id, text) in rows {
+            self.insert_text_spans(frame_id, &text)?;
+        }
+        self.conn.execute("DROP TABLE all_text_legacy", [])?;
+        Ok(())
+    }
+
+    // Function to get the current chunk ID
+    // Frames are assigned to a chunk before it is written, so frames whose chunk was never
+    // written (e.g. the app exited mid-buffer) must not have their chunk ID reused.
+    fn get_current_chunk_id(&self) -> Result<i64> {
+        self.conn.query_row(
+            "SELECT MAX(
+                (SELECT IFNULL(MAX(id), 0) FROM video_chunks),
+                (SELECT IFNULL(MAX(chunk_id), 0) FROM frames)
+            ) + 1",
             [],
             |row| row.get(0),
         )
@@ -158,31 +1177,83 @@ impl DatabaseManager {
     }
 
     // Method to start a new video chunk and return its ID
+    // The chunk takes the ID the buffered frames were assigned, and later frames go to the next one.
+    // Its frames can't be decoded until `finish_video_chunk` is called.
     pub fn start_new_video_chunk(&mut self, file_path: &str) -> Result<i64> {
-        let chunk_id = self.conn.execute(
-            "INSERT INTO video_chunks (file_path) VALUES (?1)",
-            params![file_path],
+        let chunk_id = self.current_chunk_id;
+        self.conn.execute(
+            "INSERT INTO video_chunks (id, file_path, finished) VALUES (?1, ?2, 0)",
+            params![chunk_id, file_path],
         )?;
-        self.current_chunk_id = chunk_id as i64;
+        self.current_chunk_id = chunk_id + 1;
         self.current_frame_offset = 0;
-        Ok(self.current_chunk_id)
+        Ok(chunk_id)
     }
 
-    // Method to insert a frame and return its ID
-    pub fn insert_frame(&mut self, active_application_name: Option<String>) -> Result<i64> {
-        let frame_id = self.conn.execute(
-            "INSERT INTO frames (chunk_id, offset_index, timestamp, active_application_name)
-             VALUES (?1, ?2, ?3, ?4)",
+    // Method to mark a video chunk as completely written, once ffmpeg has exited
+    pub fn finish_video_chunk(&self, chunk_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE video_chunks SET finished = 1 WHERE id = ?1",
+            params![chunk_id],
+        )?;
+        Ok(())
+    }
+
+    // Chunks ffmpeg was still writing when a previous run stopped are as complete as they'll
+    // get, so their frames are tried and fail if they can't be decoded
+    fn finish_interrupted_video_chunks(&self) -> Result<()> {
+        self.conn.execute(
+            "UPDATE video_chunks SET finished = 1 WHERE finished = 0",
+            [],
+        )?;
+        Ok(())
+    }
+
+    // Method to insert a frame and return its ID, queueing it for OCR
+    pub fn insert_frame(
+        &mut self,
+        active_application_name: Option<String>,
+        window_title: Option<String>,
+    ) -> Result<i64> {
+        self.insert_frame_with_timestamp(
+            active_application_name,
+            window_title,
+            Utc::now().naive_utc(),
+        )
+    }
+
+    fn insert_frame_with_timestamp(
+        &mut self,
+        active_application_name: Option<String>,
+        window_title: Option<String>,
+        timestamp: NaiveDateTime,
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO frames (chunk_id, offset_index, timestamp, active_application_name,
+                                 window_title)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
             params![
                 self.current_chunk_id,
                 self.current_frame_offset,
-                Utc::now().naive_utc(),
+                timestamp,
                 active_application_name,
+                window_title,
             ],
         )?;
 
         self.current_frame_offset += 1;
-        self.last_frame_id = frame_id as i64;
+        self.last_frame_id = self.conn.last_insert_rowid();
+        self.enqueue_ocr(self.last_frame_id)?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO embedding_queue (frame_id, modality) VALUES (?1, 'image')",
+            params![self.last_frame_id],
+        )?;
+        self.add_frame_to_session(
+            self.last_frame_id,
+            timestamp,
+            active_application_name.as_deref(),
+            window_title.as_deref(),
+        )?;
 
         // If the active application name exists, ensure it is in the unique_app_names table
         if let Some(app_name) = active_application_name {
@@ -192,6 +1263,260 @@ impl DatabaseManager {
         Ok(self.last_frame_id)
     }
 
+    // Method to add a frame to the session before it if it continues it, or else start a new
+    // session with it. Frames are added in ID order.
+    fn add_frame_to_session(
+        &self,
+        frame_id: i64,
+        timestamp: NaiveDateTime,
+        application_name: Option<&str>,
+        window_title: Option<&str>,
+    ) -> Result<()> {
+        let previous = self
+            .conn
+            .query_row(
+                "SELECT id, end_time, application_name, window_title FROM sessions
+                 WHERE last_frame_id < ?1 ORDER BY last_frame_id DESC LIMIT 1",
+                params![frame_id],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, NaiveDateTime>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+        match previous {
+            Some((id, end, previous_app, previous_title))
+                if previous_app.as_deref() == application_name
+                    && previous_title.as_deref() == window_title
+                    && (timestamp - end).num_seconds() <= SESSION_GAP.as_secs() as i64 =>
+            {
+                self.conn.execute(
+                    "UPDATE sessions SET end_time = ?1, last_frame_id = ?2,
+                     frame_count = frame_count + 1 WHERE id = ?3",
+                    params![timestamp, frame_id, id],
+                )?;
+            }
+            _ => {
+                self.conn.execute(
+                    "INSERT INTO sessions (start_time, end_time, application_name, window_title,
+                                           first_frame_id, last_frame_id, frame_count)
+                     VALUES (?1, ?1, ?2, ?3, ?4, ?4, 1)",
+                    params![timestamp, application_name, window_title, frame_id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Method to group the frames with IDs from `first_frame_id` to `last_frame_id` into
+    // sessions, continuing the session before them. There must be no sessions of those frames
+    // yet.
+    fn sessionize_frames(&self, first_frame_id: i64, last_frame_id: i64) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, timestamp, active_application_name, window_title FROM frames
+             WHERE id BETWEEN ?1 AND ?2 ORDER BY id",
+        )?;
+        let frames = stmt
+            .query_map(params![first_frame_id, last_frame_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, NaiveDateTime>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        for (frame_id, timestamp, application_name, window_title) in frames {
+            self.add_frame_to_session(
+                frame_id,
+                timestamp,
+                application_name.as_deref(),
+                window_title.as_deref(),
+            )?;
+        }
+        Ok(())
+    }
+
+    // Method to regroup the frames around a deleted range of frame IDs into sessions. The
+    // sessions over the range and the one after it are rebuilt, since whether that one
+    // continued from the frame before it may have changed.
+    fn rebuild_sessions(&self, first_frame_id: i64, last_frame_id: i64) -> Result<()> {
+        let (from, to): (Option<i64>, Option<i64>) = self.conn.query_row(
+            "SELECT MIN(first_frame_id), MAX(last_frame_id) FROM sessions
+             WHERE (last_frame_id >= ?1 AND first_frame_id <= ?2)
+                OR id = (SELECT id FROM sessions WHERE first_frame_id > ?2
+                         ORDER BY first_frame_id LIMIT 1)",
+            params![first_frame_id, last_frame_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Ok(()),
+        };
+        self.conn.execute(
+            "DELETE FROM sessions WHERE last_frame_id >= ?1 AND first_frame_id <= ?2",
+            params![from, to],
+        )?;
+        self.sessionize_frames(from, to)
+    }
+
+    // Method to get the sessions overlapping a time range, oldest first
+    pub fn get_sessions(
+        &self,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<Session>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, start_time, end_time, application_name, window_title, first_frame_id,
+                    last_frame_id, frame_count
+             FROM sessions
+             WHERE (?1 IS NULL OR end_time >= ?1) AND (?2 IS NULL OR start_time <= ?2)
+             ORDER BY start_time, id",
+        )?;
+        let sessions = stmt
+            .query_map(params![from, to], |row| {
+                Ok(Session {
+                    id: row.get(0)?,
+                    start: row.get(1)?,
+                    end: row.get(2)?,
+                    application_name: row.get(3)?,
+                    window_title: row.get(4)?,
+                    first_frame_id: row.get(5)?,
+                    last_frame_id: row.get(6)?,
+                    frame_count: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(sessions)
+    }
+
+    // Method to get how long each application was on screen per day or week, in local time
+    // `utc_offset` seconds ahead of UTC. Periods oldest first, the most used apps first in each.
+    pub fn get_app_usage(
+        &self,
+        period: UsagePeriod,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+        utc_offset: i32,
+    ) -> Result<Vec<AppUsage>> {
+        let period = match period {
+            UsagePeriod::Day => "date(local_time)",
+            UsagePeriod::Week => "date(local_time, 'weekday 0', '-6 days')",
+        };
+        let mut stmt = self.conn.prepare(&format!(
+            "{} SELECT {} AS period, app, SUM(seconds), COUNT(*) FROM usage
+             WHERE {}
+             GROUP BY period, app ORDER BY period, SUM(seconds) DESC, app",
+            FRAME_USAGE, period, USAGE_RANGE
+        ))?;
+        let usage = stmt
+            .query_map(
+                params![
+                    utc_offset,
+                    SCREENSHOT_INTERVAL.as_secs_f64(),
+                    from,
+                    to,
+                    (SCREENSHOT_INTERVAL * MAX_FRAME_INTERVALS).as_secs_f64()
+                ],
+                |row| {
+                    Ok(AppUsage {
+                        period: row.get(0)?,
+                        application_name: row.get(1)?,
+                        seconds: row.get(2)?,
+                        frame_count: row.get(3)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(usage)
+    }
+
+    // Method to get the window titles that were on screen longest, with their applications
+    pub fn get_top_window_titles(
+        &self,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<WindowTitleUsage>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} SELECT app, window_title, SUM(seconds), COUNT(*) FROM usage
+             WHERE window_title IS NOT NULL AND ({})
+             GROUP BY app, window_title ORDER BY SUM(seconds) DESC, COUNT(*) DESC LIMIT ?6",
+            FRAME_USAGE, USAGE_RANGE
+        ))?;
+        let usage = stmt
+            .query_map(
+                params![
+                    0,
+                    SCREENSHOT_INTERVAL.as_secs_f64(),
+                    from,
+                    to,
+                    (SCREENSHOT_INTERVAL * MAX_FRAME_INTERVALS).as_secs_f64(),
+                    limit
+                ],
+                |row| {
+                    Ok(WindowTitleUsage {
+                        application_name: row.get(0)?,
+                        window_title: row.get(1)?,
+                        seconds: row.get(2)?,
+                        frame_count: row.get(3)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(usage)
+    }
+
+    // Method to get time on screen by day of the week and hour, in local time `utc_offset`
+    // seconds ahead of UTC, for a heatmap. Hours without any are left out.
+    pub fn get_hourly_usage(
+        &self,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+        utc_offset: i32,
+    ) -> Result<Vec<HourUsage>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} SELECT (CAST(strftime('%w', local_time) AS INTEGER) + 6) % 7 AS weekday,
+                       CAST(strftime('%H', local_time) AS INTEGER) AS hour, SUM(seconds)
+             FROM usage
+             WHERE {}
+             GROUP BY weekday, hour ORDER BY weekday, hour",
+            FRAME_USAGE, USAGE_RANGE
+        ))?;
+        let usage = stmt
+            .query_map(
+                params![
+                    utc_offset,
+                    SCREENSHOT_INTERVAL.as_secs_f64(),
+                    from,
+                    to,
+                    (SCREENSHOT_INTERVAL * MAX_FRAME_INTERVALS).as_secs_f64()
+                ],
+                |row| {
+                    Ok(HourUsage {
+                        weekday: row.get(0)?,
+                        hour: row.get(1)?,
+                        seconds: row.get(2)?,
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(usage)
+    }
+
+    // Method to store the perceptual hash of a frame's screenshot
+    pub fn set_frame_dhash(&self, frame_id: i64, hash: u64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE frames SET dhash = ?1 WHERE id = ?2",
+            params![hash as i64, frame_id],
+        )?;
+        Ok(())
+    }
+
     // Method to insert unique application names if needed
     fn insert_unique_application_names_if_needed(&self, app_name: &str) -> Result<()> {
         let count: i64 = self.conn.query_row(
@@ -215,226 +1540,3757 @@ impl DatabaseManager {
         Ok(())
     }
 
-    // Method to insert text for a frame
+    // Method to insert text for a frame and queue it to be embedded
     pub fn insert_text_for_frame(&self, frame_id: i64, text: &str) -> Result<()> {
+        self.insert_text_spans(frame_id, text)?;
+        if split_text_blocks(text).is_empty() {
+            return Ok(());
+        }
+        // New text is embedded again, even if it couldn't be before
+        self.conn.execute(
+            "DELETE FROM embedding_errors WHERE frame_id = ?1",
+            params![frame_id],
+        )?;
         self.conn.execute(
-            "INSERT INTO all_text (frame_id, text) VALUES (?1, ?2)",
-            params![frame_id, text],
+            "INSERT OR IGNORE INTO embedding_queue (frame_id, modality) VALUES (?1, 'text')",
+            params![frame_id],
         )?;
         Ok(())
     }
 
-    // Method to get a frame by index
-    pub fn get_frame(&self, index: i64) -> Result<Option<(i64, String)>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT f.offset_index, vc.file_path FROM frames f
-         JOIN video_chunks vc ON f.chunk_id = vc.id
-         WHERE f.id = ?1",
+    // Function to store the text of a frame
+    // The text is split into blocks (paragraphs), each stored once, and the frame is added to
+    // the span of the neighbouring frames that contain the same block
+    fn insert_text_spans(&self, frame_id: i64, text: &str) -> Result<()> {
+        let previous_frame_id: Option<i64> = self.conn.query_row(
+            "SELECT MAX(id) FROM frames WHERE id < ?1",
+            params![frame_id],
+            |row| row.get(0),
         )?;
-        let mut rows = stmt.query(params![index])?;
-
-        if let Some(row) = rows.next()? {
-            let offset_index: i64 = row.get(0)?;
-            let file_path: String = row.get(1)?;
-            Ok(Some((offset_index, file_path)))
-        } else {
-            Ok(None)
-        }
-    }
-
-    // Method to check if a frame exists for a given index
-    pub fn frame_exists(&self, index: i64) -> Result<bool> {
-        let count: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM frames WHERE id = ?1",
-            params![index],
+        let next_frame_id: Option<i64> = self.conn.query_row(
+            "SELECT MIN(id) FROM frames WHERE id > ?1",
+            params![frame_id],
             |row| row.get(0),
         )?;
-        Ok(count > 0)
+
+        let mut seen = HashSet::new();
+        for (position, block) in split_text_blocks(text).into_iter().enumerate() {
+            if !seen.insert(block) {
+                continue;
+            }
+            let block_id = self.insert_text_block(block)?;
+
+            let before = match previous_frame_id {
+                Some(id) => self.find_text_span(block_id, "last_frame_id", id)?,
+                None => None,
+            };
+            let after = match next_frame_id {
+                Some(id) => self.find_text_span(block_id, "first_frame_id", id)?,
+                None => None,
+            };
+
+            match (before, after) {
+                (Some(before), Some(after)) => {
+                    // This frame was the gap between two spans, join them
+                    self.conn.execute(
+                        "UPDATE text_spans SET last_frame_id = ?1 WHERE id = ?2",
+                        params![after.last_frame_id, before.id],
+                    )?;
+                    self.conn
+                        .execute("DELETE FROM text_spans WHERE id = ?1", params![after.id])?;
+                }
+                (Some(before), None) => {
+                    self.conn.execute(
+                        "UPDATE text_spans SET last_frame_id = ?1 WHERE id = ?2",
+                        params![frame_id, before.id],
+                    )?;
+                }
+                (None, Some(after)) => {
+                    self.conn.execute(
+                        "UPDATE text_spans SET first_frame_id = ?1 WHERE id = ?2",
+                        params![frame_id, after.id],
+                    )?;
+                }
+                (None, None) => {
+                    self.conn.execute(
+                        "INSERT INTO text_spans (block_id, first_frame_id, last_frame_id, position)
+                         VALUES (?1, ?2, ?2, ?3)",
+                        params![block_id, frame_id, position as i64],
+                    )?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    // Method to retrieve the file path of a video chunk by its index
-    pub fn get_video_chunk_path(&self, index: i64) -> Result<Option<String>> {
-        let mut stmt = self
+    // Method to insert a text block if it's new, returns its ID either way
+    fn insert_text_block(&self, text: &str) -> Result<i64> {
+        let hash = format!("{:x}", Sha256::digest(text.as_bytes()));
+        let existing: Option<i64> = self
             .conn
-            .prepare("SELECT file_path FROM video_chunks WHERE id = ?1")?;
-        let mut rows = stmt.query(params![index])?;
-
-        if let Some(row) = rows.next()? {
-            let file_path: String = row.get(0)?;
-            Ok(Some(file_path))
-        } else {
-            Ok(None)
+            .query_row(
+                "SELECT id FROM text_blocks WHERE hash = ?1",
+                params![hash],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(block_id) = existing {
+            return Ok(block_id);
         }
+
+        self.conn
+            .execute("INSERT INTO text_blocks (hash) VALUES (?1)", params![hash])?;
+        let block_id = self.conn.last_insert_rowid();
+        self.conn.execute(
+            "INSERT INTO all_text (rowid, text) VALUES (?1, ?2)",
+            params![block_id, text],
+        )?;
+        Ok(block_id)
     }
 
-    // Method to perform a search based on app name and/or text
-    pub fn search(
+    fn find_text_span(
         &self,
-        search_text: &str,
-        limit: i64,
-        offset: i64,
-        app_name: Option<&str>,
-    ) -> Result<Vec<SearchResult>> {
-        let mut query = String::from(
-            "SELECT a.frame_id, a.text, f.active_application_name, f.timestamp, vc.file_path, f.offset_index
-             FROM all_text a
-             JOIN frames f ON f.id = a.frame_id
-             JOIN video_chunks vc ON f.chunk_id = vc.id ",
-        );
+        block_id: i64,
+        column: &str,
+        frame_id: i64,
+    ) -> Result<Option<TextSpan>> {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT id, block_id, first_frame_id, last_frame_id, position FROM text_spans
+                     WHERE block_id = ?1 AND {} = ?2",
+                    column
+                ),
+                params![block_id, frame_id],
+                |row| {
+                    Ok(TextSpan {
+                        id: row.get(0)?,
+                        block_id: row.get(1)?,
+                        first_frame_id: row.get(2)?,
+                        last_frame_id: row.get(3)?,
+                        position: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+    }
 
-        let mut params: Vec<rusqlite::types::Value> = Vec::new();
-        let mut params_count = 1;
+    // Method to remove the text of frames in an ID range, spans reaching outside it are trimmed
+    fn remove_text_for_frames(&self, from_frame_id: i64, to_frame_id: i64) -> Result<()> {
+        let before: Option<i64> = self.conn.query_row(
+            "SELECT MAX(id) FROM frames WHERE id < ?1",
+            params![from_frame_id],
+            |row| row.get(0),
+        )?;
+        let after: Option<i64> = self.conn.query_row(
+            "SELECT MIN(id) FROM frames WHERE id > ?1",
+            params![to_frame_id],
+            |row| row.get(0),
+        )?;
 
-        if let Some(app_name) = app_name {
-            query.push_str("JOIN unique_app_names uan ON uan.active_application_name = f.active_application_name ");
-            query.push_str(
-                format!("WHERE uan.active_application_name LIKE ?{} ", params_count).as_str(),
-            );
-            params.push(String::from(app_name).into());
-            // Update params count
-            params_count = params.len() + 1;
-        }
+        let mut stmt = self.conn.prepare(
+            "SELECT id, block_id, first_frame_id, last_frame_id, position FROM text_spans
+             WHERE first_frame_id <= ?2 AND last_frame_id >= ?1",
+        )?;
+        let spans = stmt
+            .query_map(params![from_frame_id, to_frame_id], |row| {
+                Ok(TextSpan {
+                    id: row.get(0)?,
+                    block_id: row.get(1)?,
+                    first_frame_id: row.get(2)?,
+                    last_frame_id: row.get(3)?,
+                    position: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
 
-        if !search_text.is_empty() {
-            if !params.is_empty() {
-                query.push_str("AND ");
-            } else {
-                query.push_str("WHERE ");
+        for span in spans {
+            self.conn
+                .execute("DELETE FROM text_spans WHERE id = ?1", params![span.id])?;
+            if let (true, Some(before)) = (span.first_frame_id < from_frame_id, before) {
+                self.conn.execute(
+                    "INSERT INTO text_spans (block_id, first_frame_id, last_frame_id, position)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![span.block_id, span.first_frame_id, before, span.position],
+                )?;
+            }
+            if let (true, Some(after)) = (span.last_frame_id > to_frame_id, after) {
+                self.conn.execute(
+                    "INSERT INTO text_spans (block_id, first_frame_id, last_frame_id, position)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![span.block_id, after, span.last_frame_id, span.position],
+                )?;
             }
-            query.push_str(format!("a.text MATCH ?{} ", params_count).as_str());
-            params.push(String::from(search_text).into());
-            // Update params count
-            params_count = params.len() + 1;
         }
+        Ok(())
+    }
 
-        query.push_str(
-            format!(
-                "ORDER BY f.timestamp DESC LIMIT ?{} OFFSET ?{}",
-                params_count,
-                params_count + 1
-            )
-            .as_str(),
-        );
-        params.push(String::from(limit.to_string()).into());
-        params.push(String::from(offset.to_string()).into());
-        // Update params count
-        params_count = params.len() + 1;
+    // Method to get the text of a frame, rebuilt from the blocks it contains
+    pub fn get_text_for_frame(&self, frame_id: i64) -> Result<Option<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT a.text FROM text_spans s
+             JOIN all_text a ON a.rowid = s.block_id
+             WHERE s.first_frame_id <= ?1 AND s.last_frame_id >= ?1
+             ORDER BY s.position",
+        )?;
+        let blocks = stmt
+            .query_map(params![frame_id], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        if blocks.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(blocks.join("\n\n")))
+    }
 
-        let mut stmt = self.conn.prepare(&query)?;
+    // Method to store the confidence and words of a frame's OCR, replacing any previous result
+    pub fn insert_ocr_result(
+        &self,
+        frame_id: i64,
+        confidence: f32,
+        words: &[OcrWord],
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO ocr_results (frame_id, confidence, word_count) VALUES (?1, ?2, ?3)",
+            params![frame_id, confidence, words.len() as i64],
+        )?;
+        self.conn.execute(
+            "DELETE FROM ocr_words WHERE frame_id = ?1",
+            params![frame_id],
+        )?;
 
-        let search_results = stmt
-            .query_map(params_from_iter(params), |row| {
-                Ok(SearchResult {
-                    frame_id: row.get(0)?,
-                    full_text: row.get(1)?,
-                    application_name: row.get(2)?,
-                    timestamp: row.get(3)?,
-                    file_path: row.get(4)?,
-                    offset_index: row.get(5)?,
+        let mut stmt = self.conn.prepare(
+            "INSERT INTO ocr_words (frame_id, word_index, text, confidence, block_num, par_num, line_num, left, top, width, height)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for (index, word) in words.iter().enumerate() {
+            stmt.execute(params![
+                frame_id,
+                index as i64,
+                word.text,
+                word.confidence,
+                word.block_num,
+                word.par_num,
+                word.line_num,
+                word.left,
+                word.top,
+                word.width,
+                word.height,
+            ])?;
+        }
+        Ok(())
+    }
+
+    // Method to get the words recognized for a frame, in reading order
+    pub fn get_ocr_words(&self, frame_id: i64) -> Result<Vec<OcrWord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT text, confidence, block_num, par_num, line_num, left, top, width, height
+             FROM ocr_words WHERE frame_id = ?1 ORDER BY word_index",
+        )?;
+        let words = stmt
+            .query_map(params![frame_id], |row| {
+                Ok(OcrWord {
+                    text: row.get(0)?,
+                    confidence: row.get(1)?,
+                    block_num: row.get(2)?,
+                    par_num: row.get(3)?,
+                    line_num: row.get(4)?,
+                    left: row.get(5)?,
+                    top: row.get(6)?,
+                    width: row.get(7)?,
+                    height: row.get(8)?,
                 })
             })?
             .collect::<Result<Vec<_>, rusqlite::Error>>()?;
-
-        Ok(search_results)
+        Ok(words)
     }
 
-    // Method to get recent results with an optional filter for application name
-    pub fn get_recent_results(
-        &self,
-        limit: i64,
-        offset: i64,
-        selected_filter_app: Option<&str>,
-    ) -> Result<Vec<SearchResult>> {
-        let mut query = String::from(
-            "SELECT f.id, NULL, f.active_application_name, f.timestamp, vc.file_path, f.offset_index
-             FROM frames f
-             JOIN video_chunks vc ON f.chunk_id = vc.id ",
-        );
-
-        let mut params: Vec<rusqlite::types::Value> = Vec::new();
-        let mut params_count = 1;
+    // Method to add a frame to the OCR queue, resetting it if it was already processed
+    pub fn enqueue_ocr(&self, frame_id: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO ocr_queue (frame_id, status, attempts, last_error, updated_at)
+             VALUES (?1, ?2, 0, NULL, ?3)",
+            params![
+                frame_id,
+                OcrStatus::Pending.as_str(),
+                Utc::now().naive_utc()
+            ],
+        )?;
+        Ok(())
+    }
 
-        if let Some(app_name) = selected_filter_app {
-            query.push_str("JOIN unique_app_names uan ON uan.active_application_name = f.active_application_name ");
-            query.push_str(
-                format!("WHERE uan.active_application_name LIKE ?{} ", params_count).as_str(),
-            );
-            params.push(String::from(app_name).into());
-            // Added a param
-            params_count = params.len() + 1;
+    // Method to claim a specific frame for OCR, returns None if it is not pending
+    pub fn claim_ocr_job(&self, frame_id: i64) -> Result<Option<OcrJob>> {
+        let updated = self.conn.execute(
+            "UPDATE ocr_queue SET status = ?1, updated_at = ?2 WHERE frame_id = ?3 AND status = ?4",
+            params![
+                OcrStatus::Processing.as_str(),
+                Utc::now().naive_utc(),
+                frame_id,
+                OcrStatus::Pending.as_str(),
+            ],
+        )?;
+        if updated == 0 {
+            return Ok(None);
         }
-
-        query.push_str(
-            format!(
-                "ORDER BY f.timestamp DESC LIMIT ?{} OFFSET ?{}",
-                params_count,
-                params_count + 1
+        self.conn
+            .query_row(
+                "SELECT frame_id, attempts FROM ocr_queue WHERE frame_id = ?1",
+                params![frame_id],
+                |row| {
+                    Ok(OcrJob {
+                        frame_id: row.get(0)?,
+                        attempts: row.get(1)?,
+                    })
+                },
             )
-            .as_str(),
-        );
-        params.push(String::from(limit.to_string()).into());
-        params.push(String::from(offset.to_string()).into());
-        // Update params count
-        params_count = params.len() + 1;
+            .optional()
+    }
 
-        let mut stmt = self.conn.prepare(&query)?;
-        let final_params = params_from_iter(params);
-        let results = stmt
-            .query_map(final_params, |row| {
-                Ok(SearchResult {
+    // Method to claim pending OCR jobs that haven't been touched within `retry_delay`
+    // Newest frames first, so a backlog doesn't starve the frames being captured now
+    pub fn claim_ocr_jobs(&self, limit: i64, retry_delay: Duration) -> Result<Vec<OcrJob>> {
+        let cutoff = Utc::now().naive_utc()
+            - chrono::Duration::from_std(retry_delay).unwrap_or(chrono::Duration::zero());
+        let mut stmt = self.conn.prepare(
+            "SELECT frame_id, attempts FROM ocr_queue
+             WHERE status = ?1 AND updated_at <= ?2
+             ORDER BY frame_id DESC LIMIT ?3",
+        )?;
+        let jobs = stmt
+            .query_map(params![OcrStatus::Pending.as_str(), cutoff, limit], |row| {
+                Ok(OcrJob {
                     frame_id: row.get(0)?,
-                    full_text: None, // Since the full text is not being fetched here
-                    application_name: row.get(2)?,
-                    timestamp: row.get(3)?,
-                    file_path: row.get(4)?,
-                    offset_index: row.get(5)?,
+                    attempts: row.get(1)?,
                 })
             })?
             .collect::<Result<Vec<_>, rusqlite::Error>>()?;
 
-        Ok(results)
+        for job in &jobs {
+            self.conn.execute(
+                "UPDATE ocr_queue SET status = ?1, updated_at = ?2 WHERE frame_id = ?3",
+                params![
+                    OcrStatus::Processing.as_str(),
+                    Utc::now().naive_utc(),
+                    job.frame_id
+                ],
+            )?;
+        }
+        Ok(jobs)
     }
 
-    // Method to get recent text context
-    pub fn get_recent_text_context(&self) -> Result<Vec<String>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT text FROM all_text ORDER BY frame_id DESC LIMIT ?1")?;
-        let texts = stmt
-            .query_map(params![self.recent_frames_threshold], |row| Ok(row.get(0)?))?
-            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    // Method to mark an OCR job as done
+    pub fn complete_ocr_job(&self, frame_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE ocr_queue SET status = ?1, last_error = NULL, updated_at = ?2 WHERE frame_id = ?3",
+            params![OcrStatus::Done.as_str(), Utc::now().naive_utc(), frame_id],
+        )?;
+        Ok(())
+    }
 
-        Ok(texts)
+    // Method to record a failed OCR attempt, the job is retried until `max_attempts` is reached
+    pub fn fail_ocr_job(&self, frame_id: i64, error: &str, max_attempts: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE ocr_queue
+             SET attempts = attempts + 1,
+                 status = CASE WHEN attempts + 1 >= ?1 THEN ?2 ELSE ?3 END,
+                 last_error = ?4,
+                 updated_at = ?5
+             WHERE frame_id = ?6",
+            params![
+                max_attempts,
+                OcrStatus::Failed.as_str(),
+                OcrStatus::Pending.as_str(),
+                error,
+                Utc::now().naive_utc(),
+                frame_id,
+            ],
+        )?;
+        Ok(())
     }
 
-    // Method to get the maximum frame ID
-    pub fn get_max_frame(&self) -> Result<i64> {
+    // Method to hand an OCR job back to the queue without counting an attempt
+    pub fn release_ocr_job(&self, frame_id: i64) -> Result<()> {
+        self.conn.execute(
+            "UPDATE ocr_queue SET status = ?1, updated_at = ?2 WHERE frame_id = ?3",
+            params![
+                OcrStatus::Pending.as_str(),
+                Utc::now().naive_utc(),
+                frame_id
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Jobs left processing by a previous run will never finish, so put them back in the queue
+    fn reset_stale_ocr_jobs(&self) -> Result<()> {
+        self.conn.execute(
+            "UPDATE ocr_queue SET status = ?1 WHERE status = ?2",
+            params![OcrStatus::Pending.as_str(), OcrStatus::Processing.as_str()],
+        )?;
+        Ok(())
+    }
+
+    // Method to re-run OCR for every frame in a time range, returns the number of frames queued
+    pub fn requeue_ocr_range(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<usize> {
+        let (first_frame_id, last_frame_id): (Option<i64>, Option<i64>) = self.conn.query_row(
+            "SELECT MIN(id), MAX(id) FROM frames WHERE timestamp BETWEEN ?1 AND ?2",
+            params![from, to],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        // Frames that lost their text but weren't queued would never get it back
+        let (queued, embeddings) = self.in_savepoint("requeue_ocr_range", || {
+            let mut embeddings = Vec::new();
+            if let (Some(first), Some(last)) = (first_frame_id, last_frame_id) {
+                self.remove_text_for_frames(first, last)?;
+                // The text is about to change, so do its embeddings, the screenshots' stay
+                embeddings = self.delete_embeddings_for_frames(first, last, Some("text"))?;
+            }
+            self.conn.execute(
+                "DELETE FROM ocr_results WHERE frame_id IN
+                 (SELECT id FROM frames WHERE timestamp BETWEEN ?1 AND ?2)",
+                params![from, to],
+            )?;
+            self.conn.execute(
+                "DELETE FROM ocr_words WHERE frame_id IN
+                 (SELECT id FROM frames WHERE timestamp BETWEEN ?1 AND ?2)",
+                params![from, to],
+            )?;
+            self.conn.execute(
+                "DELETE FROM embedding_errors WHERE frame_id IN
+                 (SELECT id FROM frames WHERE timestamp BETWEEN ?1 AND ?2)",
+                params![from, to],
+            )?;
+            let queued = self.conn.execute(
+                "INSERT OR REPLACE INTO ocr_queue (frame_id, status, attempts, last_error, updated_at)
+                 SELECT id, ?1, 0, NULL, timestamp FROM frames WHERE timestamp BETWEEN ?2 AND ?3",
+                params![OcrStatus::Pending.as_str(), from, to],
+            )?;
+            Ok((queued, embeddings))
+        })?;
+        self.after_commit(AfterCommit::Unindex(embeddings));
+        Ok(queued)
+    }
+
+    // Method to get the newest frames whose text the active model has yet to embed. A frame
+    // leaves the queue when its vectors or why it has none are stored.
+    pub fn get_frames_missing_embeddings(&self, limit: i64) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT frame_id FROM embedding_queue WHERE modality = 'text'
+             ORDER BY frame_id DESC LIMIT ?1",
+        )?;
+        let frame_ids = stmt
+            .query_map(params![limit], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        Ok(frame_ids)
+    }
+
+    // Method to get the newest frames whose screenshot is yet to be embedded, leaving out those
+    // whose chunk isn't written yet
+    pub fn get_frames_missing_image_embeddings(&self, limit: i64) -> Result<Vec<i64>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT q.frame_id FROM embedding_queue q
+             JOIN frames f ON f.id = q.frame_id
+             JOIN video_chunks vc ON vc.id = f.chunk_id
+             WHERE q.modality = 'image' AND vc.finished
+             ORDER BY q.frame_id DESC LIMIT ?1",
+        )?;
+        let frame_ids = stmt
+            .query_map(params![limit], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        Ok(frame_ids)
+    }
+
+    // Method to queue the screenshot of every frame `model_id` hasn't embedded, for frames from
+    // before it was loaded. Returns how many were added.
+    pub fn queue_missing_image_embeddings(&self, model_id: &str) -> Result<usize> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO embedding_queue (frame_id, modality)
+             SELECT f.id, 'image' FROM frames f
+             WHERE NOT EXISTS (SELECT 1 FROM frame_embeddings e
+                               WHERE e.frame_id = f.id AND e.model_id = ?1
+                               AND e.modality = 'image')",
+            params![model_id],
+        )
+    }
+
+    // Method to take a frame's screenshot off the embedding queue when it can't be embedded
+    pub fn skip_image_embedding(&self, frame_id: i64) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM embedding_queue WHERE frame_id = ?1 AND modality = 'image'",
+            params![frame_id],
+        )?;
+        Ok(())
+    }
+
+    // Method to fill the embedding queue with the frames `model_id` (or any model if None)
+    // has yet to embed, replacing what's in it. Only needed when the model changes, since
+    // frames are queued as their text comes in.
+    fn queue_missing_embeddings(&self, model_id: Option<&str>) -> Result<()> {
         self.conn
-            .query_row("SELECT IFNULL(MAX(id), 0) FROM frames", [], |row| {
-                row.get(0)
+            .execute("DELETE FROM embedding_queue WHERE modality = 'text'", [])?;
+        self.conn.execute(
+            &format!(
+                "INSERT INTO embedding_queue (frame_id, modality)
+                 SELECT f.id, 'text' FROM frames f WHERE {}",
+                MISSING_EMBEDDINGS_FILTER
+            ),
+            params![model_id],
+        )?;
+        Ok(())
+    }
+
+    // Method to start re-embedding every frame with `model_id`, or resume the job if it was
+    // interrupted. Returns None when there are no vectors from another model to replace.
+    pub fn start_reindex(&self, model_id: &str) -> Result<Option<ReindexJob>> {
+        let running = self.get_reindex_job()?;
+        if let Some(job) = running.as_ref().filter(|job| job.model_id == model_id) {
+            return Ok(Some(job.clone()));
+        }
+
+        // The model changed again before the job finished, the vectors of the model before it
+        // are still the only complete set
+        let cancelled = running.is_some();
+        let previous_model_id = match running {
+            Some(job) => {
+                self.conn.execute(
+                    "UPDATE reindex_jobs SET status = ?1, finished_at = ?2 WHERE id = ?3",
+                    params![
+                        JobStatus::Cancelled.as_str(),
+                        Utc::now().naive_utc(),
+                        job.id
+                    ],
+                )?;
+                job.previous_model_id
+            }
+            None => self
+                .conn
+                .query_row(
+                    "SELECT model_id FROM frame_embeddings
+                     WHERE model_id != ?1 AND modality = 'text'
+                     GROUP BY model_id ORDER BY COUNT(*) DESC LIMIT 1",
+                    params![model_id],
+                    |row| row.get(0),
+                )
+                .optional()?,
+        };
+        let previous_model_id = match previous_model_id {
+            Some(previous) if previous != model_id => previous,
+            _ => {
+                // Back to the model with every vector, only what it hasn't seen is left
+                if cancelled {
+                    self.queue_missing_embeddings(Some(model_id))?;
+                }
+                return Ok(None);
+            }
+        };
+
+        let started_at = Utc::now().naive_utc();
+        self.conn.execute(
+            "INSERT INTO reindex_jobs (model_id, previous_model_id, status, started_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                model_id,
+                previous_model_id,
+                JobStatus::Running.as_str(),
+                started_at
+            ],
+        )?;
+        let id = self.conn.last_insert_rowid();
+        self.queue_missing_embeddings(Some(model_id))?;
+        Ok(Some(ReindexJob {
+            id,
+            model_id: model_id.to_string(),
+            previous_model_id: Some(previous_model_id),
+            started_at,
+        }))
+    }
+
+    // Method to get the re-index job that's running, if any
+    pub fn get_reindex_job(&self) -> Result<Option<ReindexJob>> {
+        self.conn
+            .query_row(
+                "SELECT id, model_id, previous_model_id, started_at FROM reindex_jobs
+                 WHERE status = ?1 ORDER BY id DESC LIMIT 1",
+                params![JobStatus::Running.as_str()],
+                |row| {
+                    Ok(ReindexJob {
+                        id: row.get(0)?,
+                        model_id: row.get(1)?,
+                        previous_model_id: row.get(2)?,
+                        started_at: row.get(3)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // Method to count how many frames `model_id` has left to embed, out of those it has
+    // embedded or failed to and those still queued
+    pub fn get_reindex_progress(&self, model_id: &str) -> Result<ReindexProgress> {
+        let (done, remaining): (i64, i64) = self.conn.query_row(
+            "SELECT (SELECT COUNT(DISTINCT frame_id) FROM frame_embeddings
+                     WHERE model_id = ?1 AND modality = 'text')
+                    + (SELECT COUNT(*) FROM embedding_errors WHERE model_id = ?1),
+                    (SELECT COUNT(*) FROM embedding_queue WHERE modality = 'text')",
+            params![model_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(ReindexProgress {
+            total: done + remaining,
+            remaining,
+        })
+    }
+
+    // Method to finish the re-index job for `model_id` once every frame is embedded, the
+    // text vectors of other models are deleted along with their indexes
+    pub fn finish_reindex(&self, model_id: &str) -> Result<()> {
+        // A job marked done must have nothing of the old model left
+        let other_models = self.in_savepoint("finish_reindex", || {
+            self.conn.execute(
+                "UPDATE reindex_jobs SET status = ?1, finished_at = ?2
+                 WHERE status = ?3 AND model_id = ?4",
+                params![
+                    JobStatus::Done.as_str(),
+                    Utc::now().naive_utc(),
+                    JobStatus::Running.as_str(),
+                    model_id
+                ],
+            )?;
+            let mut stmt = self.conn.prepare(
+                "SELECT DISTINCT model_id FROM frame_embeddings
+                 WHERE model_id != ?1 AND modality = 'text'",
+            )?;
+            let other_models = stmt
+                .query_map(params![model_id], |row| row.get(0))?
+                .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+            self.conn.execute(
+                "DELETE FROM float_vectors WHERE embedding_id IN
+                 (SELECT id FROM frame_embeddings WHERE model_id != ?1 AND modality = 'text')",
+                params![model_id],
+            )?;
+            self.conn.execute(
+                "DELETE FROM frame_embeddings WHERE model_id != ?1 AND modality = 'text'",
+                params![model_id],
+            )?;
+            self.conn.execute(
+                "DELETE FROM embedding_errors WHERE model_id != ?1",
+                params![model_id],
+            )?;
+            self.conn.execute(
+                "DELETE FROM embedding_cache WHERE model_id != ?1",
+                params![model_id],
+            )?;
+            Ok(other_models)
+        })?;
+        for other in other_models {
+            self.vector_indexes.lock().unwrap().remove(&other);
+            if let Some(path) = self.vector_index_path(&other) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+
+    // Method to store the text embedding of a frame, or of the passage of its text at `span`
+    pub fn insert_embedding(
+        &self,
+        frame_id: i64,
+        model_id: &str,
+        span: Option<(usize, usize)>,
+        vector: &[f32],
+    ) -> Result<i64> {
+        self.store_embedding(frame_id, model_id, "text", span, vector)
+    }
+
+    // Method to store the embedding of a frame's screenshot, searched like text embeddings
+    // with a query vector from the same model
+    pub fn insert_image_embedding(
+        &self,
+        frame_id: i64,
+        model_id: &str,
+        vector: &[f32],
+    ) -> Result<i64> {
+        self.store_embedding(frame_id, model_id, "image", None, vector)
+    }
+
+    fn store_embedding(
+        &self,
+        frame_id: i64,
+        model_id: &str,
+        modality: &str,
+        span: Option<(usize, usize)>,
+        vector: &[f32],
+    ) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO frame_embeddings
+             (frame_id, model_id, dimensions, vector, created_at, span_start, span_end,
+              quantization, modality)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                frame_id,
+                model_id,
+                vector.len() as i64,
+                self.quantization.encode(vector),
+                Utc::now().naive_utc(),
+                span.map(|(start, _)| start as i64),
+                span.map(|(_, end)| end as i64),
+                self.quantization.as_str(),
+                modality,
+            ],
+        )?;
+        let embedding_id = self.conn.last_insert_rowid();
+        if self.quantization != Quantization::Float32 {
+            self.conn.execute(
+                "INSERT INTO float_vectors (embedding_id, vector) VALUES (?1, ?2)",
+                params![embedding_id, Quantization::Float32.encode(vector)],
+            )?;
+        }
+        self.conn.execute(
+            "DELETE FROM embedding_queue WHERE frame_id = ?1 AND modality = ?2",
+            params![frame_id, modality],
+        )?;
+        // Loaded indexes pick the row up from the table when they're next searched, which a
+        // reader that loaded one before this was committed couldn't do if it was added here
+        Ok(embedding_id)
+    }
+
+    // Method to delete the embeddings of frames in an ID range from the table, only those of
+    // `modality` if it's given. Returns their IDs and models to take them out of loaded indexes
+    // with once that's committed.
+    fn delete_embeddings_for_frames(
+        &self,
+        from_frame_id: i64,
+        to_frame_id: i64,
+        modality: Option<&str>,
+    ) -> Result<Vec<(i64, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, model_id FROM frame_embeddings
+             WHERE frame_id BETWEEN ?1 AND ?2 AND (?3 IS NULL OR modality = ?3)",
+        )?;
+        let embeddings = stmt
+            .query_map(params![from_frame_id, to_frame_id, modality], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        self.conn.execute(
+            "DELETE FROM float_vectors WHERE embedding_id IN
+             (SELECT id FROM frame_embeddings
+              WHERE frame_id BETWEEN ?1 AND ?2 AND (?3 IS NULL OR modality = ?3))",
+            params![from_frame_id, to_frame_id, modality],
+        )?;
+        self.conn.execute(
+            "DELETE FROM frame_embeddings
+             WHERE frame_id BETWEEN ?1 AND ?2 AND (?3 IS NULL OR modality = ?3)",
+            params![from_frame_id, to_frame_id, modality],
+        )?;
+        Ok(embeddings)
+    }
+
+    // Method to remove deleted embeddings from the loaded vector indexes
+    fn unindex_embeddings(&self, embeddings: Vec<(i64, String)>) {
+        for (embedding_id, model_id) in embeddings {
+            let shared = self.vector_indexes.lock().unwrap().get(&model_id).cloned();
+            if let Some(shared) = shared {
+                if let Some(loaded) = shared.write().unwrap().as_mut() {
+                    loaded.dirty |= loaded.index.remove(embedding_id);
+                }
+            }
+        }
+    }
+
+    // Method to delete every frame in a time range along with its text, OCR results and
+    // embeddings, returns the number of frames deleted. The frames stay in their video chunks
+    // but can no longer be found or opened.
+    pub fn forget_range(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<usize> {
+        let (first_frame_id, last_frame_id): (Option<i64>, Option<i64>) = self.conn.query_row(
+            "SELECT MIN(id), MAX(id) FROM frames WHERE timestamp BETWEEN ?1 AND ?2",
+            params![from, to],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (first, last) = match (first_frame_id, last_frame_id) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(0),
+        };
+
+        // Half forgotten frames would keep some of their text, so it's all or nothing
+        let (deleted, embeddings) = self.in_savepoint("forget_range", || {
+            self.remove_text_for_frames(first, last)?;
+            let embeddings = self.delete_embeddings_for_frames(first, last, None)?;
+            // Cached vectors aren't linked to frames, so none are kept for the forgotten text
+            self.conn.execute("DELETE FROM embedding_cache", [])?;
+            // Topic keywords come from the text too, so runs over any of the range go
+            self.delete_topic_runs(from, to)?;
+            for table in [
+                "ocr_results",
+                "ocr_words",
+                "ocr_queue",
+                "embedding_errors",
+                "embedding_queue",
+            ] {
+                self.conn.execute(
+                    &format!("DELETE FROM {} WHERE frame_id BETWEEN ?1 AND ?2", table),
+                    params![first, last],
+                )?;
+            }
+            let deleted = self.conn.execute(
+                "DELETE FROM frames WHERE id BETWEEN ?1 AND ?2",
+                params![first, last],
+            )?;
+            self.rebuild_sessions(first, last)?;
+            Ok((deleted, embeddings))
+        })?;
+        self.after_commit(AfterCommit::Unindex(embeddings));
+        self.after_commit(AfterCommit::ClearEmbeddingCache);
+        Ok(deleted)
+    }
+
+    // Method to get the file a model's vector index is saved to
+    fn vector_index_path(&self, model_id: &str) -> Option<PathBuf> {
+        let name = model_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
             })
+            .collect::<String>();
+        self.index_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("embeddings-{}.hnsw", name)))
     }
 
-    // Method to get the last accessible frame ID
-    pub fn get_last_accessible_frame(&self) -> Result<i64> {
+    // Method to get a model's vector index, loaded or not
+    fn shared_vector_index(&self, model_id: &str) -> SharedVectorIndex {
+        self.vector_indexes
+            .lock()
+            .unwrap()
+            .entry(model_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    // Method to run `f` with a model's vector index, loading it first if needed and adding
+    // embeddings stored since it was last used. Only that takes the index's write lock, `f`
+    // runs alongside other searches of it.
+    fn with_vector_index<F, R>(&self, model_id: &str, dimensions: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&HnswIndex) -> R,
+    {
+        let shared = self.shared_vector_index(model_id);
+        {
+            let mut loaded = shared.write().unwrap();
+            match loaded.as_mut() {
+                Some(loaded) if loaded.index.dimensions() == dimensions => {
+                    loaded.dirty |= self.catch_up_vector_index(&mut loaded.index, model_id)? > 0;
+                }
+                _ => {
+                    *loaded = Some(VectorIndex {
+                        index: self.load_vector_index(model_id, dimensions)?,
+                        dirty: true,
+                        last_saved: Instant::now(),
+                    });
+                }
+            }
+        }
+        let loaded = shared.read().unwrap();
+        Ok(f(&loaded.as_ref().unwrap().index))
+    }
+
+    // Method to load a model's vector index from disk, or build it if there isn't a usable one,
+    // and bring it in line with frame_embeddings. The saved index may be behind the table
+    // (embeddings added or deleted since it was written, e.g. before a crash), so anything
+    // it has that the table doesn't is removed and rows after its watermark are added.
+    fn load_vector_index(&self, model_id: &str, dimensions: usize) -> Result<HnswIndex> {
+        let saved = self.vector_index_path(model_id).and_then(|path| {
+            if !path.exists() {
+                return None;
+            }
+            match HnswIndex::load(&path) {
+                Ok(index) if index.dimensions() == dimensions => Some(index),
+                Ok(_) => None,
+                Err(e) => {
+                    println!("Rebuilding vector index {}: {}", path.display(), e);
+                    None
+                }
+            }
+        });
+        let mut index = saved.unwrap_or_else(|| HnswIndex::new(dimensions));
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM frame_embeddings WHERE model_id = ?1 AND dimensions = ?2")?;
+        let embedding_ids = stmt
+            .query_map(params![model_id, dimensions as i64], |row| row.get(0))?
+            .collect::<Result<HashSet<i64>, rusqlite::Error>>()?;
+        for id in index.ids() {
+            if !embedding_ids.contains(&id) {
+                index.remove(id);
+            }
+        }
+
+        self.catch_up_vector_index(&mut index, model_id)?;
+        Ok(index)
+    }
+
+    // Method to add a model's embeddings stored after an index's watermark, returns how many
+    fn catch_up_vector_index(&self, index: &mut HnswIndex, model_id: &str) -> Result<usize> {
+        let dimensions = index.dimensions();
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT e.id, {} FROM frame_embeddings e {}
+             WHERE e.model_id = ?1 AND e.dimensions = ?2 AND e.id > ?3 ORDER BY e.id",
+            FULL_VECTOR, FULL_VECTOR_JOIN
+        ))?;
+        let mut rows = stmt.query(params![model_id, dimensions as i64, index.watermark()])?;
+        let mut added = 0;
+        while let Some(row) = rows.next()? {
+            let vector: Vec<u8> = row.get(1)?;
+            let quantization = parse_quantization(row.get(2)?);
+            index.insert(row.get(0)?, &quantization.decode(&vector, dimensions));
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    // Method to rebuild a model's vector index from frame_embeddings, returns its size
+    pub fn rebuild_vector_index(&self, model_id: &str, dimensions: usize) -> Result<usize> {
+        self.vector_indexes.lock().unwrap().remove(model_id);
+        if let Some(path) = self.vector_index_path(model_id) {
+            let _ = fs::remove_file(path);
+        }
+        self.with_vector_index(model_id, dimensions, |index| index.len())
+    }
+
+    // Method to write changed vector indexes to disk, at most once per `min_interval` each
+    pub fn save_vector_indexes(&self, min_interval: Duration) -> std::io::Result<()> {
+        let vector_indexes = self
+            .vector_indexes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(model_id, shared)| (model_id.clone(), shared.clone()))
+            .collect::<Vec<_>>();
+        for (model_id, shared) in vector_indexes {
+            let mut loaded = shared.write().unwrap();
+            let loaded = match loaded.as_mut() {
+                Some(loaded) => loaded,
+                None => continue,
+            };
+            if !loaded.dirty || loaded.last_saved.elapsed() < min_interval {
+                continue;
+            }
+            if loaded.index.needs_compaction() {
+                loaded.index = loaded.index.compact();
+            }
+            if let Some(path) = self.vector_index_path(&model_id) {
+                loaded.index.save(&path)?;
+            }
+            loaded.dirty = false;
+            loaded.last_saved = Instant::now();
+        }
+        Ok(())
+    }
+
+    // Method to unload vector indexes and delete their files
+    fn remove_vector_indexes(&self) {
+        self.vector_indexes.lock().unwrap().clear();
+        let entries = match self.index_dir.as_ref().map(fs::read_dir) {
+            Some(Ok(entries)) => entries,
+            _ => return,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("embeddings-") && name.ends_with(".hnsw") {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    // Method to get the cached vectors of texts by hash, for those in the cache
+    pub fn get_cached_embeddings(
+        &self,
+        model_id: &str,
+        text_hashes: &[String],
+    ) -> Result<HashMap<String, Vec<f32>>> {
+        let hashes = text_hashes
+            .iter()
+            .map(|hash| rusqlite::types::Value::from(hash.clone()))
+            .collect::<Vec<_>>();
+        let mut stmt = self.conn.prepare(
+            "SELECT text_hash, dimensions, vector, quantization FROM embedding_cache
+             WHERE model_id = ?1 AND text_hash IN rarray(?2)",
+        )?;
+        let cached = stmt
+            .query_map(params![model_id, Rc::new(hashes.clone())], |row| {
+                let dimensions: i64 = row.get(1)?;
+                let vector: Vec<u8> = row.get(2)?;
+                let quantization = parse_quantization(row.get(3)?);
+                Ok((
+                    row.get(0)?,
+                    quantization.decode(&vector, dimensions as usize),
+                ))
+            })?
+            .collect::<Result<HashMap<String, Vec<f32>>, rusqlite::Error>>()?;
+        if !cached.is_empty() {
+            self.conn.execute(
+                "UPDATE embedding_cache SET last_used = ?1
+                 WHERE model_id = ?2 AND text_hash IN rarray(?3)",
+                params![Utc::now().naive_utc(), model_id, Rc::new(hashes)],
+            )?;
+        }
+        Ok(cached)
+    }
+
+    // Method to cache the vector of a text, stored in the current quantization like the
+    // embeddings made from it
+    pub fn insert_cached_embedding(
+        &self,
+        model_id: &str,
+        text_hash: &str,
+        vector: &[f32],
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO embedding_cache
+             (model_id, text_hash, dimensions, vector, quantization, last_used)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                model_id,
+                text_hash,
+                vector.len() as i64,
+                self.quantization.encode(vector),
+                self.quantization.as_str(),
+                Utc::now().naive_utc(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Method to delete the least recently used cached vectors past the cache's size
+    pub fn prune_embedding_cache(&self) -> Result<usize> {
+        self.conn.execute(
+            "DELETE FROM embedding_cache WHERE rowid NOT IN
+             (SELECT rowid FROM embedding_cache ORDER BY last_used DESC LIMIT ?1)",
+            params![EMBEDDING_CACHE_MAX_ENTRIES],
+        )
+    }
+
+    // Method to count the cached vectors
+    pub fn get_embedding_cache_count(&self) -> Result<i64> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM embedding_cache", [], |row| row.get(0))
+    }
+
+    // Method to set how new vectors are stored
+    pub fn set_quantization(&mut self, quantization: Quantization) {
+        self.quantization = quantization;
+    }
+
+    pub fn quantization(&self) -> Quantization {
+        self.quantization
+    }
+
+    pub fn embedding_cache(&self) -> &EmbeddingCache {
+        &self.embedding_cache
+    }
+
+    // Method to re-encode up to `limit` stored vectors that aren't in the current quantization,
+    // returns how many were converted. Vectors are re-encoded from their float32 copy, so
+    // only ones quantized before copies were kept lose precision for good.
+    pub fn requantize_embeddings(&self, limit: i64) -> Result<usize> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT e.id, e.dimensions, {} FROM frame_embeddings e {}
+             WHERE e.quantization != ?1 LIMIT ?2",
+            FULL_VECTOR, FULL_VECTOR_JOIN
+        ))?;
+        let rows = stmt
+            .query_map(params![self.quantization.as_str(), limit], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                    parse_quantization(row.get(3)?),
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        for (embedding_id, dimensions, vector, quantization) in &rows {
+            let vector = quantization.decode(vector, *dimensions as usize);
+            self.conn.execute(
+                "UPDATE frame_embeddings SET vector = ?1, quantization = ?2 WHERE id = ?3",
+                params![
+                    self.quantization.encode(&vector),
+                    self.quantization.as_str(),
+                    embedding_id
+                ],
+            )?;
+            // A full-precision vector is its own copy, a decoded one isn't worth keeping
+            if self.quantization == Quantization::Float32 {
+                self.conn.execute(
+                    "DELETE FROM float_vectors WHERE embedding_id = ?1",
+                    params![embedding_id],
+                )?;
+            } else if *quantization == Quantization::Float32 {
+                self.conn.execute(
+                    "INSERT OR REPLACE INTO float_vectors (embedding_id, vector) VALUES (?1, ?2)",
+                    params![embedding_id, Quantization::Float32.encode(&vector)],
+                )?;
+            }
+        }
+        Ok(rows.len())
+    }
+
+    // Method to report how much space each model's vectors take, and would take in each
+    // quantization
+    pub fn get_embedding_storage(&self) -> Result<Vec<EmbeddingStorage>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT e.model_id, e.dimensions, COUNT(*),
+                    SUM(LENGTH(e.vector) + IFNULL(LENGTH(fv.vector), 0))
+             FROM frame_embeddings e {}
+             GROUP BY e.model_id, e.dimensions ORDER BY e.model_id, e.dimensions",
+            FULL_VECTOR_JOIN
+        ))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)? as usize,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(rows
+            .into_iter()
+            .map(|(model_id, dimensions, embeddings, stored_bytes)| {
+                let index_bytes = self
+                    .vector_index_path(&model_id)
+                    .and_then(|path| fs::metadata(path).ok())
+                    .map(|metadata| metadata.len())
+                    .unwrap_or(0);
+                EmbeddingStorage {
+                    estimated_bytes: Quantization::ALL
+                        .into_iter()
+                        .map(|q| (q, embeddings * q.stored_size(dimensions) as i64))
+                        .collect(),
+                    model_id,
+                    dimensions,
+                    embeddings,
+                    stored_bytes,
+                    index_bytes,
+                }
+            })
+            .collect())
+    }
+
+    // Method to record that a frame's text couldn't be embedded, so it isn't retried
+    pub fn insert_embedding_error(&self, frame_id: i64, model_id: &str, error: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO embedding_errors (frame_id, model_id, error) VALUES (?1, ?2, ?3)",
+            params![frame_id, model_id, error],
+        )?;
+        self.conn.execute(
+            "DELETE FROM embedding_queue WHERE frame_id = ?1 AND modality = 'text'",
+            params![frame_id],
+        )?;
+        Ok(())
+    }
+
+    // Method to check whether a frame's video chunk is still being buffered, i.e. hasn't been
+    // started yet. Read from the table so any connection can tell.
+    pub fn is_frame_chunk_pending(&self, frame_id: i64) -> Result<bool> {
         self.conn.query_row(
-            "SELECT f.id FROM frames f
-             JOIN video_chunks vc ON f.chunk_id = vc.id
-             ORDER BY f.id DESC LIMIT 1",
-            [],
+            "SELECT NOT EXISTS (SELECT 1 FROM video_chunks vc
+                                WHERE vc.id = f.chunk_id AND vc.finished)
+             FROM frames f WHERE f.id = ?1",
+            params![frame_id],
             |row| row.get(0),
         )
     }
 
-    // Method to get all unique application names
-    pub fn get_all_application_names(&self) -> Result<Vec<String>> {
+    // Method to count OCR jobs in each status
+    pub fn get_ocr_queue_counts(&self) -> Result<Vec<(String, i64)>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT active_application_name FROM unique_app_names")?;
-        let app_names = stmt
-            .query_map([], |row| Ok(row.get(0)?))?
-            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+            .prepare("SELECT status, COUNT(*) FROM ocr_queue GROUP BY status")?;
+        let counts = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(counts)
+    }
 
-        Ok(app_names)
+    // Method to get a frame by index
+    pub fn get_frame(&self, index: i64) -> Result<Option<(i64, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.offset_index, vc.file_path FROM frames f
+         JOIN video_chunks vc ON f.chunk_id = vc.id
+         WHERE f.id = ?1",
+        )?;
+        let mut rows = stmt.query(params![index])?;
+
+        if let Some(row) = rows.next()? {
+            let offset_index: i64 = row.get(0)?;
+            let file_path: String = row.get(1)?;
+            Ok(Some((offset_index, file_path)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Method to check if a frame exists for a given index
+    pub fn frame_exists(&self, index: i64) -> Result<bool> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM frames WHERE id = ?1",
+            params![index],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    // Method to retrieve the file path of a video chunk by its index
+    pub fn get_video_chunk_path(&self, index: i64) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT file_path FROM video_chunks WHERE id = ?1")?;
+        let mut rows = stmt.query(params![index])?;
+
+        if let Some(row) = rows.next()? {
+            let file_path: String = row.get(0)?;
+            Ok(Some(file_path))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Method to perform a search based on app name and/or text, results contain all of `terms`
+    pub fn search(
+        &self,
+        terms: &[impl AsRef<str>],
+        limit: i64,
+        offset: i64,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>> {
+        let (match_query, conditions, mut params) = text_search_conditions(terms, filters);
+        // FTS5's functions only work in a query with a MATCH, and need the table's own name
+        let match_columns = match match_query {
+            Some(_) => format!(
+                "snippet(all_text, 0, '{open}', '{close}', '…', {tokens}),
+                 highlight(all_text, 0, '{open}', '{close}'),
+                 highlight(all_text, 0, char({offset_open}), char({offset_close}))",
+                open = MATCH_OPEN,
+                close = MATCH_CLOSE,
+                tokens = SNIPPET_TOKENS,
+                offset_open = OFFSET_OPEN as u32,
+                offset_close = OFFSET_CLOSE as u32
+            ),
+            None => "NULL, NULL, NULL".to_string(),
+        };
+
+        let mut order_by = Vec::new();
+        if let (Some(min_confidence), LowConfidence::DownRank) =
+            (filters.min_confidence, filters.low_confidence)
+        {
+            params.push((min_confidence as f64).into());
+            order_by.push(format!("{} < ?{}", SPAN_CONFIDENCE, params.len()));
+        }
+        if let (SearchOrder::Relevance, Some(_)) = (filters.order, &match_query) {
+            order_by.push("bm25(all_text)".to_string());
+        }
+        // Ties are broken like `older_than` expects
+        order_by.push("lf.timestamp DESC, s.last_frame_id DESC, s.id DESC".to_string());
+        params.push(limit.into());
+        params.push(offset.into());
+
+        let query = format!(
+            "SELECT s.first_frame_id, all_text.text, f.active_application_name, f.timestamp, vc.file_path, f.offset_index,
+                    s.last_frame_id, lf.timestamp, {}, s.id
+             {}
+             ORDER BY {} LIMIT ?{} OFFSET ?{}",
+            match_columns,
+            conditions,
+            order_by.join(", "),
+            params.len() - 1,
+            params.len()
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+
+        let search_results = stmt
+            .query_map(params_from_iter(params), |row| {
+                let offsets_marked: Option<String> = row.get(10)?;
+                Ok(SearchResult {
+                    frame_id: row.get(0)?,
+                    full_text: row.get(1)?,
+                    application_name: row.get(2)?,
+                    timestamp: row.get(3)?,
+                    file_path: row.get(4)?,
+                    offset_index: row.get(5)?,
+                    last_frame_id: row.get(6)?,
+                    last_seen: row.get(7)?,
+                    snippet: row.get(8)?,
+                    highlight: row.get(9)?,
+                    match_offsets: offsets_marked
+                        .map(|marked| match_offsets(&marked))
+                        .unwrap_or_default(),
+                    span_id: row.get(11)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(search_results)
+    }
+
+    // Method to count the results of `search`, up to COUNT_LIMIT
+    pub fn count_search(
+        &self,
+        terms: &[impl AsRef<str>],
+        filters: &SearchFilters,
+    ) -> Result<ResultCount> {
+        let filters = SearchFilters {
+            older_than: None,
+            ..filters.clone()
+        };
+        let (_, conditions, params) = text_search_conditions(terms, &filters);
+        self.count_rows(&format!("SELECT 1 {}", conditions), params)
+    }
+
+    // Method to rank frames by the cosine similarity of their text embedding to `query_vector`
+    pub fn semantic_search(
+        &self,
+        query_vector: &[f32],
+        model_id: &str,
+        limit: i64,
+        offset: i64,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SemanticResult>> {
+        let shared = self.vector_indexes.lock().unwrap().get(model_id).cloned();
+        let loaded_count = shared.and_then(|shared| {
+            shared
+                .read()
+                .unwrap()
+                .as_ref()
+                .filter(|loaded| loaded.index.dimensions() == query_vector.len())
+                .map(|loaded| loaded.index.len() as i64)
+        });
+        let embedding_count = match loaded_count {
+            Some(count) => count,
+            None => self.conn.query_row(
+                "SELECT COUNT(*) FROM frame_embeddings WHERE model_id = ?1 AND dimensions = ?2",
+                params![model_id, query_vector.len() as i64],
+                |row| row.get(0),
+            )?,
+        };
+        let needed = offset.max(0).saturating_add(limit.max(0)) as usize;
+        let scored = if embedding_count < self.ann_min_embeddings {
+            self.scan_embeddings(query_vector, model_id, needed, filters)?
+        } else {
+            self.search_vector_index(query_vector, model_id, needed, filters)?
+        };
+
+        let mut results = Vec::new();
+        for (embedding_id, frame_id, similarity) in best_per_frame(scored)
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+        {
+            if let Some(mut result) = self.get_frame_result(frame_id)? {
+                result.full_text = self.get_text_for_frame(frame_id)?;
+                let passage = match &result.full_text {
+                    Some(text) => self.get_passage(embedding_id, frame_id, text)?,
+                    None => None,
+                };
+                results.push(SemanticResult {
+                    result,
+                    similarity,
+                    passage,
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    // Method to get the passage of a frame's text an embedding was made from
+    fn get_passage(&self, embedding_id: i64, frame_id: i64, text: &str) -> Result<Option<Passage>> {
+        let span: Option<(Option<i64>, Option<i64>)> = self
+            .conn
+            .query_row(
+                "SELECT span_start, span_end FROM frame_embeddings WHERE id = ?1",
+                params![embedding_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let (start, end) = match span {
+            Some((Some(start), Some(end))) => (start as usize, end as usize),
+            _ => return Ok(None),
+        };
+        let words = self.get_ocr_words(frame_id)?;
+        Ok(Some(Passage {
+            text: text
+                .chars()
+                .skip(start)
+                .take(end.saturating_sub(start))
+                .collect(),
+            start,
+            end,
+            region: passage_region(text, &words, start, end),
+        }))
+    }
+
+    // Method to score every embedding that matches the filters, returns
+    // (embedding ID, frame ID, similarity) most similar first. Quantized vectors are scored
+    // against a quantized query first, then the embeddings of the best few frames per result
+    // needed are rescored with the float query against their float32 copies and the rest
+    // are dropped.
+    fn scan_embeddings(
+        &self,
+        query_vector: &[f32],
+        model_id: &str,
+        needed: usize,
+        filters: &SearchFilters,
+    ) -> Result<Vec<(i64, i64, f32)>> {
+        let mut query = String::from(
+            "SELECT e.id, e.frame_id, e.vector, e.quantization FROM frame_embeddings e
+             JOIN frames f ON f.id = e.frame_id
+             JOIN video_chunks vc ON f.chunk_id = vc.id
+             LEFT JOIN ocr_results o ON o.frame_id = e.frame_id
+             WHERE e.model_id = ?1 AND e.dimensions = ?2 ",
+        );
+        let mut params: Vec<rusqlite::types::Value> = vec![
+            model_id.to_string().into(),
+            (query_vector.len() as i64).into(),
+        ];
+        push_frame_filters(&mut query, &mut params, filters);
+
+        let quantized_query = QuantizedQuery::new(query_vector);
+        let mut stmt = self.conn.prepare(&query)?;
+        let mut candidates = stmt
+            .query_map(params_from_iter(params), |row| {
+                let vector: Vec<u8> = row.get(2)?;
+                let quantization = parse_quantization(row.get(3)?);
+                let similarity = quantized_query.approximate_similarity(quantization, &vector);
+                // Full-precision scores are final, so their vectors needn't be kept
+                let rescore = match quantization {
+                    Quantization::Float32 => None,
+                    _ => Some((quantization, vector)),
+                };
+                Ok((row.get(0)?, row.get(1)?, similarity, rescore))
+            })?
+            .collect::<Result<Vec<(i64, i64, f32, _)>, rusqlite::Error>>()?;
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let max_frames = needed.max(1).saturating_mul(RESCORE_FACTOR);
+        let mut frames = HashSet::new();
+        candidates.retain(|(_, frame_id, _, _)| {
+            if frames.len() == max_frames && !frames.contains(frame_id) {
+                return false;
+            }
+            frames.insert(*frame_id);
+            true
+        });
+        let float_vectors = self.get_float_vectors(
+            candidates
+                .iter()
+                .filter(|candidate| candidate.3.is_some())
+                .map(|candidate| candidate.0),
+        )?;
+        let mut scored = candidates
+            .into_iter()
+            .map(|(embedding_id, frame_id, similarity, rescore)| {
+                let similarity = match (float_vectors.get(&embedding_id), rescore) {
+                    (Some(vector), _) => quantized_query.similarity(
+                        Quantization::Float32,
+                        vector,
+                        query_vector.len(),
+                    ),
+                    (None, Some((quantization, vector))) => {
+                        quantized_query.similarity(quantization, &vector, query_vector.len())
+                    }
+                    (None, None) => similarity,
+                };
+                (embedding_id, frame_id, similarity)
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.2.total_cmp(&a.2));
+        Ok(scored)
+    }
+
+    // Method to get the float32 copies of quantized embeddings by ID, still encoded
+    fn get_float_vectors(&self, ids: impl Iterator<Item = i64>) -> Result<HashMap<i64, Vec<u8>>> {
+        let ids = ids.map(rusqlite::types::Value::from).collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT embedding_id, vector FROM float_vectors WHERE embedding_id IN rarray(?1)",
+        )?;
+        let float_vectors = stmt
+            .query_map(params![Rc::new(ids)], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, rusqlite::Error>>()?;
+        Ok(float_vectors)
+    }
+
+    // Method to get embeddings of at least `needed` frames matching the filters from the vector
+    // index, as (embedding ID, frame ID, similarity) most similar first. Filters are applied
+    // to what the index returns, so the search is widened until enough pass them or the
+    // index runs out. Hits are rescored against the vectors' float32 copies, the index is
+    // built from them too.
+    fn search_vector_index(
+        &self,
+        query_vector: &[f32],
+        model_id: &str,
+        needed: usize,
+        filters: &SearchFilters,
+    ) -> Result<Vec<(i64, i64, f32)>> {
+        let mut k = needed.saturating_mul(4).max(50);
+        loop {
+            let hits = self.with_vector_index(model_id, query_vector.len(), |index| {
+                index.search(query_vector, k)
+            })?;
+
+            let mut query = format!(
+                "SELECT e.id, e.frame_id, {} FROM frame_embeddings e
+                 JOIN frames f ON f.id = e.frame_id
+                 JOIN video_chunks vc ON f.chunk_id = vc.id
+                 LEFT JOIN ocr_results o ON o.frame_id = e.frame_id
+                 {}
+                 WHERE e.id IN rarray(?1) ",
+                FULL_VECTOR, FULL_VECTOR_JOIN
+            );
+            // ?1 is the list of IDs, bound separately since it isn't a plain value
+            let mut params: Vec<rusqlite::types::Value> = vec![rusqlite::types::Value::Null];
+            push_frame_filters(&mut query, &mut params, filters);
+
+            let ids = hits
+                .iter()
+                .map(|(id, _)| rusqlite::types::Value::from(*id))
+                .collect::<Vec<_>>();
+            let mut stmt = self.conn.prepare(&query)?;
+            stmt.raw_bind_parameter(1, Rc::new(ids))?;
+            for (index, param) in params.iter().enumerate().skip(1) {
+                stmt.raw_bind_parameter(index + 1, param)?;
+            }
+            let quantized_query = QuantizedQuery::new(query_vector);
+            let mut scored = Vec::new();
+            let mut rows = stmt.raw_query();
+            while let Some(row) = rows.next()? {
+                let vector: Vec<u8> = row.get(2)?;
+                let quantization = parse_quantization(row.get(3)?);
+                let similarity =
+                    quantized_query.similarity(quantization, &vector, query_vector.len());
+                scored.push((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, similarity));
+            }
+            scored.sort_by(|a, b| b.2.total_cmp(&a.2));
+            let frames = scored.iter().map(|s| s.1).collect::<HashSet<_>>().len();
+            if frames >= needed || hits.len() < k {
+                return Ok(scored);
+            }
+            k = k.saturating_mul(4);
+        }
+    }
+
+    // Method to combine keyword and semantic search into one ranking. Without a query vector
+    // (e.g. no model is loaded) this is a keyword search with fusion scores.
+    pub fn hybrid_search(
+        &self,
+        terms: &[impl AsRef<str>],
+        query_vector: Option<(&[f32], &str)>,
+        limit: i64,
+        offset: i64,
+        filters: &SearchFilters,
+        weights: &HybridWeights,
+    ) -> Result<Vec<HybridResult>> {
+        // Later pages need at least that many candidates from each signal
+        let candidates = weights.candidates.max(offset.saturating_add(limit));
+        // Keyword ranks are by bm25 whatever order text search was asked for, otherwise the
+        // newest matches would count as the best ones
+        let keyword_filters = SearchFilters {
+            order: SearchOrder::Relevance,
+            ..filters.clone()
+        };
+        let keyword_results = self.search(terms, candidates, 0, &keyword_filters)?;
+        let semantic_results = match query_vector {
+            Some((vector, model_id)) => {
+                self.semantic_search(vector, model_id, candidates, 0, filters)?
+            }
+            None => vec![],
+        };
+
+        let mut fused: Vec<HybridResult> = keyword_results
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| HybridResult {
+                result,
+                score: weights.keyword / (weights.rrf_k + index as f32 + 1.0),
+                keyword_rank: Some(index + 1),
+                semantic_rank: None,
+                similarity: None,
+                passage: None,
+            })
+            .collect();
+
+        for (index, semantic) in semantic_results.into_iter().enumerate() {
+            let score = weights.semantic / (weights.rrf_k + index as f32 + 1.0);
+            let frame_id = semantic.result.frame_id;
+            // A frame inside a keyword hit's span is the same hit
+            let existing = fused.iter_mut().find(|hit| {
+                hit.semantic_rank.is_none()
+                    && hit.result.frame_id <= frame_id
+                    && frame_id <= hit.result.last_frame_id
+            });
+            match existing {
+                Some(hit) => {
+                    hit.score += score;
+                    hit.semantic_rank = Some(index + 1);
+                    hit.similarity = Some(semantic.similarity);
+                    hit.passage = semantic.passage;
+                }
+                None => fused.push(HybridResult {
+                    result: semantic.result,
+                    score,
+                    keyword_rank: None,
+                    semantic_rank: Some(index + 1),
+                    similarity: Some(semantic.similarity),
+                    passage: semantic.passage,
+                }),
+            }
+        }
+
+        fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(fused
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    // Method to get the first and last frame IDs of the session `frame_id` belongs to, a run
+    // of frames without a capture gap longer than SESSION_GAP. None if there's no such frame.
+    pub fn get_session_bounds(&self, frame_id: i64) -> Result<Option<(i64, i64)>> {
+        self.conn
+            .query_row(
+                "SELECT s.first_frame_id, s.last_frame_id FROM sessions s
+                 WHERE s.last_frame_id >= ?1 AND s.first_frame_id <= ?1
+                 AND EXISTS (SELECT 1 FROM frames WHERE id = ?1)
+                 ORDER BY s.last_frame_id LIMIT 1",
+                params![frame_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+    }
+
+    // Method to find frames from other sessions that show something like `frame_id` does,
+    // by the mean of its text embeddings from `model_id` and by its screenshot's hash, whose
+    // ranks are fused like hybrid search's. Either signal is skipped when the frame doesn't
+    // have it, and hashes are only compared with frames from around the same time.
+    pub fn related_frames(
+        &self,
+        frame_id: i64,
+        model_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<RelatedFrame>> {
+        let session = match self.get_session_bounds(frame_id)? {
+            Some(session) => session,
+            None => return Ok(vec![]),
+        };
+        // Only frames that look alike have a hash rank, the distance of the rest is still shown
+        let mut candidates: HashMap<i64, RelatedCandidate> = HashMap::new();
+        let query_vector = match model_id {
+            Some(model_id) => self
+                .get_mean_embedding(frame_id, model_id)?
+                .map(|vector| (model_id, vector)),
+            None => None,
+        };
+        if let Some((model_id, query_vector)) = query_vector {
+            let filters = SearchFilters {
+                exclude_frames: Some(session),
+                ..Default::default()
+            };
+            let semantic = self.semantic_search(
+                &query_vector,
+                model_id,
+                limit.max(0).saturating_mul(2),
+                0,
+                &filters,
+            )?;
+            for (index, hit) in semantic.into_iter().enumerate() {
+                candidates.insert(hit.result.frame_id, (Some((index, hit.similarity)), None));
+            }
+        }
+
+        let (source_hash, timestamp): (Option<i64>, NaiveDateTime) = self.conn.query_row(
+            "SELECT dhash, timestamp FROM frames WHERE id = ?1",
+            params![frame_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if let Some(source_hash) = source_hash {
+            let window = chrono::Duration::from_std(RELATED_HASH_WINDOW).unwrap();
+            let mut stmt = self.conn.prepare(
+                "SELECT id, dhash FROM frames
+                 WHERE timestamp BETWEEN ?1 AND ?2 AND dhash IS NOT NULL
+                 AND (id < ?3 OR id > ?4)",
+            )?;
+            let mut alike = stmt
+                .query_map(
+                    params![timestamp - window, timestamp + window, session.0, session.1],
+                    |row| {
+                        let hash = row.get::<_, i64>(1)? as u64;
+                        Ok((row.get(0)?, dhash::distance(source_hash as u64, hash)))
+                    },
+                )?
+                .collect::<Result<Vec<(i64, u32)>, rusqlite::Error>>()?;
+            alike.sort_by_key(|&(id, distance)| (distance, std::cmp::Reverse(id)));
+            let mut rank = 0;
+            for (id, distance) in alike {
+                let hash_rank = (distance <= MAX_HASH_DISTANCE).then_some(rank);
+                match candidates.get_mut(&id) {
+                    Some(candidate) => candidate.1 = Some((hash_rank, distance)),
+                    None if hash_rank.is_some() => {
+                        candidates.insert(id, (None, Some((hash_rank, distance))));
+                    }
+                    None => continue,
+                }
+                rank += hash_rank.is_some() as usize;
+            }
+        }
+
+        let rrf = |weight: f32, rank: usize| weight / (RELATED_RRF_K + rank as f32 + 1.0);
+        let mut scored = candidates
+            .into_iter()
+            .map(|(id, (text, hash))| {
+                let score = text.map_or(0.0, |(rank, _)| rrf(RELATED_TEXT_WEIGHT, rank))
+                    + hash
+                        .and_then(|(rank, _)| rank)
+                        .map_or(0.0, |rank| rrf(1.0 - RELATED_TEXT_WEIGHT, rank));
+                (
+                    id,
+                    score,
+                    text.map(|(_, similarity)| similarity),
+                    hash.map(|(_, distance)| distance),
+                )
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+
+        let mut related = Vec::new();
+        for (id, score, similarity, hash_distance) in scored {
+            if related.len() as i64 >= limit {
+                break;
+            }
+            if let Some(result) = self.get_frame_result(id)? {
+                related.push(RelatedFrame {
+                    result,
+                    score,
+                    similarity,
+                    hash_distance,
+                });
+            }
+        }
+        Ok(related)
+    }
+
+    // Method to get the mean of a frame's text embeddings from a model, None if it has none
+    fn get_mean_embedding(&self, frame_id: i64, model_id: &str) -> Result<Option<Vec<f32>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT dimensions, vector, quantization FROM frame_embeddings
+             WHERE frame_id = ?1 AND model_id = ?2 AND modality = 'text'",
+        )?;
+        let vectors = stmt
+            .query_map(params![frame_id, model_id], |row| {
+                let dimensions: i64 = row.get(0)?;
+                let vector: Vec<u8> = row.get(1)?;
+                Ok(parse_quantization(row.get(2)?).decode(&vector, dimensions as usize))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+        Ok(vector::mean(vectors.iter().map(|v| v.as_slice())))
+    }
+
+    // Method to record a topic run over a time range, clustered by `start_topic_job`
+    pub fn start_topic_run(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        model_id: &str,
+    ) -> Result<TopicRun> {
+        let created_at = Utc::now().naive_utc();
+        self.conn.execute(
+            "INSERT INTO topic_runs (model_id, range_start, range_end, status, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![model_id, from, to, JobStatus::Running.as_str(), created_at],
+        )?;
+        Ok(TopicRun {
+            id: self.conn.last_insert_rowid(),
+            model_id: model_id.to_string(),
+            from,
+            to,
+            status: JobStatus::Running,
+            error: None,
+            created_at,
+        })
+    }
+
+    pub fn fail_topic_run(&self, run_id: i64, error: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE topic_runs SET status = ?1, error = ?2 WHERE id = ?3",
+            params![JobStatus::Failed.as_str(), error, run_id],
+        )?;
+        Ok(())
+    }
+
+    // Function to fail the topic runs that were running when the app last exited, their jobs
+    // went with it
+    fn fail_interrupted_topic_runs(&self) -> Result<()> {
+        self.conn.execute(
+            "UPDATE topic_runs SET status = ?1, error = 'Interrupted when the app exited'
+             WHERE status = ?2",
+            params![JobStatus::Failed.as_str(), JobStatus::Running.as_str()],
+        )?;
+        Ok(())
+    }
+
+    // Method to get the latest topic run over exactly this time range. A run over a longer one
+    // has topics and spans from outside it, so it doesn't count.
+    pub fn get_topic_run(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Option<TopicRun>> {
+        self.conn
+            .query_row(
+                "SELECT id, model_id, range_start, range_end, status, error, created_at
+                 FROM topic_runs WHERE range_start = ?1 AND range_end = ?2
+                 ORDER BY id DESC LIMIT 1",
+                params![from, to],
+                |row| {
+                    Ok(TopicRun {
+                        id: row.get(0)?,
+                        model_id: row.get(1)?,
+                        from: row.get(2)?,
+                        to: row.get(3)?,
+                        status: JobStatus::parse(&row.get::<_, String>(4)?)
+                            .unwrap_or(JobStatus::Failed),
+                        error: row.get(5)?,
+                        created_at: row.get(6)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // Method to get the mean text embedding from `model_id` of each frame in a time range
+    pub fn get_frame_vectors(
+        &self,
+        model_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Result<Vec<(i64, Vec<f32>)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT e.frame_id, e.dimensions, e.vector, e.quantization FROM frame_embeddings e
+             JOIN frames f ON f.id = e.frame_id
+             WHERE e.model_id = ?1 AND e.modality = 'text' AND f.timestamp BETWEEN ?2 AND ?3
+             ORDER BY e.frame_id",
+        )?;
+        let rows = stmt
+            .query_map(params![model_id, from, to], |row| {
+                let dimensions: i64 = row.get(1)?;
+                let vector: Vec<u8> = row.get(2)?;
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    parse_quantization(row.get(3)?).decode(&vector, dimensions as usize),
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        let mut frames: Vec<(i64, Vec<Vec<f32>>)> = Vec::new();
+        for (frame_id, vector) in rows {
+            match frames.last_mut() {
+                Some((last_id, vectors)) if *last_id == frame_id => vectors.push(vector),
+                _ => frames.push((frame_id, vec![vector])),
+            }
+        }
+        Ok(frames
+            .into_iter()
+            .filter_map(|(frame_id, vectors)| {
+                vector::mean(vectors.iter().map(|v| v.as_slice())).map(|mean| (frame_id, mean))
+            })
+            .collect())
+    }
+
+    // Method to store the topics of a run and mark it done
+    pub fn save_topics(&self, run_id: i64, clusters: &[TopicCluster]) -> Result<()> {
+        for cluster in clusters {
+            self.conn.execute(
+                "INSERT INTO topics (run_id, keywords, representative_frame_id)
+                 VALUES (?1, ?2, ?3)",
+                params![
+                    run_id,
+                    cluster.keywords.join(" "),
+                    cluster.representative_frame_id
+                ],
+            )?;
+            let topic_id = self.conn.last_insert_rowid();
+            let mut stmt = self
+                .conn
+                .prepare("INSERT INTO topic_frames (topic_id, frame_id) VALUES (?1, ?2)")?;
+            for frame_id in &cluster.frame_ids {
+                stmt.execute(params![topic_id, frame_id])?;
+            }
+        }
+        self.conn.execute(
+            "UPDATE topic_runs SET status = ?1 WHERE id = ?2",
+            params![JobStatus::Done.as_str(), run_id],
+        )?;
+        Ok(())
+    }
+
+    // Method to get the topics of a run, biggest first, with the stretches of time their frames
+    // were captured in. A gap longer than SESSION_GAP starts a new stretch.
+    pub fn get_topics(&self, run_id: i64) -> Result<Vec<Topic>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, keywords, representative_frame_id FROM topics WHERE run_id = ?1
+             ORDER BY id",
+        )?;
+        let topics = stmt
+            .query_map(params![run_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT f.timestamp FROM topic_frames tf
+             JOIN frames f ON f.id = tf.frame_id
+             WHERE tf.topic_id = ?1 ORDER BY f.id",
+        )?;
+        let mut result = Vec::new();
+        for (id, keywords, representative_frame_id) in topics {
+            let timestamps = stmt
+                .query_map(params![id], |row| row.get(0))?
+                .collect::<Result<Vec<NaiveDateTime>, rusqlite::Error>>()?;
+            let mut spans: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+            for timestamp in &timestamps {
+                match spans.last_mut() {
+                    Some((_, end))
+                        if (*timestamp - *end).num_seconds() <= SESSION_GAP.as_secs() as i64 =>
+                    {
+                        *end = *timestamp
+                    }
+                    _ => spans.push((*timestamp, *timestamp)),
+                }
+            }
+            result.push(Topic {
+                id,
+                keywords: keywords.split_whitespace().map(String::from).collect(),
+                representative_frame_id,
+                frame_count: timestamps.len() as i64,
+                spans,
+            });
+        }
+        result.sort_by_key(|topic| std::cmp::Reverse(topic.frame_count));
+        Ok(result)
+    }
+
+    // Method to delete topic runs over any part of a time range, with their topics
+    fn delete_topic_runs(&self, from: NaiveDateTime, to: NaiveDateTime) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM topic_frames WHERE topic_id IN
+             (SELECT t.id FROM topics t JOIN topic_runs r ON r.id = t.run_id
+              WHERE r.range_start <= ?2 AND r.range_end >= ?1)",
+            params![from, to],
+        )?;
+        self.conn.execute(
+            "DELETE FROM topics WHERE run_id IN
+             (SELECT id FROM topic_runs WHERE range_start <= ?2 AND range_end >= ?1)",
+            params![from, to],
+        )?;
+        self.conn.execute(
+            "DELETE FROM topic_runs WHERE range_start <= ?2 AND range_end >= ?1",
+            params![from, to],
+        )?;
+        Ok(())
+    }
+
+    // Method to get a single frame as a search result
+    pub fn get_frame_result(&self, frame_id: i64) -> Result<Option<SearchResult>> {
+        self.conn
+            .query_row(
+                "SELECT f.id, f.active_application_name, f.timestamp, vc.file_path, f.offset_index
+                 FROM frames f
+                 JOIN video_chunks vc ON f.chunk_id = vc.id
+                 WHERE f.id = ?1",
+                params![frame_id],
+                |row| {
+                    Ok(SearchResult {
+                        frame_id: row.get(0)?,
+                        full_text: None,
+                        application_name: row.get(1)?,
+                        timestamp: row.get(2)?,
+                        file_path: row.get(3)?,
+                        offset_index: row.get(4)?,
+                        last_frame_id: row.get(0)?,
+                        last_seen: row.get(2)?,
+                        snippet: None,
+                        highlight: None,
+                        match_offsets: vec![],
+                        span_id: None,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    // Method to get recent frames, newest first, that match the filters
+    pub fn get_recent_results(
+        &self,
+        limit: i64,
+        offset: i64,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>> {
+        let (mut query, mut params) = recent_frames_query(
+            "SELECT f.id, f.active_application_name, f.timestamp, vc.file_path, f.offset_index",
+            filters,
+        );
+        if let Some(key) = &filters.older_than {
+            params.push(timestamp_value(key.timestamp));
+            params.push(key.frame_id.into());
+            query.push_str(&format!(
+                "AND (f.timestamp, f.id) < (?{}, ?{}) ",
+                params.len() - 1,
+                params.len()
+            ));
+        }
+        params.push(limit.into());
+        params.push(offset.into());
+        query.push_str(&format!(
+            "ORDER BY f.timestamp DESC, f.id DESC LIMIT ?{} OFFSET ?{}",
+            params.len() - 1,
+            params.len()
+        ));
+
+        let mut stmt = self.conn.prepare(&query)?;
+        let results = stmt
+            .query_map(params_from_iter(params), |row| {
+                Ok(SearchResult {
+                    frame_id: row.get(0)?,
+                    full_text: None, // Since the full text is not being fetched here
+                    application_name: row.get(1)?,
+                    timestamp: row.get(2)?,
+                    file_path: row.get(3)?,
+                    offset_index: row.get(4)?,
+                    last_frame_id: row.get(0)?,
+                    last_seen: row.get(2)?,
+                    snippet: None,
+                    highlight: None,
+                    match_offsets: vec![],
+                    span_id: None,
+                })
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        Ok(results)
+    }
+
+    // Method to count the results of `get_recent_results`, up to COUNT_LIMIT
+    pub fn count_recent(&self, filters: &SearchFilters) -> Result<ResultCount> {
+        let (query, params) = recent_frames_query("SELECT 1", filters);
+        self.count_rows(&query, params)
+    }
+
+    // Method to count the frames with an embedding from `model_id` that match the filters, up
+    // to COUNT_LIMIT. Every one of them is a result of a semantic search.
+    pub fn count_embedded_frames(
+        &self,
+        model_id: &str,
+        filters: &SearchFilters,
+    ) -> Result<ResultCount> {
+        let mut query = String::from(
+            "SELECT DISTINCT e.frame_id FROM frame_embeddings e
+             JOIN frames f ON f.id = e.frame_id
+             JOIN video_chunks vc ON f.chunk_id = vc.id
+             LEFT JOIN ocr_results o ON o.frame_id = e.frame_id
+             WHERE e.model_id = ?1 ",
+        );
+        let mut params: Vec<rusqlite::types::Value> = vec![model_id.to_string().into()];
+        push_frame_filters(&mut query, &mut params, filters);
+        self.count_rows(&query, params)
+    }
+
+    // Method to count the rows of a query, stopping after COUNT_LIMIT so a count of everything
+    // stays quick
+    fn count_rows(&self, query: &str, params: Vec<rusqlite::types::Value>) -> Result<ResultCount> {
+        let count: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM ({} LIMIT {})", query, COUNT_LIMIT + 1),
+            params_from_iter(params),
+            |row| row.get(0),
+        )?;
+        Ok(ResultCount {
+            count: count.min(COUNT_LIMIT),
+            exact: count <= COUNT_LIMIT,
+        })
+    }
+
+    // Method to get recent text context
+    pub fn get_recent_text_context(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT a.text FROM text_spans s
+             JOIN all_text a ON a.rowid = s.block_id
+             ORDER BY s.last_frame_id DESC, s.position LIMIT ?1",
+        )?;
+        let texts = stmt
+            .query_map(params![self.recent_frames_threshold], |row| Ok(row.get(0)?))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+        Ok(texts)
+    }
+
+    // Method to get the maximum frame ID
+    pub fn get_max_frame(&self) -> Result<i64> {
+        self.conn
+            .query_row("SELECT IFNULL(MAX(id), 0) FROM frames", [], |row| {
+                row.get(0)
+            })
+    }
+
+    // Method to get the recorded frame closest to a time, with its timestamp
+    pub fn get_frame_near(&self, timestamp: NaiveDateTime) -> Result<Option<(i64, NaiveDateTime)>> {
+        // The nearest frame on each side, each found through the timestamp index
+        self.conn
+            .query_row(
+                "SELECT id, timestamp FROM (
+                     SELECT * FROM (
+                         SELECT f.id, f.timestamp FROM frames f
+                         JOIN video_chunks vc ON f.chunk_id = vc.id
+                         WHERE f.timestamp <= ?1 ORDER BY f.timestamp DESC LIMIT 1
+                     )
+                     UNION ALL
+                     SELECT * FROM (
+                         SELECT f.id, f.timestamp FROM frames f
+                         JOIN video_chunks vc ON f.chunk_id = vc.id
+                         WHERE f.timestamp >= ?1 ORDER BY f.timestamp ASC LIMIT 1
+                     )
+                 )
+                 ORDER BY ABS(julianday(timestamp) - julianday(?1)) LIMIT 1",
+                params![timestamp_value(timestamp)],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+    }
+
+    // Method to get the last accessible frame ID
+    pub fn get_last_accessible_frame(&self) -> Result<i64> {
+        self.conn.query_row(
+            "SELECT f.id FROM frames f
+             JOIN video_chunks vc ON f.chunk_id = vc.id
+             ORDER BY f.id DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    // Method to get all unique application names
+    pub fn get_all_application_names(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT active_application_name FROM unique_app_names")?;
+        let app_names = stmt
+            .query_map([], |row| Ok(row.get(0)?))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+        Ok(app_names)
+    }
+}
+
+// Append `AND` conditions for the filters that apply to a single frame `f`, with the
+// frame's OCR result joined as `o`
+fn push_frame_filters(
+    query: &mut String,
+    params: &mut Vec<rusqlite::types::Value>,
+    filters: &SearchFilters,
+) {
+    if let Some(app_name) = &filters.app_name {
+        params.push(app_name.clone().into());
+        query.push_str(&format!(
+            "AND f.active_application_name LIKE ?{} ESCAPE '\\' ",
+            params.len()
+        ));
+    }
+    if let Some(from) = filters.from {
+        params.push(timestamp_value(from));
+        query.push_str(&format!("AND f.timestamp >= ?{} ", params.len()));
+    }
+    if let Some(to) = filters.to {
+        params.push(timestamp_value(to));
+        query.push_str(&format!("AND f.timestamp <= ?{} ", params.len()));
+    }
+    if let (Some(min_confidence), LowConfidence::Ignore) =
+        (filters.min_confidence, filters.low_confidence)
+    {
+        params.push((min_confidence as f64).into());
+        query.push_str(&format!(
+            "AND IFNULL(o.confidence, 100) >= ?{} ",
+            params.len()
+        ));
+    }
+    if let Some((first, last)) = filters.exclude_frames {
+        params.push(first.into());
+        params.push(last.into());
+        query.push_str(&format!(
+            "AND f.id NOT BETWEEN ?{} AND ?{} ",
+            params.len() - 1,
+            params.len()
+        ));
+    }
+    if let Some(up_to_frame) = filters.up_to_frame {
+        params.push(up_to_frame.into());
+        query.push_str(&format!("AND f.id <= ?{} ", params.len()));
+    }
+    for condition in exclude_terms_conditions("f.id", "f.id", params, filters) {
+        query.push_str(&format!("AND {} ", condition));
+    }
+}
+
+// A `NOT EXISTS` for each excluded term, leaving out results with a frame between `first` and
+// `last` that shows it. Keyword and semantic search use the same, so a term excludes the same
+// frames from both.
+fn exclude_terms_conditions(
+    first: &str,
+    last: &str,
+    params: &mut Vec<rusqlite::types::Value>,
+    filters: &SearchFilters,
+) -> Vec<String> {
+    filters
+        .exclude_terms
+        .iter()
+        .map(|term| {
+            params.push(format!("%{}%", escape_like(term)).into());
+            format!(
+                "NOT EXISTS (
+                     SELECT 1 FROM text_spans xs JOIN all_text xa ON xa.rowid = xs.block_id
+                     WHERE xs.last_frame_id >= {} AND xs.first_frame_id <= {}
+                     AND xa.text LIKE ?{} ESCAPE '\\'
+                 )",
+                first,
+                last,
+                params.len()
+            )
+        })
+        .collect()
+}
+
+// The FROM and WHERE of a text search for `terms`, with the FTS5 query if any of them can use
+// the index and the parameters bound
+fn text_search_conditions(
+    terms: &[impl AsRef<str>],
+    filters: &SearchFilters,
+) -> (Option<String>, String, Vec<rusqlite::types::Value>) {
+    let (match_query, short_terms) = text_match_query(terms);
+    let mut conditions = Vec::new();
+    let mut params: Vec<rusqlite::types::Value> = Vec::new();
+
+    if let Some(app_name) = &filters.app_name {
+        params.push(app_name.clone().into());
+        conditions.push(format!(
+            "f.active_application_name LIKE ?{} ESCAPE '\\'",
+            params.len()
+        ));
+    }
+    if let Some(match_query) = &match_query {
+        params.push(match_query.clone().into());
+        conditions.push(format!("all_text.text MATCH ?{}", params.len()));
+    }
+    for term in &short_terms {
+        params.push(format!("%{}%", escape_like(term)).into());
+        conditions.push(format!("all_text.text LIKE ?{} ESCAPE '\\'", params.len()));
+    }
+    conditions.extend(exclude_terms_conditions(
+        "s.first_frame_id",
+        "s.last_frame_id",
+        &mut params,
+        filters,
+    ));
+    // A span matches a time range if any part of it falls within the range
+    if let Some(from) = filters.from {
+        params.push(timestamp_value(from));
+        conditions.push(format!("lf.timestamp >= ?{}", params.len()));
+    }
+    if let Some(to) = filters.to {
+        params.push(timestamp_value(to));
+        conditions.push(format!("f.timestamp <= ?{}", params.len()));
+    }
+    if let (Some(min_confidence), LowConfidence::Ignore) =
+        (filters.min_confidence, filters.low_confidence)
+    {
+        params.push((min_confidence as f64).into());
+        conditions.push(format!("{} >= ?{}", SPAN_CONFIDENCE, params.len()));
+    }
+    if let Some(up_to_frame) = filters.up_to_frame {
+        params.push(up_to_frame.into());
+        conditions.push(format!("s.first_frame_id <= ?{}", params.len()));
+    }
+    if let Some(key) = &filters.older_than {
+        params.push(timestamp_value(key.timestamp));
+        params.push(key.frame_id.into());
+        params.push(key.span_id.into());
+        conditions.push(format!(
+            "(lf.timestamp, s.last_frame_id, s.id) < (?{}, ?{}, ?{})",
+            params.len() - 2,
+            params.len() - 1,
+            params.len()
+        ));
+    }
+
+    let mut query = String::from(
+        "FROM all_text
+         JOIN text_spans s ON s.block_id = all_text.rowid
+         JOIN frames f ON f.id = s.first_frame_id
+         JOIN frames lf ON lf.id = s.last_frame_id
+         JOIN video_chunks vc ON f.chunk_id = vc.id ",
+    );
+    if !conditions.is_empty() {
+        query.push_str(&format!("WHERE {} ", conditions.join(" AND ")));
+    }
+    (match_query, query, params)
+}
+
+// A query for frames that have been written to a video and match the filters, other than
+// `older_than`
+fn recent_frames_query(
+    columns: &str,
+    filters: &SearchFilters,
+) -> (String, Vec<rusqlite::types::Value>) {
+    let mut query = format!(
+        "{} FROM frames f
+         JOIN video_chunks vc
+         LEFT JOIN ocr_results o ON o.frame_id = f.id
+         WHERE vc.id = f.chunk_id ",
+        columns
+    );
+    let mut params = Vec::new();
+    push_frame_filters(&mut query, &mut params, filters);
+    (query, params)
+}
+
+// Turns search terms into an FTS5 query matching all of them, each quoted so punctuation in
+// them (e.g. `db.rs` or `-v`) is searched for rather than read as query syntax. Terms too
+// short for the trigram index are returned separately.
+fn text_match_query(terms: &[impl AsRef<str>]) -> (Option<String>, Vec<String>) {
+    let (terms, short_terms): (Vec<&str>, Vec<&str>) = terms
+        .iter()
+        .map(|term| term.as_ref())
+        .partition(|term| term.chars().count() >= MIN_MATCH_TERM_CHARS);
+    let match_query = terms
+        .iter()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    (
+        (!match_query.is_empty()).then(|| match_query.join(" ")),
+        short_terms.into_iter().map(String::from).collect(),
+    )
+}
+
+// Escapes LIKE's wildcards in `term` so it's matched as is, with `ESCAPE '\'`
+pub fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// Character offsets of the matches in text FTS5's highlight() marked with OFFSET_OPEN and
+// OFFSET_CLOSE, as offsets into the unmarked text
+fn match_offsets(marked: &str) -> Vec<(usize, usize)> {
+    let mut offsets = Vec::new();
+    let mut position = 0;
+    let mut start = 0;
+    for c in marked.chars() {
+        match c {
+            OFFSET_OPEN => start = position,
+            OFFSET_CLOSE => offsets.push((start, position)),
+            _ => position += 1,
+        }
+    }
+    offsets
+}
+
+// Keeps the most similar passage of each frame from scores sorted most similar first
+fn best_per_frame(scored: Vec<(i64, i64, f32)>) -> Vec<(i64, i64, f32)> {
+    let mut seen = HashSet::new();
+    scored
+        .into_iter()
+        .filter(|(_, frame_id, _)| seen.insert(*frame_id))
+        .collect()
+}
+
+// Timestamps as rusqlite stores them, for queries whose parameters are built up dynamically
+fn timestamp_value(timestamp: NaiveDateTime) -> rusqlite::types::Value {
+    rusqlite::types::Value::Text(timestamp.format("%F %T%.f").to_string())
+}
+
+// Rows from before quantization was recorded hold little-endian f32 bytes
+fn parse_quantization(value: String) -> Quantization {
+    Quantization::parse(&value).unwrap_or_default()
+}
+
+// Split OCR text into the blocks it's stored as, paragraphs separated by blank lines
+fn split_text_blocks(text: &str) -> Vec<&str> {
+    text.split("\n\n")
+        .map(|block| block.trim())
+        .filter(|block| !block.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Inserts a frame captured at `timestamp`, in the session it would have been in then
+    fn insert_frame_at(
+        db: &mut DatabaseManager,
+        timestamp: NaiveDateTime,
+        app: Option<&str>,
+        title: Option<&str>,
+    ) -> i64 {
+        db.insert_frame_with_timestamp(app.map(String::from), title.map(String::from), timestamp)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ocr_queue_retries_until_failed() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frame_id = db.insert_frame(None, None).unwrap();
+
+        assert!(db.claim_ocr_job(frame_id).unwrap().is_some());
+        // Already claimed
+        assert!(db.claim_ocr_job(frame_id).unwrap().is_none());
+
+        for attempt in 1..=3 {
+            db.fail_ocr_job(frame_id, "tesseract exploded", 3).unwrap();
+            let job = db.claim_ocr_job(frame_id).unwrap();
+            if attempt < 3 {
+                assert_eq!(attempt, job.unwrap().attempts);
+            } else {
+                assert!(job.is_none());
+            }
+        }
+        assert_eq!(
+            vec![("failed".to_string(), 1)],
+            db.get_ocr_queue_counts().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_text_read_well_in_any_frame_of_its_span_is_confident() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..3)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(frames[0], "quarterly report")
+            .unwrap();
+        db.insert_text_for_frame(frames[1], "quarterly report")
+            .unwrap();
+        db.insert_text_for_frame(frames[2], "blurry notes").unwrap();
+        for (frame, confidence) in [(frames[0], 30.0), (frames[1], 90.0), (frames[2], 30.0)] {
+            db.insert_ocr_result(frame, confidence, &[]).unwrap();
+        }
+
+        let filters = SearchFilters {
+            min_confidence: Some(50.0),
+            ..Default::default()
+        };
+        let found = |db: &DatabaseManager, term: &str, filters: &SearchFilters| {
+            db.search(&[term], 10, 0, filters)
+                .unwrap()
+                .iter()
+                .map(|r| r.frame_id)
+                .collect::<Vec<_>>()
+        };
+        // The span's first frame was blurry but its second wasn't
+        assert_eq!(vec![frames[0]], found(&db, "report", &filters));
+        assert!(found(&db, "notes", &filters).is_empty());
+
+        let filters = SearchFilters {
+            low_confidence: LowConfidence::DownRank,
+            ..filters
+        };
+        let results = db.search(&[] as &[&str], 10, 0, &filters).unwrap();
+        assert_eq!(
+            vec![frames[0], frames[2]],
+            results.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_frames_belong_to_the_chunk_written_after_them() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let first = db.insert_frame(None, None).unwrap();
+        let second = db.insert_frame(None, None).unwrap();
+        assert_ne!(first, second);
+        assert!(db.is_frame_chunk_pending(first).unwrap());
+
+        let chunk_id = db.start_new_video_chunk("output-1.mp4").unwrap();
+        // Not until ffmpeg has finished writing it
+        assert!(db.is_frame_chunk_pending(first).unwrap());
+        db.finish_video_chunk(chunk_id).unwrap();
+        assert!(!db.is_frame_chunk_pending(first).unwrap());
+        assert_eq!(
+            Some((1, "output-1.mp4".to_string())),
+            db.get_frame(second).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_frames_are_found_by_time() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let start = Utc::now().naive_utc();
+        let at = |minutes: i64| start + chrono::Duration::minutes(minutes);
+        let mut frames = vec![];
+        for minutes in [0, 10, 20] {
+            frames.push(insert_frame_at(&mut db, at(minutes), None, None));
+        }
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        // Not in a video yet
+        db.insert_frame(None, None).unwrap();
+
+        assert_eq!(
+            Some((frames[1], at(10))),
+            db.get_frame_near(at(12)).unwrap()
+        );
+        assert_eq!(frames[2], db.get_frame_near(at(16)).unwrap().unwrap().0);
+        assert_eq!(frames[2], db.get_frame_near(at(60)).unwrap().unwrap().0);
+        assert_eq!(frames[0], db.get_frame_near(at(-60)).unwrap().unwrap().0);
+
+        let filters = SearchFilters {
+            from: Some(at(5)),
+            to: Some(at(25)),
+            ..Default::default()
+        };
+        let results = db.get_recent_results(10, 0, &filters).unwrap();
+        assert_eq!(
+            vec![frames[2], frames[1]],
+            results.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_pages_continue_after_the_last_result() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..4)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        for (frame_id, name) in frames.iter().zip(["alpha", "beta", "gamma", "delta"]) {
+            db.insert_text_for_frame(*frame_id, &format!("report {}", name))
+                .unwrap();
+        }
+
+        let filters = SearchFilters::default();
+        let first = db.search(&["report"], 2, 0, &filters).unwrap();
+        assert_eq!(
+            vec![frames[3], frames[2]],
+            first.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+        let recent = db.get_recent_results(2, 0, &filters).unwrap();
+
+        // A frame recorded meanwhile doesn't shift the next page
+        let new_frame = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-2.mp4").unwrap();
+        db.insert_text_for_frame(new_frame, "report epsilon")
+            .unwrap();
+
+        let filters = SearchFilters {
+            older_than: Some(first[1].page_key()),
+            ..Default::default()
+        };
+        let second = db.search(&["report"], 2, 0, &filters).unwrap();
+        assert_eq!(
+            vec![frames[1], frames[0]],
+            second.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+        let filters = SearchFilters {
+            older_than: Some(recent[1].page_key()),
+            ..Default::default()
+        };
+        let second = db.get_recent_results(2, 0, &filters).unwrap();
+        assert_eq!(
+            vec![frames[1], frames[0]],
+            second.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+
+        // Counts ignore the page
+        assert_eq!(
+            ResultCount {
+                count: 5,
+                exact: true
+            },
+            db.count_search(&["report"], &filters).unwrap()
+        );
+        assert_eq!(
+            ResultCount {
+                count: 1,
+                exact: true
+            },
+            db.count_search(&["gamma"], &filters).unwrap()
+        );
+        assert_eq!(
+            ResultCount {
+                count: 5,
+                exact: true
+            },
+            db.count_recent(&filters).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_consecutive_frames_share_text_spans() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..4)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+
+        // Out of order, like the OCR pool finishes them
+        db.insert_text_for_frame(frames[0], "design doc\n\nstatus: draft")
+            .unwrap();
+        db.insert_text_for_frame(frames[2], "design doc\n\nstatus: final")
+            .unwrap();
+        db.insert_text_for_frame(frames[1], "design doc\n\nstatus: draft")
+            .unwrap();
+        db.insert_text_for_frame(frames[3], "design doc").unwrap();
+
+        let results = db
+            .search(&["design"], 10, 0, &SearchFilters::default())
+            .unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!(frames[0], results[0].frame_id);
+        assert_eq!(frames[3], results[0].last_frame_id);
+
+        let results = db
+            .search(&["draft"], 10, 0, &SearchFilters::default())
+            .unwrap();
+        assert_eq!(1, results.len());
+        assert_eq!(frames[1], results[0].last_frame_id);
+
+        assert_eq!(
+            Some("design doc\n\nstatus: final".to_string()),
+            db.get_text_for_frame(frames[2]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_requeue_trims_text_spans() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..3)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        for frame_id in &frames {
+            db.insert_text_for_frame(*frame_id, "same text").unwrap();
+        }
+
+        db.remove_text_for_frames(frames[1], frames[1]).unwrap();
+        let results = db
+            .search(&["same"], 10, 0, &SearchFilters::default())
+            .unwrap();
+        assert_eq!(2, results.len());
+        assert_eq!(None, db.get_text_for_frame(frames[1]).unwrap());
+
+        // Putting it back joins the spans again
+        db.insert_text_for_frame(frames[1], "same text").unwrap();
+        let results = db
+            .search(&["same"], 10, 0, &SearchFilters::default())
+            .unwrap();
+        assert_eq!(1, results.len());
+    }
+
+    #[test]
+    fn test_legacy_text_is_converted_to_blocks() {
+        let path = std::env::temp_dir().join(format!("xrem-legacy-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE VIRTUAL TABLE all_text USING fts4(frame_id INTEGER NOT NULL, text TEXT NOTNULL);
+                 INSERT INTO all_text (frame_id, text) VALUES (1, 'old text'), (2, 'old text');",
+            )
+            .unwrap();
+        }
+
+        let db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            Some("old text".to_string()),
+            db.get_text_for_frame(1).unwrap()
+        );
+        assert!(!db.has_legacy_text_table().unwrap());
+        drop(db);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(format!("{}.v0.bak", path.display()));
+    }
+
+    fn column_names(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_migrations_back_up_and_version_the_schema() {
+        let dir = std::env::temp_dir().join(format!("xrem-migrate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("db.sqlite");
+        let backup = dir.join("db.sqlite.v0.bak");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE frames (
+                     id INTEGER PRIMARY KEY AUTOINCREMENT,
+                     chunk_id INTEGER NOT NULL,
+                     offset_index INTEGER NOT NULL,
+                     timestamp TIMESTAMP NOT NULL,
+                     active_application_name TEXT
+                 );
+                 INSERT INTO frames (chunk_id, offset_index, timestamp)
+                 VALUES (1, 0, '2024-01-01 10:00:00');",
+            )
+            .unwrap();
+        }
+
+        let db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(MIGRATIONS.len() as i64, db.schema_version().unwrap());
+        assert!(column_names(&db.conn, "frames").contains(&"dhash".to_string()));
+        // Existing rows are kept
+        assert_eq!(1, db.get_last_frame_id().unwrap());
+        drop(db);
+        // The backup is the database as it was before migrating
+        let old = Connection::open(&backup).unwrap();
+        assert!(!column_names(&old, "frames").contains(&"dhash".to_string()));
+        drop(old);
+
+        // Up to date databases aren't backed up again
+        std::fs::remove_file(&backup).unwrap();
+        drop(DatabaseManager::new(path.to_str().unwrap()).unwrap());
+        assert!(!backup.exists());
+
+        // A database from a newer version of the app is left alone
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1))
+            .unwrap();
+        let error = DatabaseManager::new(path.to_str().unwrap()).err().unwrap();
+        assert!(error.to_string().contains("newer"), "{}", error);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back() {
+        let db = DatabaseManager::new(":memory:").unwrap();
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push(Migration {
+            description: "Add a table",
+            apply: |db| db.conn.execute_batch("CREATE TABLE scratch (id INTEGER)"),
+        });
+        migrations.push(Migration {
+            description: "Fail half way",
+            apply: |db| {
+                db.conn.execute_batch("CREATE TABLE broken (id INTEGER)")?;
+                db.conn.execute_batch("INSERT INTO missing VALUES (1)")
+            },
+        });
+
+        assert!(db.migrate(&migrations, None).is_err());
+        assert_eq!(MIGRATIONS.len() as i64 + 1, db.schema_version().unwrap());
+        assert!(column_names(&db.conn, "scratch").contains(&"id".to_string()));
+        assert!(column_names(&db.conn, "broken").is_empty());
+    }
+
+    #[test]
+    fn test_frames_missing_embeddings() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let with_text = db.insert_frame(None, None).unwrap();
+        let failed = db.insert_frame(None, None).unwrap();
+        db.insert_frame(None, None).unwrap();
+        db.insert_text_for_frame(with_text, "some text").unwrap();
+        db.insert_text_for_frame(failed, "other text").unwrap();
+        db.insert_embedding_error(failed, "gte-small", "too long")
+            .unwrap();
+
+        assert_eq!(
+            vec![with_text],
+            db.get_frames_missing_embeddings(10).unwrap()
+        );
+        db.insert_embedding(with_text, "gte-small", None, &[0.5, 0.5])
+            .unwrap();
+        assert!(db.get_frames_missing_embeddings(10).unwrap().is_empty());
+
+        // A new model has every frame with text to embed
+        db.start_reindex("bge-small").unwrap().unwrap();
+        assert_eq!(
+            vec![failed, with_text],
+            db.get_frames_missing_embeddings(10).unwrap()
+        );
+        // Switching back only leaves what the first model hadn't seen
+        let new_text = db.insert_frame(None, None).unwrap();
+        db.insert_text_for_frame(new_text, "new text").unwrap();
+        db.start_reindex("gte-small").unwrap();
+        assert_eq!(
+            vec![new_text],
+            db.get_frames_missing_embeddings(10).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_semantic_search_ranks_by_similarity() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let close = db.insert_frame(Some("Firefox".to_string()), None).unwrap();
+        let far = db.insert_frame(Some("Firefox".to_string()), None).unwrap();
+        let other_app = db.insert_frame(Some("Slack".to_string()), None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_embedding(close, "gte-small", None, &[1.0, 0.1])
+            .unwrap();
+        db.insert_embedding(far, "gte-small", None, &[0.0, 1.0])
+            .unwrap();
+        db.insert_embedding(other_app, "gte-small", None, &[1.0, 0.0])
+            .unwrap();
+
+        let results = db
+            .semantic_search(&[1.0, 0.0], "gte-small", 10, 0, &SearchFilters::default())
+            .unwrap();
+        let frame_ids = results
+            .iter()
+            .map(|r| r.result.frame_id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![other_app, close, far], frame_ids);
+
+        let filters = SearchFilters {
+            app_name: Some("Firefox".to_string()),
+            ..Default::default()
+        };
+        let results = db
+            .semantic_search(&[1.0, 0.0], "gte-small", 1, 0, &filters)
+            .unwrap();
+        assert_eq!(close, results[0].result.frame_id);
+        assert!(results[0].similarity > 0.99);
+
+        // Frames showing an excluded word are left out
+        db.insert_text_for_frame(other_app, "general channel")
+            .unwrap();
+        let filters = SearchFilters {
+            exclude_terms: vec!["channel".to_string()],
+            ..Default::default()
+        };
+        let results = db
+            .semantic_search(&[1.0, 0.0], "gte-small", 10, 0, &filters)
+            .unwrap();
+        let frame_ids = results
+            .iter()
+            .map(|r| r.result.frame_id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![close, far], frame_ids);
+    }
+
+    #[test]
+    fn test_excluded_terms_and_app_names_match_as_typed() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let with_slack = db.insert_frame(Some("my_app".to_string()), None).unwrap();
+        db.insert_frame(None, None).unwrap();
+        let without = db.insert_frame(Some("myXapp".to_string()), None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(with_slack, "quarterly report\n\nslack")
+            .unwrap();
+        db.insert_text_for_frame(without, "quarterly report")
+            .unwrap();
+
+        // A frame showing an excluded word is left out, even where another block matched
+        let filters = SearchFilters {
+            exclude_terms: vec!["slack".to_string()],
+            ..Default::default()
+        };
+        let results = db.search(&["quarterly"], 10, 0, &filters).unwrap();
+        assert_eq!(
+            vec![without],
+            results.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+
+        // _ in an app name isn't a wildcard
+        let filters = SearchFilters {
+            app_name: Some(format!("%{}%", escape_like("my_app"))),
+            ..Default::default()
+        };
+        let results = db.search(&["quarterly"], 10, 0, &filters).unwrap();
+        assert_eq!(
+            vec![with_slack],
+            results.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_hybrid_search_fuses_keyword_and_semantic_hits() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..3)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(frames[0], "error E1234 in build")
+            .unwrap();
+        db.insert_text_for_frame(frames[1], "error E1234 in build")
+            .unwrap();
+        db.insert_text_for_frame(frames[2], "compilation failed")
+            .unwrap();
+        db.insert_embedding(frames[1], "gte-small", None, &[1.0, 0.0])
+            .unwrap();
+        db.insert_embedding(frames[2], "gte-small", None, &[0.9, 0.1])
+            .unwrap();
+
+        let results = db
+            .hybrid_search(
+                &["E1234"],
+                Some((&[1.0, 0.0], "gte-small")),
+                10,
+                0,
+                &SearchFilters::default(),
+                &HybridWeights::default(),
+            )
+            .unwrap();
+        assert_eq!(2, results.len());
+        // The keyword span and the closest vector are the same hit
+        assert_eq!(frames[0], results[0].result.frame_id);
+        assert_eq!(Some(1), results[0].keyword_rank);
+        assert_eq!(Some(1), results[0].semantic_rank);
+        assert_eq!(frames[2], results[1].result.frame_id);
+        assert_eq!(None, results[1].keyword_rank);
+        assert!(results[0].score > results[1].score);
+    }
+
+    #[test]
+    fn test_hybrid_search_ranks_keyword_hits_by_relevance() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let older = db.insert_frame(None, None).unwrap();
+        let newer = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(older, "deploy deploy deploy the release")
+            .unwrap();
+        db.insert_text_for_frame(
+            newer,
+            "a note that mentions deploy once among many other words",
+        )
+        .unwrap();
+
+        let filters = SearchFilters {
+            order: SearchOrder::Recent,
+            ..Default::default()
+        };
+        let results = db
+            .hybrid_search(
+                &["deploy"],
+                None,
+                10,
+                0,
+                &filters,
+                &HybridWeights::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            vec![older, newer],
+            results
+                .iter()
+                .map(|r| r.result.frame_id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(1), results[0].keyword_rank);
+
+        // A better hit recorded after the first page doesn't shift later pages
+        let later = db.insert_frame(None, None).unwrap();
+        db.insert_text_for_frame(later, "deploy deploy deploy deploy")
+            .unwrap();
+        let filters = SearchFilters {
+            up_to_frame: Some(newer),
+            ..filters
+        };
+        let results = db
+            .hybrid_search(&["deploy"], None, 1, 1, &filters, &HybridWeights::default())
+            .unwrap();
+        assert_eq!(newer, results[0].result.frame_id);
+    }
+
+    #[test]
+    fn test_text_search_ranks_and_highlights_matches() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..4)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(frames[0], "deploy deploy deploy the release")
+            .unwrap();
+        db.insert_text_for_frame(
+            frames[1],
+            "a note that mentions deploy once among many other words",
+        )
+        .unwrap();
+        db.insert_text_for_frame(frames[2], "let cfg = parseConfigFile(path); // 東京の天気")
+            .unwrap();
+        db.insert_text_for_frame(frames[3], "open db.rs in vim")
+            .unwrap();
+
+        let search = |terms: &[&str], order: SearchOrder| {
+            let filters = SearchFilters {
+                order,
+                ..Default::default()
+            };
+            db.search(terms, 10, 0, &filters).unwrap()
+        };
+
+        // Newest first by default, best match first when asked
+        let recent = search(&["deploy"], SearchOrder::Recent);
+        assert_eq!(
+            vec![frames[1], frames[0]],
+            recent.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+        let relevant = search(&["deploy"], SearchOrder::Relevance);
+        assert_eq!(
+            vec![frames[0], frames[1]],
+            relevant.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+
+        let result = &relevant[1];
+        assert_eq!(vec![(21, 27)], result.match_offsets);
+        assert_eq!(
+            Some("a note that mentions <mark>deploy</mark> once among many other words"),
+            result.highlight.as_deref()
+        );
+        assert!(result
+            .snippet
+            .as_ref()
+            .unwrap()
+            .contains("<mark>deploy</mark>"));
+
+        // Inside identifiers and text without spaces, with offsets in characters
+        let config = search(&["ConfigFile"], SearchOrder::Recent);
+        assert_eq!(vec![(15, 25)], config[0].match_offsets);
+        let tokyo = search(&["東京の"], SearchOrder::Recent);
+        assert_eq!(frames[2], tokyo[0].frame_id);
+        assert_eq!(vec![(36, 39)], tokyo[0].match_offsets);
+
+        // Punctuation is searched for, and words too short for the index still match
+        assert_eq!(
+            frames[3],
+            search(&["db.rs"], SearchOrder::Recent)[0].frame_id
+        );
+        assert_eq!(
+            frames[3],
+            search(&["db", "vim"], SearchOrder::Recent)[0].frame_id
+        );
+        assert!(search(&["\"quoted\"", "OR"], SearchOrder::Recent).is_empty());
+
+        // Phrases match as a whole, and excluded words and phrases leave results out
+        assert_eq!(
+            1,
+            search(&["deploy the release"], SearchOrder::Recent).len()
+        );
+        assert!(search(&["deploy release"], SearchOrder::Recent).is_empty());
+        let filters = SearchFilters {
+            exclude_terms: vec!["other words".to_string()],
+            ..Default::default()
+        };
+        let results = db.search(&["deploy"], 10, 0, &filters).unwrap();
+        assert_eq!(
+            vec![frames[0]],
+            results.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_vector_index_catches_up_after_restart() {
+        let dir = std::env::temp_dir().join(format!("xrem-ann-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("db.sqlite");
+
+        let (kept, forgotten, late) = {
+            let mut db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+            db.ann_min_embeddings = 0;
+            let kept = db.insert_frame(None, None).unwrap();
+            let forgotten = db.insert_frame(None, None).unwrap();
+            let late = db.insert_frame(None, None).unwrap();
+            db.start_new_video_chunk("output-1.mp4").unwrap();
+            db.insert_embedding(kept, "gte-small", None, &[1.0, 0.0])
+                .unwrap();
+            db.insert_embedding(forgotten, "gte-small", None, &[0.9, 0.1])
+                .unwrap();
+
+            let results = db
+                .semantic_search(&[1.0, 0.0], "gte-small", 10, 0, &SearchFilters::default())
+                .unwrap();
+            assert_eq!(2, results.len());
+            db.save_vector_indexes(Duration::ZERO).unwrap();
+            assert!(dir.join("embeddings-gte-small.hnsw").exists());
+
+            // Changes after the last save, lost from the index file as if the app had crashed
+            db.insert_embedding(late, "gte-small", None, &[0.0, 1.0])
+                .unwrap();
+            let timestamp: NaiveDateTime = db
+                .conn
+                .query_row(
+                    "SELECT timestamp FROM frames WHERE id = ?1",
+                    params![forgotten],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(1, db.forget_range(timestamp, timestamp).unwrap());
+            (kept, forgotten, late)
+        };
+
+        let mut db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+        db.ann_min_embeddings = 0;
+        let results = db
+            .semantic_search(&[1.0, 0.0], "gte-small", 10, 0, &SearchFilters::default())
+            .unwrap();
+        let frame_ids = results
+            .iter()
+            .map(|r| r.result.frame_id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![kept, late], frame_ids);
+        assert!(!frame_ids.contains(&forgotten));
+        assert_eq!(2, db.rebuild_vector_index("gte-small", 2).unwrap());
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_semantic_search_returns_matching_passage() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frame = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(frame, "Inbox\n\nQuarterly report")
+            .unwrap();
+        db.insert_embedding(frame, "gte-small", Some((0, 5)), &[0.0, 1.0])
+            .unwrap();
+        db.insert_embedding(frame, "gte-small", Some((7, 23)), &[1.0, 0.0])
+            .unwrap();
+
+        let results = db
+            .semantic_search(&[1.0, 0.0], "gte-small", 10, 0, &SearchFilters::default())
+            .unwrap();
+        assert_eq!(1, results.len());
+        let passage = results[0].passage.as_ref().unwrap();
+        assert_eq!("Quarterly report", passage.text);
+        assert_eq!((7, 23), (passage.start, passage.end));
+    }
+
+    #[test]
+    fn test_reindex_job_replaces_previous_model_vectors() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let first = db.insert_frame(None, None).unwrap();
+        db.insert_text_for_frame(first, "Inbox").unwrap();
+        let second = db.insert_frame(None, None).unwrap();
+        db.insert_text_for_frame(second, "Quarterly report")
+            .unwrap();
+        db.insert_embedding(first, "gte-small", None, &[1.0, 0.0])
+            .unwrap();
+        db.insert_embedding(second, "gte-small", None, &[0.0, 1.0])
+            .unwrap();
+
+        // Nothing to re-embed for the model the vectors came from
+        assert_eq!(None, db.start_reindex("gte-small").unwrap());
+
+        let job = db.start_reindex("bge-small-en-v1.5").unwrap().unwrap();
+        assert_eq!(Some("gte-small".to_string()), job.previous_model_id);
+        // Restarting resumes the same job
+        assert_eq!(
+            Some(job.clone()),
+            db.start_reindex("bge-small-en-v1.5").unwrap()
+        );
+
+        db.insert_embedding(second, "bge-small-en-v1.5", None, &[0.0, 1.0, 0.0])
+            .unwrap();
+        assert_eq!(
+            ReindexProgress {
+                total: 2,
+                remaining: 1
+            },
+            db.get_reindex_progress("bge-small-en-v1.5").unwrap()
+        );
+
+        // Switching model again keeps searching the last complete set of vectors
+        let switched = db.start_reindex("gte-base").unwrap().unwrap();
+        assert_eq!(Some("gte-small".to_string()), switched.previous_model_id);
+        assert_eq!(Some(switched), db.get_reindex_job().unwrap());
+
+        db.insert_embedding(first, "gte-base", None, &[1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        db.insert_embedding_error(second, "gte-base", "No text to embed")
+            .unwrap();
+        assert_eq!(0, db.get_reindex_progress("gte-base").unwrap().remaining);
+        db.finish_reindex("gte-base").unwrap();
+
+        assert_eq!(None, db.get_reindex_job().unwrap());
+        let models: Vec<String> = db
+            .conn
+            .prepare("SELECT DISTINCT model_id FROM frame_embeddings")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec!["gte-base".to_string()], models);
+    }
+
+    #[test]
+    fn test_image_embeddings_are_searchable_and_outlive_reindex() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let chart = db.insert_frame(None, None).unwrap();
+        let photo = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_embedding(chart, "gte-small", None, &[1.0, 0.0])
+            .unwrap();
+        db.insert_image_embedding(chart, "clip", &[0.0, 1.0, 0.0])
+            .unwrap();
+        db.insert_image_embedding(photo, "clip", &[1.0, 0.0, 0.0])
+            .unwrap();
+
+        let results = db
+            .semantic_search(&[0.1, 1.0, 0.0], "clip", 10, 0, &SearchFilters::default())
+            .unwrap();
+        let frame_ids = results
+            .iter()
+            .map(|r| r.result.frame_id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![chart, photo], frame_ids);
+        assert!(results[0].passage.is_none());
+
+        // Image vectors aren't what a new text model replaces
+        let job = db.start_reindex("gte-base").unwrap().unwrap();
+        assert_eq!(Some("gte-small".to_string()), job.previous_model_id);
+        db.insert_embedding(chart, "gte-base", None, &[1.0, 0.0, 0.0])
+            .unwrap();
+        db.finish_reindex("gte-base").unwrap();
+        let results = db
+            .semantic_search(&[0.1, 1.0, 0.0], "clip", 10, 0, &SearchFilters::default())
+            .unwrap();
+        assert_eq!(2, results.len());
+    }
+
+    #[test]
+    fn test_image_queue_waits_for_the_chunk_and_backfills() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let first = db.insert_frame(None, None).unwrap();
+        let second = db.insert_frame(None, None).unwrap();
+        let chunk_id = db.start_new_video_chunk("output-1.mp4").unwrap();
+
+        // Frames can't be decoded until their chunk is written
+        assert!(db
+            .get_frames_missing_image_embeddings(10)
+            .unwrap()
+            .is_empty());
+        db.finish_video_chunk(chunk_id).unwrap();
+        assert_eq!(
+            vec![second, first],
+            db.get_frames_missing_image_embeddings(10).unwrap()
+        );
+
+        db.insert_image_embedding(second, "clip", &[1.0, 0.0])
+            .unwrap();
+        db.skip_image_embedding(first).unwrap();
+        assert!(db
+            .get_frames_missing_image_embeddings(10)
+            .unwrap()
+            .is_empty());
+
+        // The same model only has what it skipped to embed again, a new one every frame
+        assert_eq!(1, db.queue_missing_image_embeddings("clip").unwrap());
+        assert_eq!(
+            vec![first],
+            db.get_frames_missing_image_embeddings(10).unwrap()
+        );
+        db.queue_missing_image_embeddings("siglip").unwrap();
+        assert_eq!(
+            vec![second, first],
+            db.get_frames_missing_image_embeddings(10).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_requeued_ocr_keeps_image_embeddings() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frame = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        db.insert_text_for_frame(frame, "Quarterly report").unwrap();
+        db.insert_embedding(frame, "gte-small", None, &[1.0, 0.0])
+            .unwrap();
+        db.insert_image_embedding(frame, "clip", &[0.0, 1.0])
+            .unwrap();
+
+        let timestamp = db.get_frame_result(frame).unwrap().unwrap().timestamp;
+        assert_eq!(1, db.requeue_ocr_range(timestamp, timestamp).unwrap());
+        let modalities: Vec<String> = db
+            .conn
+            .prepare("SELECT modality FROM frame_embeddings WHERE frame_id = ?1")
+            .unwrap()
+            .query_map(params![frame], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(vec!["image".to_string()], modalities);
+    }
+
+    #[test]
+    fn test_frames_are_grouped_into_sessions() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let start = Utc::now().naive_utc() - chrono::Duration::days(1);
+        let mut frames = vec![];
+        for (minutes, app, title) in [
+            (0, Some("Firefox"), Some("Docs")),
+            (1, Some("Firefox"), Some("Docs")),
+            (2, Some("Firefox"), Some("Mail")),
+            (3, Some("Slack"), None),
+            (4, Some("Firefox"), Some("Mail")),
+            (20, Some("Firefox"), Some("Mail")),
+        ] {
+            let timestamp = start + chrono::Duration::minutes(minutes);
+            frames.push(insert_frame_at(&mut db, timestamp, app, title));
+        }
+        let bounds = |db: &DatabaseManager| {
+            db.get_sessions(None, None)
+                .unwrap()
+                .iter()
+                .map(|s| (s.first_frame_id, s.last_frame_id, s.frame_count))
+                .collect::<Vec<_>>()
+        };
+        // Switching window title or application, or a gap in capture, starts a new session
+        assert_eq!(
+            vec![
+                (frames[0], frames[1], 2),
+                (frames[2], frames[2], 1),
+                (frames[3], frames[3], 1),
+                (frames[4], frames[4], 1),
+                (frames[5], frames[5], 1),
+            ],
+            bounds(&db)
+        );
+        // Rebuilding them from the frames gives the same sessions
+        let live = bounds(&db);
+        db.conn.execute("DELETE FROM sessions", []).unwrap();
+        db.sessionize_frames(1, i64::MAX).unwrap();
+        assert_eq!(live, bounds(&db));
+
+        // New frames extend the last session or start one as they arrive
+        let now = db
+            .insert_frame(Some("Firefox".to_string()), Some("Mail".to_string()))
+            .unwrap();
+        let next = db
+            .insert_frame(Some("Firefox".to_string()), Some("Mail".to_string()))
+            .unwrap();
+        let sessions = db.get_sessions(None, None).unwrap();
+        assert_eq!(6, sessions.len());
+        assert_eq!((now, next, 2), bounds(&db)[5]);
+        assert_eq!(Some("Mail"), sessions[5].window_title.as_deref());
+
+        // Sessions overlapping the range
+        let sessions = db
+            .get_sessions(
+                Some(start + chrono::Duration::minutes(2)),
+                Some(start + chrono::Duration::minutes(3)),
+            )
+            .unwrap();
+        assert_eq!(
+            vec![Some("Firefox"), Some("Slack")],
+            sessions
+                .iter()
+                .map(|s| s.application_name.as_deref())
+                .collect::<Vec<_>>()
+        );
+
+        // Forgetting the Slack frame joins the Mail sessions either side of it
+        let minute = start + chrono::Duration::minutes(3);
+        assert_eq!(1, db.forget_range(minute, minute).unwrap());
+        assert_eq!(
+            vec![
+                (frames[0], frames[1], 2),
+                (frames[2], frames[4], 2),
+                (frames[5], frames[5], 1),
+                (now, next, 2),
+            ],
+            bounds(&db)
+        );
+    }
+
+    #[test]
+    fn test_usage_leaves_out_pauses() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        // Monday 2026-10-12
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 12).unwrap();
+        let tuesday = monday.succ_opt().unwrap();
+        // Each frame counts the time until the next one, or one capture interval (2s) before a
+        // pause and for the last frame
+        for (time, app, title) in [
+            (monday.and_hms_opt(8, 0, 0), "Firefox", "Docs"),
+            (monday.and_hms_opt(8, 0, 3), "Firefox", "Docs"),
+            (monday.and_hms_opt(8, 0, 5), "Slack", "general"),
+            // Paused for a minute after this one, and for an hour after the next
+            (monday.and_hms_opt(8, 0, 7), "Slack", "general"),
+            (monday.and_hms_opt(8, 1, 7), "Slack", "general"),
+            (monday.and_hms_opt(9, 3, 0), "Firefox", "Mail"),
+            (monday.and_hms_opt(9, 3, 5), "Firefox", "Mail"),
+            (tuesday.and_hms_opt(10, 0, 0), "Firefox", "Docs"),
+        ] {
+            insert_frame_at(&mut db, time.unwrap(), Some(app), Some(title));
+        }
+        let summary = |usage: Vec<AppUsage>| {
+            usage
+                .into_iter()
+                .map(|u| (u.period, u.application_name, u.seconds, u.frame_count))
+                .collect::<Vec<_>>()
+        };
+        let day = |day: u32| NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+
+        assert_eq!(
+            vec![
+                (day(12), Some("Firefox".to_string()), 12.0, 4),
+                (day(12), Some("Slack".to_string()), 6.0, 3),
+                (day(13), Some("Firefox".to_string()), 2.0, 1),
+            ],
+            summary(db.get_app_usage(UsagePeriod::Day, None, None, 0).unwrap())
+        );
+        // Nine hours behind UTC the first frames are on Sunday, the week before
+        assert_eq!(
+            vec![
+                (day(5), Some("Slack".to_string()), 6.0, 3),
+                (day(5), Some("Firefox".to_string()), 5.0, 2),
+                (day(12), Some("Firefox".to_string()), 9.0, 3),
+            ],
+            summary(
+                db.get_app_usage(UsagePeriod::Week, None, None, -9 * 3600)
+                    .unwrap()
+            )
+        );
+        // Frames in range still count the time until the next one after it
+        let to = monday.and_hms_opt(8, 0, 3);
+        assert_eq!(
+            vec![(day(12), Some("Firefox".to_string()), 5.0, 2)],
+            summary(db.get_app_usage(UsagePeriod::Day, None, to, 0).unwrap())
+        );
+
+        let titles = db.get_top_window_titles(None, None, 2).unwrap();
+        assert_eq!(
+            vec![("Docs", 7.0, 3), ("Mail", 7.0, 2)],
+            titles
+                .iter()
+                .map(|t| (t.window_title.as_str(), t.seconds, t.frame_count))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![(0, 8, 11.0), (0, 9, 7.0), (1, 10, 2.0)],
+            db.get_hourly_usage(None, None, 0)
+                .unwrap()
+                .iter()
+                .map(|h| (h.weekday, h.hour, h.seconds))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_related_frames_come_from_other_sessions() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let start = Utc::now().naive_utc();
+        // Looks the same as the first frame, but was seen too long ago to be compared
+        let long_ago = insert_frame_at(&mut db, start - chrono::Duration::days(60), None, None);
+        // Two sessions of frames a minute apart, then a frame on its own hours later
+        let frames = [0, 1, 2, 60, 61, 180]
+            .into_iter()
+            .map(|minutes| {
+                insert_frame_at(
+                    &mut db,
+                    start + chrono::Duration::minutes(minutes),
+                    None,
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+
+        assert_eq!(
+            Some((frames[0], frames[2])),
+            db.get_session_bounds(frames[1]).unwrap()
+        );
+        assert_eq!(
+            Some((frames[3], frames[4])),
+            db.get_session_bounds(frames[4]).unwrap()
+        );
+        assert_eq!(
+            Some((frames[5], frames[5])),
+            db.get_session_bounds(frames[5]).unwrap()
+        );
+        assert_eq!(None, db.get_session_bounds(frames[5] + 1).unwrap());
+
+        db.insert_embedding(frames[0], "gte-small", Some((0, 10)), &[1.0, 0.0])
+            .unwrap();
+        db.insert_embedding(frames[0], "gte-small", Some((10, 20)), &[0.8, 0.2])
+            .unwrap();
+        db.insert_embedding(frames[1], "gte-small", None, &[1.0, 0.0])
+            .unwrap();
+        db.insert_embedding(frames[3], "gte-small", None, &[0.9, 0.1])
+            .unwrap();
+        db.insert_embedding(frames[4], "gte-small", None, &[0.0, 1.0])
+            .unwrap();
+        db.set_frame_dhash(frames[0], 0xffff).unwrap();
+        db.set_frame_dhash(frames[2], 0xffff).unwrap();
+        db.set_frame_dhash(frames[4], 0xffff_0000).unwrap();
+        // Looks the same but has no text
+        db.set_frame_dhash(frames[5], 0xfffe).unwrap();
+        db.set_frame_dhash(long_ago, 0xffff).unwrap();
+
+        // Text ranks count for more than hash ranks, and a hash too far off doesn't count
+        let related = db.related_frames(frames[0], Some("gte-small"), 10).unwrap();
+        let frame_ids = related
+            .iter()
+            .map(|r| r.result.frame_id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![frames[3], frames[4], frames[5]], frame_ids);
+        assert_eq!(None, related[0].hash_distance);
+        assert_eq!(Some(32), related[1].hash_distance);
+        assert_eq!(
+            (None, Some(1)),
+            (related[2].similarity, related[2].hash_distance)
+        );
+
+        // Without a model only the hash is compared
+        let related = db.related_frames(frames[0], None, 10).unwrap();
+        assert_eq!(
+            vec![frames[5]],
+            related
+                .iter()
+                .map(|r| r.result.frame_id)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_topic_runs_store_clusters_with_time_spans() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let start = Utc::now().naive_utc();
+        let mut frames = vec![];
+        for (minutes, vector) in [
+            (0, [1.0, 0.0]),
+            (1, [0.0, 1.0]),
+            (2, [1.0, 0.1]),
+            (30, [0.9, 0.0]),
+        ] {
+            let frame_id = insert_frame_at(
+                &mut db,
+                start + chrono::Duration::minutes(minutes),
+                None,
+                None,
+            );
+            db.insert_embedding(frame_id, "gte-small", Some((0, 4)), &vector)
+                .unwrap();
+            frames.push(frame_id);
+        }
+        db.insert_embedding(frames[0], "gte-small", Some((4, 8)), &[0.8, 0.2])
+            .unwrap();
+
+        let end = start + chrono::Duration::hours(1);
+        let run = db.start_topic_run(start, end, "gte-small").unwrap();
+        let vectors = db.get_frame_vectors("gte-small", start, end).unwrap();
+        assert_eq!(4, vectors.len());
+        // A frame's passages are averaged
+        assert_eq!((frames[0], vec![0.9, 0.1]), vectors[0]);
+
+        let clusters = vec![
+            TopicCluster {
+                keywords: vec!["invoice".to_string(), "audit".to_string()],
+                representative_frame_id: frames[0],
+                frame_ids: vec![frames[0], frames[2], frames[3]],
+            },
+            TopicCluster {
+                keywords: vec!["pods".to_string()],
+                representative_frame_id: frames[1],
+                frame_ids: vec![frames[1]],
+            },
+        ];
+        db.save_topics(run.id, &clusters).unwrap();
+        assert_eq!(
+            JobStatus::Done,
+            db.get_topic_run(start, end).unwrap().unwrap().status
+        );
+        // A run over a longer range isn't one over part of it
+        assert_eq!(
+            None,
+            db.get_topic_run(start + chrono::Duration::minutes(1), end)
+                .unwrap()
+        );
+
+        let topics = db.get_topics(run.id).unwrap();
+        assert_eq!(vec!["invoice", "audit"], topics[0].keywords);
+        assert_eq!(3, topics[0].frame_count);
+        // The frame half an hour later is a stretch of its own
+        assert_eq!(
+            vec![
+                (start, start + chrono::Duration::minutes(2)),
+                (
+                    start + chrono::Duration::minutes(30),
+                    start + chrono::Duration::minutes(30)
+                )
+            ],
+            topics[0].spans
+        );
+        assert_eq!(frames[1], topics[1].representative_frame_id);
+
+        db.forget_range(start, start + chrono::Duration::minutes(1))
+            .unwrap();
+        assert_eq!(None, db.get_topic_run(start, end).unwrap());
+        assert!(db.get_topics(run.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_topic_runs_left_running_fail_on_restart() {
+        let dir = std::env::temp_dir().join(format!("xrem-topics-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("db.sqlite");
+        let start = Utc::now().naive_utc();
+        let end = start + chrono::Duration::hours(1);
+
+        let db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+        db.start_topic_run(start, end, "gte-small").unwrap();
+        drop(db);
+
+        let db = DatabaseManager::new(path.to_str().unwrap()).unwrap();
+        let run = db.get_topic_run(start, end).unwrap().unwrap();
+        assert_eq!(JobStatus::Failed, run.status);
+        assert!(run.error.is_some());
+
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_quantized_embeddings_are_rescored_and_converted() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frames = (0..3)
+            .map(|_| db.insert_frame(None, None).unwrap())
+            .collect::<Vec<_>>();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        let vectors = [
+            [0.9, 0.1, 0.3, -0.2],
+            [0.8, 0.3, 0.1, -0.3],
+            [-0.5, 0.5, 0.2, 0.9],
+        ];
+        for (frame, vector) in frames.iter().zip(&vectors).take(2) {
+            db.insert_embedding(*frame, "gte-small", None, vector)
+                .unwrap();
+        }
+        db.set_quantization(Quantization::Binary);
+        db.insert_embedding(frames[2], "gte-small", None, &vectors[2])
+            .unwrap();
+
+        // The first two only differ at full precision, which the ranking keeps
+        let query = [0.9, 0.2, 0.3, -0.2];
+        let ranked = |db: &DatabaseManager| {
+            db.semantic_search(&query, "gte-small", 10, 0, &SearchFilters::default())
+                .unwrap()
+                .iter()
+                .map(|r| r.result.frame_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frames, ranked(&db));
+        // Index hits are rescored the same way
+        db.ann_min_embeddings = 0;
+        assert_eq!(frames, ranked(&db));
+        db.ann_min_embeddings = ANN_MIN_EMBEDDINGS;
+
+        db.set_quantization(Quantization::Int8);
+        assert_eq!(3, db.requantize_embeddings(10).unwrap());
+        assert_eq!(0, db.requantize_embeddings(10).unwrap());
+        assert_eq!(frames, ranked(&db));
+
+        let storage = db.get_embedding_storage().unwrap();
+        assert_eq!(1, storage.len());
+        assert_eq!(3, storage[0].embeddings);
+        // Every vector keeps its float32 copy, the third's from when it was stored as binary
+        assert_eq!(3 * (4 + 4 + 16), storage[0].stored_bytes);
+        assert!(storage[0]
+            .estimated_bytes
+            .contains(&(Quantization::Float32, 3 * 16)));
+        assert!(storage[0]
+            .estimated_bytes
+            .contains(&(Quantization::Binary, 3 * (1 + 16))));
+
+        // Going back to float32 restores the vectors exactly and drops the copies
+        db.set_quantization(Quantization::Float32);
+        assert_eq!(3, db.requantize_embeddings(10).unwrap());
+        let storage = db.get_embedding_storage().unwrap();
+        assert_eq!(3 * 16, storage[0].stored_bytes);
+        let stored = db
+            .get_mean_embedding(frames[2], "gte-small")
+            .unwrap()
+            .unwrap();
+        assert_eq!(vectors[2].to_vec(), stored);
+    }
+
+    #[test]
+    fn test_memory_is_only_changed_once_deletes_commit() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        let frame = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        let timestamp = db.get_frame_result(frame).unwrap().unwrap().timestamp;
+        let hashes = [embed_cache::text_hash("Quarterly report")];
+        db.embedding_cache()
+            .insert("gte-small", &hashes[0], &[0.5, -1.0]);
+
+        // A write that's rolled back leaves the cache alone
+        let kept = db
+            .in_transaction(|db| {
+                db.in_nested_transaction(|db| {
+                    db.forget_range(timestamp, timestamp).unwrap();
+                    false
+                })
+            })
+            .unwrap()
+            .unwrap();
+        assert!(!kept);
+        assert_eq!(1, db.embedding_cache().get_many("gte-small", &hashes).len());
+
+        db.in_transaction(|db| {
+            db.forget_range(timestamp, timestamp).unwrap();
+            assert_eq!(1, db.embedding_cache().get_many("gte-small", &hashes).len());
+        })
+        .unwrap();
+        assert!(db
+            .embedding_cache()
+            .get_many("gte-small", &hashes)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_embedding_cache_returns_vectors_by_text_hash() {
+        let mut db = DatabaseManager::new(":memory:").unwrap();
+        db.set_quantization(Quantization::Int8);
+        let seen = embed_cache::text_hash("Quarterly report");
+        let unseen = embed_cache::text_hash("Inbox");
+        db.insert_cached_embedding("gte-small", &seen, &[0.5, -1.0])
+            .unwrap();
+
+        let cached = db
+            .get_cached_embeddings("gte-small", &[seen.clone(), unseen])
+            .unwrap();
+        assert_eq!(1, cached.len());
+        let vector = &cached[&seen];
+        assert!((vector[0] - 0.5).abs() < 0.01 && (vector[1] + 1.0).abs() < 0.01);
+        assert!(db
+            .get_cached_embeddings("bge-small-en-v1.5", &[seen])
+            .unwrap()
+            .is_empty());
+
+        // Forgetting frames drops the cache, it isn't known which text it came from
+        let frame = db.insert_frame(None, None).unwrap();
+        db.start_new_video_chunk("output-1.mp4").unwrap();
+        let timestamp = db.get_frame_result(frame).unwrap().unwrap().timestamp;
+        db.forget_range(timestamp, timestamp).unwrap();
+        assert_eq!(0, db.get_embedding_cache_count().unwrap());
     }
 }
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+use super::db::DatabaseManager;
+use super::vector::Quantization;
+
+// Read-only connections for queries, each serves one at a time
+const READER_COUNT: usize = 4;
+// Writes queued while a batch runs go in the next one, up to this many
+const WRITE_BATCH_SIZE: usize = 64;
+
+// A write run on the writer thread. It returns what to tell its caller once its batch is
+// committed or not, or None if it failed and its writes are rolled back (its caller has been
+// told why).
+type WriteJob = Box<dyn FnOnce(&mut DatabaseManager) -> Option<Reply> + Send>;
+type Reply = Box<dyn FnOnce(Result<(), String>) + Send>;
+
+// Why a write didn't happen
+#[derive(Debug)]
+pub enum WriteError {
+    // The database hasn't been opened yet
+    NotOpen,
+    // The write returned an error, and its changes were rolled back
+    Failed(rusqlite::Error),
+    // The write panicked or never ran, none of its changes were kept
+    Aborted,
+    // The write went through but the batch it was in couldn't be committed
+    CommitFailed(String),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WriteError::NotOpen => write!(f, "Database not open"),
+            WriteError::Failed(e) => write!(f, "{}", e),
+            WriteError::Aborted => write!(f, "Database write aborted"),
+            WriteError::CommitFailed(e) => write!(f, "Failed to commit writes: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+// The database as shared by capture, OCR, the embedding worker and the server. Writes go to
+// one connection on its own thread, which commits everything queued as one transaction, and
+// queries run on a pool of read-only connections, so in WAL mode a slow search doesn't hold
+// up capture and the reverse. Before `open` there's no database, reads return None and writes
+// `WriteError::NotOpen`.
+#[derive(Clone, Default)]
+pub struct DbPool {
+    inner: Arc<OnceLock<Pool>>,
+}
+
+struct Pool {
+    writer: Mutex<Sender<WriteJob>>,
+    readers: Mutex<Vec<DatabaseManager>>,
+    reader_returned: Condvar,
+}
+
+impl DbPool {
+    pub fn open(&self, database_path: &str, quantization: Quantization) -> rusqlite::Result<()> {
+        let mut db = DatabaseManager::new(database_path)?;
+        db.set_quantization(quantization);
+        let readers = (0..READER_COUNT)
+            .map(|_| db.open_reader())
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let (writer, jobs) = mpsc::channel();
+        let pool = Pool {
+            writer: Mutex::new(writer),
+            readers: Mutex::new(readers),
+            reader_returned: Condvar::new(),
+        };
+        if self.inner.set(pool).is_err() {
+            panic!("Database opened twice");
+        }
+        thread::spawn(move || run_writer(db, jobs));
+        Ok(())
+    }
+
+    // Runs `f` on a read-only connection, waiting for one to be free
+    pub fn read<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&DatabaseManager) -> R,
+    {
+        let pool = self.inner.get()?;
+        let reader = {
+            let mut readers = pool.readers.lock().unwrap();
+            loop {
+                match readers.pop() {
+                    Some(reader) => break reader,
+                    None => readers = pool.reader_returned.wait(readers).unwrap(),
+                }
+            }
+        };
+        // Put back even if `f` panics
+        let reader = CheckedOut {
+            pool,
+            reader: Some(reader),
+        };
+        Some(f(reader.reader.as_ref().unwrap()))
+    }
+
+    // Runs `f` on the writer thread and waits until it's committed. If `f` fails or panics
+    // only its own writes are rolled back, the rest of its batch is still committed.
+    pub fn write<F, R>(&self, f: F) -> Result<R, WriteError>
+    where
+        F: FnOnce(&mut DatabaseManager) -> rusqlite::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let pool = self.inner.get().ok_or(WriteError::NotOpen)?;
+        let (done, result) = mpsc::channel();
+        let job: WriteJob = Box::new(move |db| match f(db) {
+            Ok(value) => Some(Box::new(move |committed: Result<(), String>| {
+                let _ = done.send(committed.map(|_| value).map_err(WriteError::CommitFailed));
+            })),
+            Err(e) => {
+                let _ = done.send(Err(WriteError::Failed(e)));
+                None
+            }
+        });
+        // Without a reply the write panicked, or the writer thread is gone
+        if pool.writer.lock().unwrap().send(job).is_err() {
+            return Err(WriteError::Aborted);
+        }
+        result.recv().unwrap_or(Err(WriteError::Aborted))
+    }
+}
+
+struct CheckedOut<'a> {
+    pool: &'a Pool,
+    reader: Option<DatabaseManager>,
+}
+
+impl Drop for CheckedOut<'_> {
+    fn drop(&mut self) {
+        if let Some(reader) = self.reader.take() {
+            self.pool.readers.lock().unwrap().push(reader);
+            self.pool.reader_returned.notify_one();
+        }
+    }
+}
+
+// Runs writes in batches of whatever has queued up, and only tells the callers they're done
+// once they're committed and the readers can see them
+fn run_writer(mut db: DatabaseManager, jobs: Receiver<WriteJob>) {
+    while let Ok(job) = jobs.recv() {
+        let mut batch = vec![job];
+        while batch.len() < WRITE_BATCH_SIZE {
+            match jobs.try_recv() {
+                Ok(job) => batch.push(job),
+                Err(_) => break,
+            }
+        }
+
+        let mut replies = Vec::new();
+        let committed = db
+            .in_transaction(|db| {
+                for job in batch {
+                    replies.extend(run_job(db, job));
+                }
+            })
+            .map_err(|e| {
+                println!("Failed to commit writes: {:?}", e);
+                e.to_string()
+            });
+        for reply in replies {
+            reply(committed.clone());
+        }
+    }
+}
+
+// Runs a job in its own savepoint, so a job that fails or panics leaves none of its writes
+// behind and doesn't take the others in its batch with it
+fn run_job(db: &mut DatabaseManager, job: WriteJob) -> Option<Reply> {
+    let mut reply = None;
+    let nested = db.in_nested_transaction(|db| {
+        reply = panic::catch_unwind(AssertUnwindSafe(|| job(db)))
+            .ok()
+            .flatten();
+        reply.is_some()
+    });
+    if let Err(e) = nested {
+        println!("Failed to end the savepoint of a write: {:?}", e);
+    }
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::SearchFilters;
+
+    #[test]
+    fn test_writes_are_seen_by_readers_once_committed() {
+        let dir = std::env::temp_dir().join(format!("xrem-pool-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("db.sqlite");
+
+        let db = DbPool::default();
+        assert_eq!(None, db.read(|db| db.get_max_frame().unwrap()));
+        assert!(matches!(
+            db.write(|db| db.insert_frame(None, None)),
+            Err(WriteError::NotOpen)
+        ));
+        db.open(path.to_str().unwrap(), Quantization::default())
+            .unwrap();
+
+        // Writers on several threads share batches
+        let handles = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                thread::spawn(move || db.write(|db| db.insert_frame(None, None)).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let mut frames = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        frames.sort();
+        db.write(|db| db.start_new_video_chunk("output-1.mp4"))
+            .unwrap();
+        let frame = frames[0];
+        db.write(move |db| db.insert_text_for_frame(frame, "quarterly report"))
+            .unwrap();
+
+        // A write that panics doesn't take the rest of its batch with it
+        assert!(matches!(
+            db.write(|_| -> rusqlite::Result<i64> { panic!("broken write") }),
+            Err(WriteError::Aborted)
+        ));
+
+        let (max_frame, results) = db
+            .read(|db| {
+                let results = db.search(&["report"], 10, 0, &SearchFilters::default());
+                (db.get_max_frame().unwrap(), results.unwrap())
+            })
+            .unwrap();
+        assert_eq!(frames[7], max_frame);
+        assert_eq!(
+            vec![frame],
+            results.iter().map(|r| r.frame_id).collect::<Vec<_>>()
+        );
+        assert!(db.read(|db| db.enqueue_ocr(frame)).unwrap().is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_write_is_rolled_back_alone() {
+        let dir = std::env::temp_dir().join(format!("xrem-pool-failed-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("db.sqlite");
+        let db = DbPool::default();
+        db.open(path.to_str().unwrap(), Quantization::default())
+            .unwrap();
+
+        // Writes a frame and then fails, in the same batch as writes that succeed
+        let handles = (0..8)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.write(move |db| {
+                        let frame_id = db.insert_frame(None, None)?;
+                        match i {
+                            3 => Err(rusqlite::Error::QueryReturnedNoRows),
+                            _ => Ok(frame_id),
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            results[3],
+            Err(WriteError::Failed(rusqlite::Error::QueryReturnedNoRows))
+        ));
+        let mut frames = results
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+        frames.sort();
+        assert_eq!(7, frames.len());
+
+        // Its frame is gone, and the frames after it kept their place in the video
+        db.write(|db| db.start_new_video_chunk("output-1.mp4"))
+            .unwrap();
+        let offsets = db
+            .read(move |db| {
+                frames
+                    .iter()
+                    .map(|&frame_id| Ok(db.get_frame(frame_id)?.unwrap().0))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!((0..7).collect::<Vec<_>>(), offsets);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,47 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+// A 64 bit difference hash of what an image looks like: the image is shrunk to 9x8 grey
+// pixels and each bit is whether a pixel is darker than the one to its right. Screens
+// showing the same thing hash to nearby values even after scrolling a little or resizing.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] < pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+// Number of differing bits, 0 for the same hash and 64 at most
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn gradient(width: u32, height: u32, flip: bool) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let x = if flip { width - 1 - x } else { x };
+            let value = ((x * 255 / width) as u8).wrapping_add((y % 16) as u8);
+            Rgb([value, value, value])
+        }))
+    }
+
+    #[test]
+    fn test_similar_images_have_close_hashes() {
+        let hash = dhash(&gradient(640, 400, false));
+        // Same content at another size
+        assert!(distance(hash, dhash(&gradient(1280, 800, false))) <= 4);
+        assert!(distance(hash, dhash(&gradient(640, 400, true))) >= 32);
+        assert_eq!(0, distance(hash, hash));
+    }
+}
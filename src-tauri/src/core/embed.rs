@@ -3,162 +3,271 @@ use candle_nn::VarBuilder;
 use candle_transformers::models::bert::{BertModel, Config, HiddenAct, DTYPE};
 use lazy_static::lazy_static;
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::Mutex;
-use tokenizers::{PaddingParams, Tokenizer};
+use tokenizers::{Tokenizer, TruncationParams};
+
+use super::models::{ModelError, ResolvedModel};
 
 lazy_static! {
-    static ref MODEL: Mutex<Option<(BertModel, Tokenizer)>> = Mutex::new(None);
+    static ref MODEL: Mutex<Option<LoadedModel>> = Mutex::new(None);
+    // The model existing vectors were made with, loaded while they're re-embedded with MODEL
+    // so searches can keep using them
+    static ref FALLBACK_MODEL: Mutex<Option<LoadedModel>> = Mutex::new(None);
+}
+
+struct LoadedModel {
+    id: String,
+    model: BertModel,
+    tokenizer: Tokenizer,
+    // Whether vectors are scaled to unit length
+    normalize: bool,
+    query_prefix: String,
+}
+
+#[derive(Debug)]
+pub enum EmbedError {
+    ModelNotInitialized,
+    Tokenizer(String),
+    Candle(candle::Error),
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbedError::ModelNotInitialized => write!(f, "Model not initialized"),
+            EmbedError::Tokenizer(e) => write!(f, "Tokenizer error: {}", e),
+            EmbedError::Candle(e) => write!(f, "Model error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EmbedError {}
+
+impl From<candle::Error> for EmbedError {
+    fn from(e: candle::Error) -> Self {
+        EmbedError::Candle(e)
+    }
+}
+
+// The embedding of a search query and the model it was made with
+#[derive(Debug)]
+pub struct QueryEmbedding {
+    pub model_id: String,
+    pub vector: Vec<f32>,
+}
+
+// Function to initialize the model and tokenizer from a model's files
+pub fn init_model(resolved: &ResolvedModel) -> Result<(), ModelError> {
+    *MODEL.lock().unwrap() = Some(load_model(resolved)?);
+    Ok(())
+}
+
+// Function to load the model searches use until a re-index finishes
+pub fn init_fallback_model(resolved: &ResolvedModel) -> Result<(), ModelError> {
+    *FALLBACK_MODEL.lock().unwrap() = Some(load_model(resolved)?);
+    Ok(())
+}
+
+pub fn unload_fallback_model() {
+    *FALLBACK_MODEL.lock().unwrap() = None;
 }
 
-// Function to initialize the model and tokenizer from local files
-pub fn init_model(
-    config_path: &str,
-    tokenizer_path: &str,
-    weights_path: &str,
-    approximate_gelu: bool,
-) -> bool {
+fn load_model(resolved: &ResolvedModel) -> Result<LoadedModel, ModelError> {
     let device = candle::Device::Cpu;
+    let config_error = |e: serde_json::Error| ModelError::InvalidConfig {
+        path: resolved.config_path.clone(),
+        reason: e.to_string(),
+    };
+    let tokenizer_error = |e: tokenizers::Error| ModelError::Tokenizer {
+        path: resolved.tokenizer_path.clone(),
+        reason: e.to_string(),
+    };
 
     // Load config
-    let config_contents = std::fs::read_to_string(config_path).unwrap();
-    let mut config: Config = serde_json::from_str(&config_contents).unwrap();
+    let config_contents =
+        std::fs::read_to_string(&resolved.config_path).map_err(|e| ModelError::InvalidConfig {
+            path: resolved.config_path.clone(),
+            reason: e.to_string(),
+        })?;
+    let mut config: Config = serde_json::from_str(&config_contents).map_err(config_error)?;
+    let max_length = serde_json::from_str::<serde_json::Value>(&config_contents)
+        .map_err(config_error)?["max_position_embeddings"]
+        .as_u64()
+        .unwrap_or(512) as usize;
 
-    // Load tokenizer
-    let tokenizer = Tokenizer::from_file(tokenizer_path).unwrap();
+    // Load tokenizer, cutting texts the model can't fit
+    let mut tokenizer = Tokenizer::from_file(&resolved.tokenizer_path).map_err(tokenizer_error)?;
+    tokenizer
+        .with_truncation(Some(TruncationParams {
+            max_length,
+            ..Default::default()
+        }))
+        .map_err(tokenizer_error)?;
 
     // Load weights
     let vb =
-        unsafe { VarBuilder::from_mmaped_safetensors(&[weights_path], DTYPE, &device).unwrap() };
+        unsafe { VarBuilder::from_mmaped_safetensors(&[&resolved.weights_path], DTYPE, &device)? };
 
-    if approximate_gelu {
+    if resolved.spec.approximate_gelu {
         config.hidden_act = HiddenAct::GeluApproximate;
     }
 
-    let model = BertModel::load(vb, &config).unwrap();
+    let model = BertModel::load(vb, &config)?;
 
-    // Store model and tokenizer in the global MODEL variable
-    let mut model_guard = MODEL.lock().unwrap();
-    *model_guard = Some((model, tokenizer));
-    true
+    Ok(LoadedModel {
+        id: resolved.spec.id.clone(),
+        model,
+        tokenizer,
+        normalize: resolved.spec.normalize,
+        query_prefix: resolved.spec.query_prefix.clone(),
+    })
 }
 
-pub struct EmbeddingResult {
-    pub embeddings: Vec<f32>,
-    pub len: usize,
-    pub error: String,
+// The ID of the loaded model, stored alongside every vector it produces
+pub fn current_model_id() -> Option<String> {
+    MODEL.lock().unwrap().as_ref().map(|m| m.id.clone())
 }
 
-impl EmbeddingResult {
-    fn from_error_string(e: &str) -> EmbeddingResult {
-        return EmbeddingResult {
-            embeddings: vec![],
-            len: 0,
-            error: e.to_owned(),
-        };
+// Function to generate the embedding of a search query, with the model's query prefix if it
+// has one. While a re-index is running this uses the fallback model, whose vectors are complete.
+pub fn generate_query_embedding(query: &str) -> Result<QueryEmbedding, EmbedError> {
+    let fallback_guard = FALLBACK_MODEL.lock().unwrap();
+    if let Some(fallback) = fallback_guard.as_ref() {
+        return fallback.embed_query(query);
     }
+    drop(fallback_guard);
 
-    fn from_err(e: Box<dyn std::error::Error>) -> EmbeddingResult {
-        EmbeddingResult::from_error_string(e.to_string().as_str())
-    }
+    let model_guard = MODEL.lock().unwrap();
+    model_guard
+        .as_ref()
+        .ok_or(EmbedError::ModelNotInitialized)?
+        .embed_query(query)
 }
 
-// Function to generate embeddings
-pub fn generate_embeddings(text: &str) -> EmbeddingResult {
+// Function to generate embeddings for several texts, in the same order
+pub fn generate_embeddings_batch(texts: &[&str]) -> Result<Vec<Vec<f32>>, EmbedError> {
     let model_guard = MODEL.lock().unwrap();
-    let (model, tokenizer) = match model_guard.as_ref() {
-        Some(data) => data,
-        None => return EmbeddingResult::from_error_string("Model not initialized"),
-    };
-
-    // Create a new tokenizer instance with the desired configuration
-    let mut new_tokenizer = tokenizer.clone();
-    new_tokenizer.with_padding(Some(PaddingParams::default()));
+    model_guard
+        .as_ref()
+        .ok_or(EmbedError::ModelNotInitialized)?
+        .embed_batch(texts)
+}
 
-    if let Err(e) = new_tokenizer.with_truncation(None) {
-        return EmbeddingResult::from_err(e);
+impl LoadedModel {
+    fn embed_query(&self, query: &str) -> Result<QueryEmbedding, EmbedError> {
+        let text = format!("{}{}", self.query_prefix, query);
+        Ok(QueryEmbedding {
+            model_id: self.id.clone(),
+            vector: self.embed_batch(&[&text])?.remove(0),
+        })
     }
 
-    let tokens = match tokenizer.encode(text, true) {
-        Ok(t) => t,
-        Err(e) => return EmbeddingResult::from_err(e),
-    };
-
-    let token_ids = match Tensor::new(&tokens.get_ids().to_vec()[..], &model.device)
-        .unwrap()
-        .unsqueeze(0)
-    {
-        Ok(t) => t,
-        Err(e) => return EmbeddingResult::from_error_string(e.to_string().as_str()),
-    };
-
-    let token_type_ids = token_ids.zeros_like().unwrap();
-
-    let embeddings = match model.forward(&token_ids, &token_type_ids) {
-        Ok(e) => e,
-        Err(e) => return EmbeddingResult::from_error_string(e.to_string().as_str()),
-    };
-
-    let (_n_sentence, n_tokens, _hidden_size) = match embeddings.dims3() {
-        Ok(e) => e,
-        Err(e) => {
-            return EmbeddingResult::from_error_string(e.to_string().as_str());
+    // candle's BertModel doesn't take an attention mask, so padded tokens would be attended to
+    // and shift the vectors of shorter texts. Texts are batched with others of the same token
+    // count instead, which need no padding.
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, EmbedError> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| EmbedError::Tokenizer(e.to_string()))?;
+        let mut by_length: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for (i, encoding) in encodings.iter().enumerate() {
+            by_length.entry(encoding.len()).or_default().push(i);
         }
-    };
-    let summed = match embeddings.sum(1) {
-        Ok(e) => e,
-        Err(e) => {
-            return EmbeddingResult::from_error_string(e.to_string().as_str());
-        }
-    };
-    let embeddings = match summed / (n_tokens as f64) {
-        Ok(e) => e,
-        Err(e) => {
-            return EmbeddingResult::from_error_string(e.to_string().as_str());
-        }
-    };
 
-    // Flatten the tensor without changing the total number of elements
-    let reshaped_embeddings = match embeddings.reshape(&[embeddings.elem_count()]) {
-        Ok(r) => r,
-        Err(e) => {
-            return EmbeddingResult {
-                embeddings: vec![],
-                len: 0,
-                error: e.to_string(),
+        let device = &self.model.device;
+        let mut vectors = vec![Vec::new(); texts.len()];
+        for (n_tokens, indexes) in by_length {
+            let shape = (indexes.len(), n_tokens);
+            let token_ids = indexes
+                .iter()
+                .flat_map(|&i| encodings[i].get_ids().to_vec())
+                .collect::<Vec<_>>();
+            let attention_mask = indexes
+                .iter()
+                .flat_map(|&i| encodings[i].get_attention_mask().to_vec())
+                .map(|m| m as f32)
+                .collect::<Vec<_>>();
+            let token_ids = Tensor::from_vec(token_ids, shape, device)?;
+            let token_type_ids = token_ids.zeros_like()?;
+            let attention_mask = Tensor::from_vec(attention_mask, shape, device)?;
+
+            let embeddings = self.model.forward(&token_ids, &token_type_ids)?;
+            let mut pooled = mean_pool(&embeddings, &attention_mask)?;
+            if self.normalize {
+                pooled = l2_normalize(&pooled)?;
+            }
+            for (i, vector) in indexes.into_iter().zip(pooled.to_vec2::<f32>()?) {
+                vectors[i] = vector;
             }
         }
-    };
+        Ok(vectors)
+    }
+}
 
-    let elem_count = reshaped_embeddings.elem_count();
-    let out = match reshaped_embeddings.to_vec1::<f32>() {
-        Ok(a) => a,
-        Err(_e) => vec![],
-    };
+// Averages each text's token embeddings (batch, tokens, hidden), leaving out padding
+fn mean_pool(embeddings: &Tensor, attention_mask: &Tensor) -> candle::Result<Tensor> {
+    // (batch, tokens) to (batch, tokens, 1) so it broadcasts over the hidden size
+    let mask = attention_mask.unsqueeze(2)?;
+    let summed = embeddings.broadcast_mul(&mask)?.sum(1)?;
+    let counts = mask.sum(1)?.clamp(1e-9, f64::MAX)?;
+    summed.broadcast_div(&counts)
+}
 
-    EmbeddingResult {
-        embeddings: out,
-        len: elem_count,
-        error: "".to_string(),
-    }
+fn l2_normalize(vectors: &Tensor) -> candle::Result<Tensor> {
+    let norms = vectors
+        .sqr()?
+        .sum_keepdim(1)?
+        .sqrt()?
+        .clamp(1e-12, f64::MAX)?;
+    vectors.broadcast_div(&norms)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::models::ModelRegistry;
+    use std::path::PathBuf;
 
     #[test]
     fn test_generate_embeddings() {
-        let config_path = "models/gte-small/config.json";
-        let tokenizer_path = "models/gte-small/tokenizer.json";
-        let weights_path = "models/gte-small/model.safetensors";
-
         // Initialize the model first
-        init_model(config_path, tokenizer_path, weights_path, false);
+        ModelRegistry::new(vec![PathBuf::from("models")])
+            .unwrap()
+            .load("gte-small")
+            .unwrap();
 
         // Test embedding generation
         let text = "Test sentence for embeddings.";
-        let result: EmbeddingResult = generate_embeddings(text);
-        let arr = result.embeddings;
-        assert_eq!(384, arr.len())
+        let arr = generate_embeddings_batch(&[text]).unwrap().remove(0);
+        assert_eq!(384, arr.len());
+
+        // A text in a batch with a longer one isn't padded, so its vector doesn't change
+        let batch = generate_embeddings_batch(&[
+            text,
+            "A much longer sentence that would pad the first one quite a bit.",
+        ])
+        .unwrap();
+        assert_eq!(arr, batch[0]);
+    }
+
+    #[test]
+    fn test_mean_pool_ignores_padding() {
+        let device = candle::Device::Cpu;
+        // Two texts of two tokens each, the second one padded
+        let embeddings =
+            Tensor::new(&[[[1f32, 2.], [3., 4.]], [[5., 6.], [100., 100.]]], &device).unwrap();
+        let mask = Tensor::new(&[[1f32, 1.], [1., 0.]], &device).unwrap();
+
+        let pooled = mean_pool(&embeddings, &mask).unwrap();
+        assert_eq!(
+            vec![vec![2f32, 3.], vec![5., 6.]],
+            pooled.to_vec2::<f32>().unwrap()
+        );
+
+        let normalized = l2_normalize(&pooled).unwrap().to_vec2::<f32>().unwrap();
+        assert!((normalized[1][0] - 5. / 61f32.sqrt()).abs() < 1e-6);
     }
 }
@@ -0,0 +1,170 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Vectors kept in memory, the least recently used are dropped past this
+const MEMORY_CAPACITY: usize = 4096;
+
+// Vectors by model and text hash kept in memory in front of the embedding_cache table, and how
+// often texts were found in either. One is shared by the database's connections.
+pub struct EmbeddingCache {
+    memory: Mutex<MemoryCache>,
+    capacity: usize,
+    memory_hits: AtomicU64,
+    database_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+type CacheKey = (String, String);
+
+// Each entry has when it was last used, and `by_last_use` has the entries in that order so
+// the least recently used is the first one
+#[derive(Default)]
+struct MemoryCache {
+    entries: HashMap<CacheKey, (Vec<f32>, u64)>,
+    by_last_use: BTreeMap<u64, CacheKey>,
+    clock: u64,
+}
+
+impl MemoryCache {
+    fn touch(&mut self, key: &CacheKey) -> Option<Vec<f32>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (vector, last_used) = self.entries.get_mut(key)?;
+        self.by_last_use.remove(last_used);
+        *last_used = clock;
+        self.by_last_use.insert(clock, key.clone());
+        Some(vector.clone())
+    }
+}
+
+// How often texts were found in the cache since the app started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub memory_hits: u64,
+    pub database_hits: u64,
+    // Texts that went through the model
+    pub misses: u64,
+    pub memory_entries: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.memory_hits + self.database_hits;
+        match hits + self.misses {
+            0 => 0.0,
+            lookups => hits as f64 / lookups as f64,
+        }
+    }
+}
+
+// The key a text's vector is cached under, for each model
+pub fn text_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+impl Default for EmbeddingCache {
+    fn default() -> EmbeddingCache {
+        EmbeddingCache::with_capacity(MEMORY_CAPACITY)
+    }
+}
+
+impl EmbeddingCache {
+    pub fn with_capacity(capacity: usize) -> EmbeddingCache {
+        EmbeddingCache {
+            memory: Mutex::new(MemoryCache::default()),
+            capacity,
+            memory_hits: AtomicU64::new(0),
+            database_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    // Returns the vectors in memory for the given hashes
+    pub fn get_many(&self, model_id: &str, hashes: &[String]) -> HashMap<String, Vec<f32>> {
+        let mut memory = self.memory.lock().unwrap();
+        let found = hashes
+            .iter()
+            .filter_map(|hash| {
+                let vector = memory.touch(&(model_id.to_string(), hash.clone()))?;
+                Some((hash.clone(), vector))
+            })
+            .collect::<HashMap<_, _>>();
+        self.memory_hits
+            .fetch_add(found.len() as u64, Ordering::Relaxed);
+        found
+    }
+
+    pub fn insert(&self, model_id: &str, hash: &str, vector: &[f32]) {
+        let mut memory = self.memory.lock().unwrap();
+        let key = (model_id.to_string(), hash.to_string());
+        memory.clock += 1;
+        let clock = memory.clock;
+        if let Some((_, last_used)) = memory.entries.insert(key.clone(), (vector.to_vec(), clock)) {
+            memory.by_last_use.remove(&last_used);
+        }
+        memory.by_last_use.insert(clock, key);
+        while memory.entries.len() > self.capacity {
+            match memory.by_last_use.pop_first() {
+                Some((_, oldest)) => memory.entries.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    pub fn record_database_hits(&self, count: usize) {
+        self.database_hits
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_misses(&self, count: usize) {
+        self.misses.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    // Drops the vectors in memory, e.g. after the text they came from was forgotten
+    pub fn clear(&self) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.entries.clear();
+        memory.by_last_use.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            memory_hits: self.memory_hits.load(Ordering::Relaxed),
+            database_hits: self.database_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            memory_entries: self.memory.lock().unwrap().entries.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recently_used_vector_is_dropped() {
+        let cache = EmbeddingCache::with_capacity(2);
+        cache.insert("gte-small", "a", &[1.0]);
+        cache.insert("gte-small", "b", &[2.0]);
+        // Using "a" makes "b" the oldest
+        assert_eq!(1, cache.get_many("gte-small", &["a".to_string()]).len());
+        cache.insert("gte-small", "c", &[3.0]);
+
+        let found = cache.get_many(
+            "gte-small",
+            &["a".to_string(), "b".to_string(), "c".to_string()],
+        );
+        assert_eq!(Some(&vec![1.0]), found.get("a"));
+        assert_eq!(None, found.get("b"));
+        assert_eq!(Some(&vec![3.0]), found.get("c"));
+        assert!(cache
+            .get_many("bge-small-en-v1.5", &["a".to_string()])
+            .is_empty());
+
+        let stats = cache.stats();
+        assert_eq!(2, stats.memory_entries);
+        assert_eq!(3, stats.memory_hits);
+    }
+}
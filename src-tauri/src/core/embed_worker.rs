@@ -0,0 +1,378 @@
+use crate::core::embed::{self, EmbedError};
+use crate::core::embed_cache;
+use crate::core::image_embed::{current_image_model_id, generate_image_embedding};
+use crate::core::models::ModelRegistry;
+use crate::core::ocr::load_frame_image;
+use crate::core::passages::{split_passages, Passage};
+use crate::core::{DatabaseManager, DbPool, Governor, WriteError};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const EMBED_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const EMBED_BATCH_SIZE: i64 = 16;
+// Screenshots are decoded from their chunk one at a time, so fewer are taken per loop
+const IMAGE_BATCH_SIZE: i64 = 4;
+// Vector indexes are rebuilt from the table after a crash, so they're only saved now and then
+const INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(300);
+// Stored vectors converted per loop when the storage quantization changes
+const REQUANTIZE_BATCH_SIZE: i64 = 1000;
+
+// A frame's passages and their vectors, or why they couldn't be embedded
+type EmbeddedFrame = (i64, Vec<Passage>, Result<Vec<Vec<f32>>, String>);
+
+// Embeds the OCR text of frames in the background, a passage at a time, and their screenshots,
+// and stores the vectors. Frames are picked up from the database, so anything left over when
+// the app exits is embedded on the next run.
+pub fn start_embed_worker(db: DbPool, governor: Arc<Governor>) {
+    thread::spawn(move || loop {
+        let (embedded, images_embedded) = if governor.is_busy() {
+            (0, 0)
+        } else {
+            requantize_embeddings(&db);
+            (embed_pending_frames(&db), embed_pending_images(&db))
+        };
+        save_vector_indexes(&db);
+        // Keep going while there's a backlog, otherwise wait for more frames to come in
+        if embedded < EMBED_BATCH_SIZE as usize && images_embedded < IMAGE_BATCH_SIZE as usize {
+            thread::sleep(EMBED_POLL_INTERVAL);
+        }
+    });
+}
+
+// Loads the embedding models, and if the text model isn't the one the stored vectors were
+// made with, starts (or resumes) re-embedding every frame. The model before it is loaded too
+// so searches keep working on its vectors until the job is done. The image model is optional,
+// without it frames are only searchable by their text.
+pub fn load_embedding_model(
+    db: DbPool,
+    model_dirs: Vec<PathBuf>,
+    model_id: String,
+    image_model_id: String,
+) {
+    thread::spawn(move || {
+        let registry = match ModelRegistry::new(model_dirs) {
+            Ok(registry) => registry,
+            Err(e) => {
+                println!("Failed to load embedding model: {}", e);
+                return;
+            }
+        };
+        match registry.load_image_model(&image_model_id) {
+            Ok(()) => {
+                println!("Loaded image embedding model {}", image_model_id);
+                queue_missing_images(&db, image_model_id);
+            }
+            Err(e) => println!("Image search disabled: {}", e),
+        }
+        load_text_model(&db, &registry, &model_id);
+    });
+}
+
+fn load_text_model(db: &DbPool, registry: &ModelRegistry, model_id: &str) {
+    if let Err(e) = registry.load(model_id) {
+        println!("Failed to load embedding model: {}", e);
+        return;
+    }
+    println!("Loaded embedding model {}", model_id);
+
+    let reindex_model_id = model_id.to_string();
+    let job = match db.write(move |db| db.start_reindex(&reindex_model_id)) {
+        Ok(job) => job,
+        Err(WriteError::NotOpen) => None,
+        Err(e) => {
+            println!("Failed to start re-index: {}", e);
+            None
+        }
+    };
+    if let Some(previous) = job.and_then(|job| job.previous_model_id) {
+        println!("Re-indexing embeddings from {} with {}", previous, model_id);
+        if let Err(e) = registry.load_fallback(&previous) {
+            println!(
+                "Failed to load {} for search while re-indexing: {}",
+                previous, e
+            );
+        }
+    }
+}
+
+// Frames captured before the image model was loaded, or embedded by another one, are queued
+// so the worker gets to them
+fn queue_missing_images(db: &DbPool, model_id: String) {
+    match db.write(move |db| db.queue_missing_image_embeddings(&model_id)) {
+        Ok(0) | Err(WriteError::NotOpen) => {}
+        Ok(queued) => println!("Queued {} screenshots to embed", queued),
+        Err(e) => println!("Failed to queue screenshots to embed: {}", e),
+    }
+}
+
+fn requantize_embeddings(db: &DbPool) {
+    match db.write(|db| db.requantize_embeddings(REQUANTIZE_BATCH_SIZE)) {
+        Ok(_) | Err(WriteError::NotOpen) => {}
+        Err(e) => println!("Failed to convert stored vectors: {}", e),
+    }
+}
+
+// The indexes are shared by every connection, so saving them doesn't need to hold up writes
+fn save_vector_indexes(db: &DbPool) {
+    if let Some(Err(e)) = db.read(|db| db.save_vector_indexes(INDEX_SAVE_INTERVAL)) {
+        println!("Failed to save vector index: {}", e);
+    }
+}
+
+// Embeds a batch of frames, returns how many were stored. Passage texts seen before get
+// their vectors from the cache, only the rest go through the model.
+fn embed_pending_frames(db: &DbPool) -> usize {
+    // Nothing to do until a model is loaded
+    let model_id = match embed::current_model_id() {
+        Some(model_id) => model_id,
+        None => return 0,
+    };
+
+    let frame_ids = match db.read(|db| db.get_frames_missing_embeddings(EMBED_BATCH_SIZE)) {
+        Some(Ok(frame_ids)) => frame_ids,
+        Some(Err(e)) => {
+            println!("Failed to get frames to embed: {:?}", e);
+            return 0;
+        }
+        None => return 0,
+    };
+    if frame_ids.is_empty() {
+        finish_reindex(db, &model_id);
+        return 0;
+    }
+    let batch = db.read(|db| {
+        let frames = frame_ids
+            .into_iter()
+            .filter_map(|frame_id| match db.get_text_for_frame(frame_id) {
+                Ok(Some(text)) => {
+                    let words = db.get_ocr_words(frame_id).unwrap_or_default();
+                    Some((frame_id, split_passages(&text, &words)))
+                }
+                // The text was removed since, it's recorded as having none to take it off the queue
+                Ok(None) => Some((frame_id, Vec::new())),
+                Err(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let vectors = cached_vectors(db, &model_id, &frames);
+        (frames, vectors)
+    });
+    let (frames, mut vectors) = match batch {
+        Some(batch) => batch,
+        None => return 0,
+    };
+
+    // The model is locked while embedding, so don't hold a connection at the same time
+    let mut uncached = HashMap::new();
+    for passage in frames.iter().flat_map(|(_, passages)| passages) {
+        let hash = embed_cache::text_hash(&passage.text);
+        if !vectors.contains_key(&hash) {
+            uncached.insert(hash, passage.text.as_str());
+        }
+    }
+    let (hashes, texts): (Vec<String>, Vec<&str>) = uncached.into_iter().unzip();
+    let embedded_vectors = match embed_texts(&texts) {
+        Ok(embedded_vectors) => embedded_vectors,
+        Err(_) => return 0,
+    };
+    let misses = texts.len();
+
+    let mut new_vectors = Vec::new();
+    for (hash, result) in hashes.into_iter().zip(embedded_vectors) {
+        if let Ok(vector) = &result {
+            new_vectors.push((hash.clone(), vector.clone()));
+        }
+        vectors.insert(hash, result);
+    }
+    let frames = frames
+        .into_iter()
+        .map(|(frame_id, passages)| {
+            let frame_vectors = passages
+                .iter()
+                .map(|passage| vectors[&embed_cache::text_hash(&passage.text)].clone())
+                .collect::<Result<Vec<_>, String>>();
+            (frame_id, passages, frame_vectors)
+        })
+        .collect::<Vec<_>>();
+
+    db.write(move |db| Ok(store_embeddings(db, &model_id, misses, new_vectors, frames)))
+        .unwrap_or(0)
+}
+
+// Embeds a batch of queued screenshots with the image model, returns how many were stored. A
+// frame that can't be decoded or embedded is taken off the queue rather than retried.
+fn embed_pending_images(db: &DbPool) -> usize {
+    let model_id = match current_image_model_id() {
+        Some(model_id) => model_id,
+        None => return 0,
+    };
+    let frame_ids = match db.read(|db| db.get_frames_missing_image_embeddings(IMAGE_BATCH_SIZE)) {
+        Some(Ok(frame_ids)) => frame_ids,
+        Some(Err(e)) => {
+            println!("Failed to get screenshots to embed: {:?}", e);
+            return 0;
+        }
+        None => return 0,
+    };
+
+    // Decoding and the model are slow, so no connection is held meanwhile
+    let embedded = frame_ids
+        .into_iter()
+        .filter_map(|frame_id| {
+            let vector = match load_frame_image(db, frame_id) {
+                Ok(Some(image)) => generate_image_embedding(&image).map_err(|e| e.to_string()),
+                // Left queued until its chunk is written
+                Ok(None) => return None,
+                Err(e) => Err(e),
+            };
+            Some((frame_id, vector))
+        })
+        .collect::<Vec<_>>();
+
+    db.write(move |db| {
+        let mut stored = 0;
+        for (frame_id, vector) in embedded {
+            let result = match vector {
+                Ok(vector) => db
+                    .insert_image_embedding(frame_id, &model_id, &vector)
+                    .map(|_| stored += 1),
+                Err(e) => {
+                    println!("Failed to embed image of frame {}: {}", frame_id, e);
+                    db.skip_image_embedding(frame_id)
+                }
+            };
+            if let Err(e) = result {
+                println!(
+                    "Failed to store image embedding for frame {}: {:?}",
+                    frame_id, e
+                );
+            }
+        }
+        Ok(stored)
+    })
+    .unwrap_or(0)
+}
+
+// Stores newly embedded texts in the cache and each frame's passage vectors, or why it has
+// none, returns how many frames got vectors
+fn store_embeddings(
+    db: &DatabaseManager,
+    model_id: &str,
+    misses: usize,
+    new_vectors: Vec<(String, Vec<f32>)>,
+    frames: Vec<EmbeddedFrame>,
+) -> usize {
+    let cache = db.embedding_cache();
+    cache.record_misses(misses);
+    for (hash, vector) in &new_vectors {
+        cache.insert(model_id, hash, vector);
+        if let Err(e) = db.insert_cached_embedding(model_id, hash, vector) {
+            println!("Failed to cache embedding: {:?}", e);
+        }
+    }
+    if !new_vectors.is_empty() {
+        if let Err(e) = db.prune_embedding_cache() {
+            println!("Failed to prune embedding cache: {:?}", e);
+        }
+    }
+
+    let mut embedded = 0;
+    for (frame_id, passages, frame_vectors) in frames {
+        let stored = match frame_vectors {
+            Ok(frame_vectors) if frame_vectors.is_empty() => {
+                db.insert_embedding_error(frame_id, model_id, "No text to embed")
+            }
+            // A frame's passages are stored together, so if one fails the frame has none
+            Ok(frame_vectors) => passages
+                .iter()
+                .zip(frame_vectors)
+                .try_for_each(|(passage, vector)| {
+                    let span = Some((passage.start, passage.end));
+                    db.insert_embedding(frame_id, model_id, span, &vector)
+                        .map(|_| ())
+                })
+                .map(|_| embedded += 1),
+            Err(e) => {
+                println!("Failed to embed frame {}: {}", frame_id, e);
+                db.insert_embedding_error(frame_id, model_id, &e)
+            }
+        };
+        if let Err(e) = stored {
+            println!("Failed to store embedding for frame {}: {:?}", frame_id, e);
+        }
+    }
+    embedded
+}
+
+// The vectors of the frames' passage texts that are cached, in memory or in the database,
+// by text hash
+fn cached_vectors(
+    db: &DatabaseManager,
+    model_id: &str,
+    frames: &[(i64, Vec<Passage>)],
+) -> HashMap<String, Result<Vec<f32>, String>> {
+    let mut hashes = frames
+        .iter()
+        .flat_map(|(_, passages)| passages.iter().map(|p| embed_cache::text_hash(&p.text)))
+        .collect::<Vec<_>>();
+    hashes.sort();
+    hashes.dedup();
+
+    let cache = db.embedding_cache();
+    let mut cached = cache.get_many(model_id, &hashes);
+    let missing = hashes
+        .into_iter()
+        .filter(|hash| !cached.contains_key(hash))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        match db.get_cached_embeddings(model_id, &missing) {
+            Ok(stored) => {
+                cache.record_database_hits(stored.len());
+                for (hash, vector) in stored {
+                    cache.insert(model_id, &hash, &vector);
+                    cached.insert(hash, vector);
+                }
+            }
+            Err(e) => println!("Failed to get cached embeddings: {:?}", e),
+        }
+    }
+    cached
+        .into_iter()
+        .map(|(hash, vector)| (hash, Ok(vector)))
+        .collect()
+}
+
+// Every frame has vectors from `model_id` now, so a re-index to it is done
+fn finish_reindex(db: &DbPool, model_id: &str) {
+    let job_model_id = model_id.to_string();
+    let finished = db.write(move |db| match db.get_reindex_job()? {
+        Some(job) if job.model_id == job_model_id => db.finish_reindex(&job_model_id).map(|_| true),
+        _ => Ok(false),
+    });
+    match finished {
+        Ok(true) => {
+            embed::unload_fallback_model();
+            println!("Re-indexed embeddings with {}", model_id);
+        }
+        Ok(false) | Err(WriteError::NotOpen) => {}
+        Err(e) => println!("Failed to finish re-index: {}", e),
+    }
+}
+
+// Embeds the texts in one batch. Errors come from the tokenizer or the model rather than any
+// one text, so a failed batch fails each of its texts. Fails only when there's no model.
+fn embed_texts(texts: &[&str]) -> Result<Vec<Result<Vec<f32>, String>>, EmbedError> {
+    if texts.is_empty() {
+        return Ok(Vec::new());
+    }
+    match embed::generate_embeddings_batch(texts) {
+        Ok(vectors) => Ok(vectors.into_iter().map(Ok).collect()),
+        Err(EmbedError::ModelNotInitialized) => Err(EmbedError::ModelNotInitialized),
+        Err(e) => {
+            println!("Failed to embed batch: {}", e);
+            Ok(vec![Err(e.to_string()); texts.len()])
+        }
+    }
+}
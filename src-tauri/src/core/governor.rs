@@ -0,0 +1,255 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_CPU_SHARE: f32 = 0.5;
+const DEFAULT_MAX_WORKERS: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GovernorConfig {
+    // Share of the machine's CPUs (0-1) xrem may use for OCR, embeddings and encoding
+    pub max_cpu_share: f32,
+    pub max_workers: usize,
+}
+
+impl Default for GovernorConfig {
+    fn default() -> Self {
+        GovernorConfig {
+            max_cpu_share: DEFAULT_MAX_CPU_SHARE,
+            max_workers: DEFAULT_MAX_WORKERS,
+        }
+    }
+}
+
+impl GovernorConfig {
+    // Reads XREM_MAX_CPU_SHARE and XREM_MAX_WORKERS, falling back to the defaults
+    pub fn from_env() -> Self {
+        let defaults = GovernorConfig::default();
+        let max_cpu_share = std::env::var("XREM_MAX_CPU_SHARE")
+            .ok()
+            .and_then(|v| v.parse::<f32>().ok())
+            .filter(|v| *v > 0.0)
+            .map(|v| v.min(1.0))
+            .unwrap_or(defaults.max_cpu_share);
+        let max_workers = std::env::var("XREM_MAX_WORKERS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(defaults.max_workers);
+        GovernorConfig {
+            max_cpu_share,
+            max_workers,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct LoadSample {
+    // 1 minute load average of the whole system
+    system_load: Option<f32>,
+    // CPUs worth of time used by this process since the last sample
+    process_load: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Plan {
+    ocr_workers: usize,
+    encode_workers: usize,
+    is_busy: bool,
+}
+
+// Adjusts how much background work runs based on system load. When the machine is busy,
+// OCR and embeddings are left in the database backlog and picked up again once it's idle.
+pub struct Governor {
+    config: GovernorConfig,
+    cpus: usize,
+    ocr_workers: AtomicUsize,
+    encode_workers: AtomicUsize,
+    is_busy: AtomicBool,
+    last_cpu_time: Mutex<Option<(Instant, f32)>>,
+}
+
+impl Governor {
+    pub fn start(config: GovernorConfig) -> Arc<Governor> {
+        let cpus = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let initial = plan(cpus, &config, LoadSample::default());
+        let governor = Arc::new(Governor {
+            config,
+            cpus,
+            ocr_workers: AtomicUsize::new(initial.ocr_workers),
+            encode_workers: AtomicUsize::new(initial.encode_workers),
+            is_busy: AtomicBool::new(initial.is_busy),
+            last_cpu_time: Mutex::new(None),
+        });
+
+        if read_load_average().is_none() {
+            println!("Governor: can't read the system load here, background work isn't throttled");
+        }
+        let sampler = governor.clone();
+        thread::spawn(move || loop {
+            sampler.update();
+            thread::sleep(SAMPLE_INTERVAL);
+        });
+
+        governor
+    }
+
+    pub fn ocr_workers(&self) -> usize {
+        self.ocr_workers.load(Ordering::Relaxed)
+    }
+
+    pub fn encode_workers(&self) -> usize {
+        self.encode_workers.load(Ordering::Relaxed)
+    }
+
+    // Whether deferrable work (OCR, embeddings) should wait in the backlog
+    pub fn is_busy(&self) -> bool {
+        self.is_busy.load(Ordering::Relaxed)
+    }
+
+    fn update(&self) {
+        let sample = LoadSample {
+            system_load: read_load_average(),
+            process_load: self.sample_process_load(),
+        };
+        let plan = plan(self.cpus, &self.config, sample);
+        if plan.is_busy != self.is_busy() {
+            println!(
+                "Governor: system is {}",
+                if plan.is_busy { "busy" } else { "idle" }
+            );
+        }
+        self.ocr_workers.store(plan.ocr_workers, Ordering::Relaxed);
+        self.encode_workers
+            .store(plan.encode_workers, Ordering::Relaxed);
+        self.is_busy.store(plan.is_busy, Ordering::Relaxed);
+    }
+
+    fn sample_process_load(&self) -> Option<f32> {
+        let cpu_time = read_process_cpu_time()?;
+        let now = Instant::now();
+        let mut last = self.last_cpu_time.lock().unwrap();
+        let load = last.map(|(at, previous)| {
+            let elapsed = now.duration_since(at).as_secs_f32();
+            if elapsed > 0.0 {
+                (cpu_time - previous).max(0.0) / elapsed
+            } else {
+                0.0
+            }
+        });
+        *last = Some((now, cpu_time));
+        load
+    }
+}
+
+fn plan(cpus: usize, config: &GovernorConfig, sample: LoadSample) -> Plan {
+    let cpus_f = cpus as f32;
+    let process_share = sample.process_load.unwrap_or(0.0) / cpus_f;
+    // Load from everything other than xrem
+    let other_share = sample
+        .system_load
+        .map(|load| (load / cpus_f - process_share).clamp(0.0, 1.0))
+        .unwrap_or(0.0);
+
+    let share = config.max_cpu_share.min(1.0 - other_share);
+    let is_busy = other_share > 1.0 - config.max_cpu_share;
+    let workers = ((cpus_f * share).floor() as usize).clamp(1, config.max_workers.max(1));
+
+    Plan {
+        ocr_workers: if is_busy { 1 } else { workers },
+        encode_workers: workers,
+        is_busy,
+    }
+}
+
+// 1 minute load average, from getloadavg on Linux and macOS
+#[cfg(unix)]
+fn read_load_average() -> Option<f32> {
+    let mut loads = [0f64; 3];
+    match unsafe { libc::getloadavg(loads.as_mut_ptr(), 3) } {
+        n if n >= 1 => Some(loads[0] as f32),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn read_load_average() -> Option<f32> {
+    None
+}
+
+// CPU seconds (user + system) used by this process so far
+#[cfg(unix)]
+fn read_process_cpu_time() -> Option<f32> {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let seconds = |time: libc::timeval| time.tv_sec as f32 + time.tv_usec as f32 / 1e6;
+    Some(seconds(usage.ru_utime) + seconds(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn read_process_cpu_time() -> Option<f32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reads_load() {
+        assert!(read_load_average().unwrap() >= 0.0);
+        let before = read_process_cpu_time().unwrap();
+        // Burn a little CPU so the process' time moves
+        let mut x = 0u64;
+        for i in 0..20_000_000u64 {
+            x = x.wrapping_add(i * i);
+        }
+        std::hint::black_box(x);
+        assert!(read_process_cpu_time().unwrap() > before);
+    }
+
+    #[test]
+    fn test_plan_backs_off_when_busy() {
+        let config = GovernorConfig {
+            max_cpu_share: 0.5,
+            max_workers: 4,
+        };
+
+        let idle = plan(
+            8,
+            &config,
+            LoadSample {
+                system_load: Some(1.0),
+                process_load: Some(1.0),
+            },
+        );
+        assert_eq!(
+            Plan {
+                ocr_workers: 4,
+                encode_workers: 4,
+                is_busy: false
+            },
+            idle
+        );
+
+        // A build using 6 of 8 CPUs
+        let busy = plan(
+            8,
+            &config,
+            LoadSample {
+                system_load: Some(7.0),
+                process_load: Some(1.0),
+            },
+        );
+        assert!(busy.is_busy);
+        assert_eq!(1, busy.ocr_workers);
+        assert_eq!(2, busy.encode_workers);
+    }
+}
@@ -0,0 +1,177 @@
+use candle::{DType, Tensor};
+use candle_nn::VarBuilder;
+use image::DynamicImage;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+use tokenizers::{Tokenizer, TruncationParams};
+
+use super::clip::{ClipConfig, ClipModel};
+use super::embed::{EmbedError, QueryEmbedding};
+use super::models::{ModelError, ResolvedModel};
+
+lazy_static! {
+    static ref IMAGE_MODEL: Mutex<Option<LoadedImageModel>> = Mutex::new(None);
+}
+
+struct LoadedImageModel {
+    id: String,
+    model: ClipModel,
+    tokenizer: Tokenizer,
+}
+
+// Function to initialize the image model and its tokenizer from a model's files
+pub fn init_image_model(resolved: &ResolvedModel) -> Result<(), ModelError> {
+    let config_error = |reason: String| ModelError::InvalidConfig {
+        path: resolved.config_path.clone(),
+        reason,
+    };
+    let tokenizer_error = |e: tokenizers::Error| ModelError::Tokenizer {
+        path: resolved.tokenizer_path.clone(),
+        reason: e.to_string(),
+    };
+
+    let config_contents =
+        std::fs::read_to_string(&resolved.config_path).map_err(|e| config_error(e.to_string()))?;
+    let config: ClipConfig =
+        serde_json::from_str(&config_contents).map_err(|e| config_error(e.to_string()))?;
+
+    // Texts longer than the model's positions are cut, search queries rarely are
+    let mut tokenizer = Tokenizer::from_file(&resolved.tokenizer_path).map_err(tokenizer_error)?;
+    tokenizer
+        .with_truncation(Some(TruncationParams {
+            max_length: config.text_config.max_position_embeddings,
+            ..Default::default()
+        }))
+        .map_err(tokenizer_error)?;
+
+    let device = candle::Device::Cpu;
+    let vb = unsafe {
+        VarBuilder::from_mmaped_safetensors(&[&resolved.weights_path], DType::F32, &device)?
+    };
+    let model = ClipModel::new(vb, &config)?;
+
+    *IMAGE_MODEL.lock().unwrap() = Some(LoadedImageModel {
+        id: resolved.spec.id.clone(),
+        model,
+        tokenizer,
+    });
+    Ok(())
+}
+
+// The ID of the loaded image model, stored alongside every image vector it produces
+pub fn current_image_model_id() -> Option<String> {
+    IMAGE_MODEL.lock().unwrap().as_ref().map(|m| m.id.clone())
+}
+
+// Function to generate the embedding of a frame's image
+pub fn generate_image_embedding(image: &DynamicImage) -> Result<Vec<f32>, EmbedError> {
+    let model_guard = IMAGE_MODEL.lock().unwrap();
+    let loaded = model_guard
+        .as_ref()
+        .ok_or(EmbedError::ModelNotInitialized)?;
+    let vectors = loaded.model.embed_images(std::slice::from_ref(image))?;
+    Ok(l2_normalize(&vectors)?.get(0)?.to_vec1::<f32>()?)
+}
+
+// Function to generate the embedding of a search query for finding frames by their image
+pub fn generate_image_query_embedding(query: &str) -> Result<QueryEmbedding, EmbedError> {
+    let model_guard = IMAGE_MODEL.lock().unwrap();
+    let loaded = model_guard
+        .as_ref()
+        .ok_or(EmbedError::ModelNotInitialized)?;
+    let encoding = loaded
+        .tokenizer
+        .encode(query, true)
+        .map_err(|e| EmbedError::Tokenizer(e.to_string()))?;
+    if encoding.get_ids().is_empty() {
+        return Err(EmbedError::Tokenizer("Query has no tokens".to_string()));
+    }
+    let vector = loaded.model.embed_text(encoding.get_ids())?;
+    Ok(QueryEmbedding {
+        model_id: loaded.id.clone(),
+        vector: l2_normalize(&vector)?.get(0)?.to_vec1::<f32>()?,
+    })
+}
+
+fn l2_normalize(vectors: &Tensor) -> candle::Result<Tensor> {
+    let norms = vectors
+        .sqr()?
+        .sum_keepdim(1)?
+        .sqrt()?
+        .clamp(1e-12, f64::MAX)?;
+    vectors.broadcast_div(&norms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::ModelRegistry;
+    use candle_nn::VarMap;
+    use image::{Rgb, RgbImage};
+    use std::fs;
+
+    // A CLIP model small enough to build in a test, with random weights
+    fn write_tiny_clip(dir: &std::path::Path) {
+        fs::create_dir_all(dir.join("tiny-clip")).unwrap();
+        let config = r#"{
+            "model_type": "clip",
+            "projection_dim": 8,
+            "text_config": {"vocab_size": 6, "hidden_size": 16, "intermediate_size": 32,
+                            "num_hidden_layers": 2, "num_attention_heads": 2,
+                            "max_position_embeddings": 8},
+            "vision_config": {"hidden_size": 16, "intermediate_size": 32, "num_hidden_layers": 2,
+                              "num_attention_heads": 2, "image_size": 32, "patch_size": 8}
+        }"#;
+        fs::write(dir.join("tiny-clip/config.json"), config).unwrap();
+        let tokenizer = r#"{
+            "version": "1.0",
+            "pre_tokenizer": {"type": "Whitespace"},
+            "model": {"type": "WordLevel", "unk_token": "<unk>",
+                      "vocab": {"<unk>": 0, "a": 1, "bar": 2, "chart": 3, "of": 4, "sales": 5}}
+        }"#;
+        fs::write(dir.join("tiny-clip/tokenizer.json"), tokenizer).unwrap();
+
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &candle::Device::Cpu);
+        ClipModel::new(vb, &serde_json::from_str(config).unwrap()).unwrap();
+        varmap
+            .save(dir.join("tiny-clip/model.safetensors"))
+            .unwrap();
+        fs::write(
+            dir.join("manifest.json"),
+            r#"{"models": [{"id": "tiny-clip", "architecture": "clip", "dimensions": 8}]}"#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_images_and_queries_share_a_vector_space() {
+        let dir = std::env::temp_dir().join(format!("xrem-clip-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        write_tiny_clip(&dir);
+
+        ModelRegistry::new(vec![dir.clone()])
+            .unwrap()
+            .load_image_model("tiny-clip")
+            .unwrap();
+        assert_eq!(Some("tiny-clip".to_string()), current_image_model_id());
+
+        // Not the model's input size, it's scaled
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(48, 20, |x, y| {
+            Rgb([(x * 5) as u8, (y * 12) as u8, 128])
+        }));
+        let image_vector = generate_image_embedding(&image).unwrap();
+        let query = generate_image_query_embedding("a bar chart of sales").unwrap();
+        assert_eq!("tiny-clip", query.model_id);
+        for vector in [&image_vector, &query.vector] {
+            assert_eq!(8, vector.len());
+            assert!((super::super::vector::norm(vector) - 1.0).abs() < 1e-4);
+        }
+        assert_ne!(
+            image_vector,
+            generate_image_embedding(&DynamicImage::new_rgb8(32, 32)).unwrap()
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,9 +1,41 @@
+mod ann;
+mod clip;
 mod core;
+mod cursor;
+mod dates;
 mod db;
+mod db_pool;
+mod dhash;
 mod embed;
+mod embed_cache;
+mod embed_worker;
+mod governor;
+mod image_embed;
+mod models;
+mod ocr;
+mod passages;
+mod query;
+mod topics;
+mod vector;
 mod video;
 
 pub use core::start_recording;
 pub use core::CaptureHandles;
-pub use db::DatabaseManager;
+pub use cursor::Cursor;
+pub use dates::{parse_date, parse_time, DATE_EXAMPLES};
+pub use db::{
+    escape_like, DatabaseManager, HybridWeights, LowConfidence, PageKey, ResultCount,
+    SearchFilters, SearchOrder, SearchResult, UsagePeriod,
+};
+pub use db_pool::{DbPool, WriteError};
+pub use embed::{current_model_id, generate_query_embedding, EmbedError, QueryEmbedding};
+pub use embed_worker::{load_embedding_model, start_embed_worker};
+pub use governor::{Governor, GovernorConfig};
+pub use image_embed::generate_image_query_embedding;
+pub use models::{active_image_model_id, active_model_id, model_dirs};
+pub use ocr::OcrWorker;
+pub use passages::Passage;
+pub use query::{parse_query, QueryError, SearchQuery};
+pub use topics::start_topic_job;
+pub use vector::Quantization;
 pub use video::{extract_all_frames_from_video, extract_frames_from_video};
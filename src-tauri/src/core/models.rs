@@ -0,0 +1,487 @@
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::embed;
+use super::image_embed;
+
+// Descriptions of the supported models. A manifest.json in a models directory can add
+// models or override these.
+const BUILTIN_MANIFEST: &str = include_str!("../../models/manifest.json");
+const DEFAULT_MODEL: &str = "gte-small";
+const DEFAULT_IMAGE_MODEL: &str = "clip-vit-base-patch32";
+
+// What kind of network a model is: BERT models embed text, CLIP models embed images and
+// the search queries for them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Architecture {
+    #[default]
+    Bert,
+    Clip,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelSpec {
+    pub id: String,
+    // Directory the files are in, inside a models directory. Defaults to the ID.
+    #[serde(default)]
+    pub dir: Option<String>,
+    pub dimensions: usize,
+    #[serde(default)]
+    pub architecture: Architecture,
+    #[serde(default = "default_config_file")]
+    pub config: String,
+    #[serde(default = "default_tokenizer_file")]
+    pub tokenizer: String,
+    #[serde(default = "default_weights_file")]
+    pub weights: String,
+    #[serde(default)]
+    pub approximate_gelu: bool,
+    // Whether vectors are scaled to unit length
+    #[serde(default = "default_normalize")]
+    pub normalize: bool,
+    // Prepended to search queries, for models trained with an instruction (e.g. bge)
+    #[serde(default)]
+    pub query_prefix: String,
+}
+
+fn default_config_file() -> String {
+    "config.json".to_string()
+}
+
+fn default_tokenizer_file() -> String {
+    "tokenizer.json".to_string()
+}
+
+fn default_weights_file() -> String {
+    "model.safetensors".to_string()
+}
+
+fn default_normalize() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    models: Vec<ModelSpec>,
+}
+
+// A model whose files were found
+#[derive(Debug, Clone)]
+pub struct ResolvedModel {
+    pub spec: ModelSpec,
+    pub config_path: PathBuf,
+    pub tokenizer_path: PathBuf,
+    pub weights_path: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    UnknownModel(String),
+    NotFound {
+        model_id: String,
+        searched: Vec<PathBuf>,
+    },
+    MissingFiles {
+        model_id: String,
+        dir: PathBuf,
+        missing: Vec<String>,
+    },
+    InvalidManifest {
+        path: PathBuf,
+        reason: String,
+    },
+    InvalidConfig {
+        path: PathBuf,
+        reason: String,
+    },
+    DimensionMismatch {
+        model_id: String,
+        expected: usize,
+        found: usize,
+    },
+    WrongArchitecture {
+        model_id: String,
+        expected: Architecture,
+    },
+    InvalidWeights {
+        path: PathBuf,
+        reason: String,
+    },
+    Tokenizer {
+        path: PathBuf,
+        reason: String,
+    },
+    Load(candle::Error),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::UnknownModel(id) => write!(f, "Unknown model {}", id),
+            ModelError::NotFound { model_id, searched } => write!(
+                f,
+                "Model {} not found in {}",
+                model_id,
+                searched
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ModelError::MissingFiles {
+                model_id,
+                dir,
+                missing,
+            } => write!(
+                f,
+                "Model {} in {} is missing {}",
+                model_id,
+                dir.display(),
+                missing.join(", ")
+            ),
+            ModelError::InvalidManifest { path, reason } => {
+                write!(f, "Invalid manifest {}: {}", path.display(), reason)
+            }
+            ModelError::InvalidConfig { path, reason } => {
+                write!(f, "Invalid model config {}: {}", path.display(), reason)
+            }
+            ModelError::DimensionMismatch {
+                model_id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Model {} should have {} dimensions but its config has {}",
+                model_id, expected, found
+            ),
+            ModelError::WrongArchitecture { model_id, expected } => {
+                write!(f, "Model {} is not a {:?} model", model_id, expected)
+            }
+            ModelError::InvalidWeights { path, reason } => {
+                write!(f, "Invalid weights {}: {}", path.display(), reason)
+            }
+            ModelError::Tokenizer { path, reason } => {
+                write!(f, "Invalid tokenizer {}: {}", path.display(), reason)
+            }
+            ModelError::Load(e) => write!(f, "Failed to load model: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<candle::Error> for ModelError {
+    fn from(e: candle::Error) -> Self {
+        ModelError::Load(e)
+    }
+}
+
+// The embedding models xrem knows about and the directories their files are looked up in,
+// in order of preference
+pub struct ModelRegistry {
+    specs: Vec<ModelSpec>,
+    search_dirs: Vec<PathBuf>,
+}
+
+impl ModelRegistry {
+    pub fn new(search_dirs: Vec<PathBuf>) -> Result<ModelRegistry, ModelError> {
+        let builtin: Manifest =
+            serde_json::from_str(BUILTIN_MANIFEST).map_err(|e| ModelError::InvalidManifest {
+                path: PathBuf::from("manifest.json"),
+                reason: e.to_string(),
+            })?;
+        let mut registry = ModelRegistry {
+            specs: builtin.models,
+            search_dirs,
+        };
+
+        // Lowest priority first, so earlier directories win
+        for dir in registry.search_dirs.clone().iter().rev() {
+            let path = dir.join("manifest.json");
+            if !path.is_file() {
+                continue;
+            }
+            let manifest: Manifest = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+                .map_err(|reason| ModelError::InvalidManifest {
+                    path: path.clone(),
+                    reason,
+                })?;
+            for spec in manifest.models {
+                registry.specs.retain(|s| s.id != spec.id);
+                registry.specs.push(spec);
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn models(&self) -> &[ModelSpec] {
+        &self.specs
+    }
+
+    pub fn get(&self, model_id: &str) -> Option<&ModelSpec> {
+        self.specs.iter().find(|s| s.id == model_id)
+    }
+
+    // Finds the first directory with all of a model's files and checks they're usable
+    pub fn resolve(&self, model_id: &str) -> Result<ResolvedModel, ModelError> {
+        let spec = self
+            .get(model_id)
+            .ok_or_else(|| ModelError::UnknownModel(model_id.to_string()))?;
+        let dir_name = spec.dir.as_deref().unwrap_or(&spec.id);
+
+        let mut incomplete = None;
+        for search_dir in &self.search_dirs {
+            let dir = search_dir.join(dir_name);
+            if !dir.is_dir() {
+                continue;
+            }
+            let missing = [&spec.config, &spec.tokenizer, &spec.weights]
+                .into_iter()
+                .filter(|file| !dir.join(file).is_file())
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                incomplete.get_or_insert(ModelError::MissingFiles {
+                    model_id: spec.id.clone(),
+                    dir,
+                    missing,
+                });
+                continue;
+            }
+
+            let resolved = ResolvedModel {
+                spec: spec.clone(),
+                config_path: dir.join(&spec.config),
+                tokenizer_path: dir.join(&spec.tokenizer),
+                weights_path: dir.join(&spec.weights),
+            };
+            resolved.validate()?;
+            return Ok(resolved);
+        }
+
+        Err(incomplete.unwrap_or_else(|| ModelError::NotFound {
+            model_id: spec.id.clone(),
+            searched: self.search_dirs.clone(),
+        }))
+    }
+
+    // Resolves a model and makes it the one used for embeddings
+    pub fn load(&self, model_id: &str) -> Result<(), ModelError> {
+        embed::init_model(&self.resolve_architecture(model_id, Architecture::Bert)?)
+    }
+
+    // Resolves a model and uses it for search queries until a re-index finishes
+    pub fn load_fallback(&self, model_id: &str) -> Result<(), ModelError> {
+        embed::init_fallback_model(&self.resolve_architecture(model_id, Architecture::Bert)?)
+    }
+
+    // Resolves a model and makes it the one used for frame images
+    pub fn load_image_model(&self, model_id: &str) -> Result<(), ModelError> {
+        image_embed::init_image_model(&self.resolve_architecture(model_id, Architecture::Clip)?)
+    }
+
+    fn resolve_architecture(
+        &self,
+        model_id: &str,
+        expected: Architecture,
+    ) -> Result<ResolvedModel, ModelError> {
+        let resolved = self.resolve(model_id)?;
+        if resolved.spec.architecture != expected {
+            return Err(ModelError::WrongArchitecture {
+                model_id: model_id.to_string(),
+                expected,
+            });
+        }
+        Ok(resolved)
+    }
+}
+
+impl ResolvedModel {
+    // Checks the config is for a model of the spec's architecture and size and the weights
+    // file is safetensors, without loading the weights
+    fn validate(&self) -> Result<(), ModelError> {
+        let invalid_config = |reason: String| ModelError::InvalidConfig {
+            path: self.config_path.clone(),
+            reason,
+        };
+        let contents =
+            fs::read_to_string(&self.config_path).map_err(|e| invalid_config(e.to_string()))?;
+        let config: serde_json::Value =
+            serde_json::from_str(&contents).map_err(|e| invalid_config(e.to_string()))?;
+        // The size of the vectors, CLIP projects both encoders to the same size
+        let (model_type, size_key) = match self.spec.architecture {
+            Architecture::Bert => ("bert", "hidden_size"),
+            Architecture::Clip => ("clip", "projection_dim"),
+        };
+        match config["model_type"].as_str() {
+            Some(found) if found == model_type => {}
+            other => {
+                return Err(invalid_config(format!(
+                    "Unsupported model type {}",
+                    other.unwrap_or("(none)")
+                )))
+            }
+        }
+        let size = config[size_key]
+            .as_u64()
+            .ok_or_else(|| invalid_config(format!("No {}", size_key)))? as usize;
+        if size != self.spec.dimensions {
+            return Err(ModelError::DimensionMismatch {
+                model_id: self.spec.id.clone(),
+                expected: self.spec.dimensions,
+                found: size,
+            });
+        }
+
+        validate_safetensors(&self.weights_path).map_err(|reason| ModelError::InvalidWeights {
+            path: self.weights_path.clone(),
+            reason,
+        })
+    }
+}
+
+// A safetensors file starts with the length of its JSON header
+fn validate_safetensors(path: &Path) -> Result<(), String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let file_length = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut length = [0u8; 8];
+    file.read_exact(&mut length)
+        .map_err(|_| "File is too short".to_string())?;
+    let header_length = u64::from_le_bytes(length);
+    if header_length == 0 || header_length > file_length - 8 {
+        return Err("Not a safetensors file".to_string());
+    }
+    let mut header = vec![0u8; header_length as usize];
+    file.read_exact(&mut header).map_err(|e| e.to_string())?;
+    serde_json::from_slice::<serde_json::Value>(&header)
+        .map(|_| ())
+        .map_err(|_| "Not a safetensors file".to_string())
+}
+
+// Directories models are looked up in: XREM_MODELS_DIR if set, then the app's data directory,
+// then the app's bundled resources, then `models` in the working directory (for development)
+pub fn model_dirs(local_data_dir: &Path, resource_dir: Option<PathBuf>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Ok(dir) = std::env::var("XREM_MODELS_DIR") {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs.push(local_data_dir.join("models"));
+    if let Some(dir) = resource_dir {
+        dirs.push(dir.join("models"));
+    }
+    dirs.push(PathBuf::from("models"));
+    dirs
+}
+
+// The model to embed with, from XREM_EMBEDDING_MODEL or the default
+pub fn active_model_id() -> String {
+    std::env::var("XREM_EMBEDDING_MODEL")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+// The model to embed frame images with, from XREM_IMAGE_MODEL or the default
+pub fn active_image_model_id() -> String {
+    std::env::var("XREM_IMAGE_MODEL")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| DEFAULT_IMAGE_MODEL.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_model(dir: &Path, hidden_size: usize, weights: &[u8]) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(
+            dir.join("config.json"),
+            format!(
+                r#"{{"model_type": "bert", "hidden_size": {}}}"#,
+                hidden_size
+            ),
+        )
+        .unwrap();
+        fs::write(dir.join("tokenizer.json"), "{}").unwrap();
+        fs::write(dir.join("model.safetensors"), weights).unwrap();
+    }
+
+    fn safetensors() -> Vec<u8> {
+        let header = b"{}";
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header);
+        bytes
+    }
+
+    #[test]
+    fn test_resolve_searches_directories_in_order() {
+        let root = std::env::temp_dir().join(format!("xrem-models-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (user, bundled) = (root.join("user"), root.join("bundled"));
+        // Incomplete in the first directory, so the bundled copy is used
+        fs::create_dir_all(user.join("gte-small")).unwrap();
+        write_model(&bundled.join("gte-small"), 384, &safetensors());
+        write_model(&bundled.join("gte-base"), 384, &safetensors());
+        write_model(&bundled.join("all-MiniLM-L6-v2"), 384, b"not weights");
+
+        let registry = ModelRegistry::new(vec![user.clone(), bundled.clone()]).unwrap();
+        let resolved = registry.resolve("gte-small").unwrap();
+        assert_eq!(
+            bundled.join("gte-small/model.safetensors"),
+            resolved.weights_path
+        );
+
+        assert!(matches!(
+            registry.resolve("gte-base"),
+            Err(ModelError::DimensionMismatch { found: 384, .. })
+        ));
+        assert!(matches!(
+            registry.resolve("all-MiniLM-L6-v2"),
+            Err(ModelError::InvalidWeights { .. })
+        ));
+        assert!(matches!(
+            registry.resolve("bge-small-en-v1.5"),
+            Err(ModelError::NotFound { .. })
+        ));
+        assert!(matches!(
+            registry.resolve("nope"),
+            Err(ModelError::UnknownModel(_))
+        ));
+
+        // Only the first directory, where the model is incomplete
+        let registry = ModelRegistry::new(vec![user.clone()]).unwrap();
+        assert!(matches!(
+            registry.resolve("gte-small"),
+            Err(ModelError::MissingFiles { .. })
+        ));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_manifest_in_models_dir_adds_models() {
+        let root = std::env::temp_dir().join(format!("xrem-manifest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join("manifest.json"),
+            r#"{"models": [{"id": "e5-small", "dir": "e5", "dimensions": 384, "normalize": false}]}"#,
+        )
+        .unwrap();
+
+        let registry = ModelRegistry::new(vec![root.clone()]).unwrap();
+        let spec = registry.get("e5-small").unwrap();
+        assert_eq!(Some("e5"), spec.dir.as_deref());
+        assert!(!spec.normalize);
+        assert!(registry.get("gte-small").unwrap().normalize);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::core::db::OcrWord;
+use crate::core::dhash::dhash;
+use crate::core::{extract_frames_from_video, DatabaseManager, DbPool, Governor, WriteError};
+use image::DynamicImage;
+use rusty_tesseract::{image_to_data, Args, Image};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use threadpool::ThreadPool;
+
+const OCR_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// How long a queued frame waits before the backlog picks it up, or before a failed frame is retried
+const OCR_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_OCR_ATTEMPTS: i64 = 3;
+// Tesseract's `level` for a word, the other levels are pages, blocks, paragraphs and lines
+const WORD_LEVEL: i32 = 5;
+// Lines below this mean confidence (0-100) are dropped
+const MIN_LINE_CONFIDENCE: f32 = 40.0;
+// Lines where fewer than this share of words look like real words are dropped
+const MIN_WORD_LIKE_RATIO: f32 = 0.5;
+
+struct OcrOutput {
+    text: String,
+    confidence: f32,
+    words: Vec<OcrWord>,
+}
+
+// Runs OCR for frames queued in the database. Freshly captured frames are handed over with
+// their image, anything else (a backlog after a restart, re-OCR requests, retries) is decoded
+// from its video chunk. While the governor reports the machine as busy, frames are left
+// in the queue and caught up on later.
+pub struct OcrWorker {
+    db: DbPool,
+    governor: Arc<Governor>,
+    pool: Mutex<ThreadPool>,
+}
+
+impl OcrWorker {
+    pub fn start(db: DbPool, governor: Arc<Governor>) -> Arc<OcrWorker> {
+        let pool = ThreadPool::new(governor.ocr_workers());
+        let worker = Arc::new(OcrWorker {
+            db,
+            governor,
+            pool: Mutex::new(pool),
+        });
+
+        let backlog_worker = worker.clone();
+        thread::spawn(move || backlog_worker.run_backlog());
+
+        worker
+    }
+
+    // Submit a freshly captured frame, if it's no longer pending this is a no-op
+    pub fn submit(&self, frame_id: i64, image: DynamicImage) {
+        if self.governor.is_busy() {
+            // Leave it queued, it'll be decoded from its chunk once there's CPU to spare
+            return;
+        }
+        match self.db.write(move |db| db.claim_ocr_job(frame_id)) {
+            Ok(Some(job)) => self.execute(job.frame_id, Some(image)),
+            Ok(None) | Err(WriteError::NotOpen) => {}
+            Err(e) => println!("Failed to claim OCR job for frame {}: {}", frame_id, e),
+        }
+    }
+
+    fn run_backlog(&self) {
+        loop {
+            let pool = {
+                let mut pool = self.pool.lock().unwrap();
+                pool.set_num_threads(self.governor.ocr_workers());
+                pool.clone()
+            };
+            // Only pull from the backlog when the pool is idle, live frames take priority
+            if pool.queued_count() == 0 && !self.governor.is_busy() {
+                let limit = pool.max_count() as i64;
+                match self
+                    .db
+                    .write(move |db| db.claim_ocr_jobs(limit, OCR_RETRY_DELAY))
+                {
+                    Ok(jobs) => {
+                        for job in jobs {
+                            self.execute(job.frame_id, None);
+                        }
+                    }
+                    Err(WriteError::NotOpen) => {}
+                    Err(e) => println!("Failed to claim OCR jobs: {}", e),
+                }
+            }
+            thread::sleep(OCR_POLL_INTERVAL);
+        }
+    }
+
+    fn execute(&self, frame_id: i64, image: Option<DynamicImage>) {
+        let db = self.db.clone();
+        self.pool
+            .lock()
+            .unwrap()
+            .execute(move || process_frame(&db, frame_id, image));
+    }
+}
+
+fn process_frame(db: &DbPool, frame_id: i64, image: Option<DynamicImage>) {
+    let image = match image {
+        Some(image) => image,
+        None => match load_frame_image(db, frame_id) {
+            Ok(Some(image)) => image,
+            Ok(None) => {
+                // The frame's chunk hasn't been written yet, try again later
+                with_db(db, move |db| db.release_ocr_job(frame_id));
+                return;
+            }
+            Err(e) => {
+                println!("Failed to load frame {} for OCR: {}", frame_id, e);
+                with_db(db, move |db| {
+                    db.fail_ocr_job(frame_id, &e, MAX_OCR_ATTEMPTS)
+                });
+                return;
+            }
+        },
+    };
+
+    match perform_ocr(&image) {
+        Ok(output) => {
+            let hash = dhash(&image);
+            with_db(db, move |db| {
+                db.set_frame_dhash(frame_id, hash)?;
+                if !output.text.is_empty() {
+                    db.insert_text_for_frame(frame_id, &output.text)?;
+                }
+                db.insert_ocr_result(frame_id, output.confidence, &output.words)?;
+                db.complete_ocr_job(frame_id)
+            });
+        }
+        Err(e) => {
+            println!("OCR Failed! {:?}", e);
+            let error = e.to_string();
+            with_db(db, move |db| {
+                db.fail_ocr_job(frame_id, &error, MAX_OCR_ATTEMPTS)
+            });
+        }
+    }
+}
+
+// Decodes a frame from its video chunk, None if the chunk is still being written
+pub fn load_frame_image(db: &DbPool, frame_id: i64) -> Result<Option<DynamicImage>, String> {
+    let (frame, is_chunk_pending) = db
+        .read(|db| {
+            Ok::<_, rusqlite::Error>((
+                db.get_frame(frame_id)?,
+                db.is_frame_chunk_pending(frame_id)?,
+            ))
+        })
+        .ok_or("Database not initialized")?
+        .map_err(|e| e.to_string())?;
+
+    if is_chunk_pending {
+        return Ok(None);
+    }
+    match frame {
+        Some((offset_index, video_path)) => extract_frames_from_video(&video_path, &[offset_index])
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .next()
+            .map(Some)
+            .ok_or_else(|| format!("Frame {} not found in {}", offset_index, video_path)),
+        None => Err("Video chunk was never written".to_string()),
+    }
+}
+
+// Runs `f` on the writer, OCR threads only wait for their own updates to be committed
+fn with_db<F>(db: &DbPool, f: F)
+where
+    F: FnOnce(&DatabaseManager) -> rusqlite::Result<()> + Send + 'static,
+{
+    match db.write(move |db| f(db)) {
+        Ok(()) | Err(WriteError::NotOpen) => {}
+        Err(e) => println!("Failed to update OCR queue: {}", e),
+    }
+}
+
+fn perform_ocr(dynamic_image: &DynamicImage) -> Result<OcrOutput, Box<dyn std::error::Error>> {
+    let args = Args::default();
+    let image = Image::from_dynamic_image(dynamic_image)?;
+
+    // OCR
+    let data = image_to_data(&image, &args)?;
+    let words = data
+        .data
+        .into_iter()
+        .filter(|d| d.level == WORD_LEVEL && d.conf >= 0.0 && !d.text.trim().is_empty())
+        .map(|d| OcrWord {
+            text: d.text,
+            confidence: d.conf,
+            block_num: d.block_num,
+            par_num: d.par_num,
+            line_num: d.line_num,
+            left: d.left,
+            top: d.top,
+            width: d.width,
+            height: d.height,
+        })
+        .collect::<Vec<_>>();
+
+    let output = filter_noise(words);
+    println!("OCR ({:.1}): {}", output.confidence, output.text);
+
+    Ok(output)
+}
+
+// Drops lines that are mostly noise (low confidence, or not made of word-like tokens) and
+// joins what's left into text, one line per OCR line and a blank line between paragraphs
+fn filter_noise(words: Vec<OcrWord>) -> OcrOutput {
+    let mut lines: Vec<Vec<OcrWord>> = Vec::new();
+    for word in words {
+        match lines.last_mut() {
+            Some(line) if same_line(&line[0], &word) => line.push(word),
+            _ => lines.push(vec![word]),
+        }
+    }
+
+    let kept = lines
+        .into_iter()
+        .filter(|line| {
+            let confidence = mean_confidence(line);
+            let word_like = line.iter().filter(|w| is_word_like(&w.text)).count();
+            confidence >= MIN_LINE_CONFIDENCE
+                && word_like as f32 >= line.len() as f32 * MIN_WORD_LIKE_RATIO
+        })
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    let mut previous: Option<&OcrWord> = None;
+    for line in &kept {
+        if let Some(previous) = previous {
+            let new_paragraph =
+                previous.block_num != line[0].block_num || previous.par_num != line[0].par_num;
+            text.push_str(if new_paragraph { "\n\n" } else { "\n" });
+        }
+        let line_text = line.iter().map(|w| w.text.as_str()).collect::<Vec<_>>();
+        text.push_str(&line_text.join(" "));
+        previous = Some(&line[0]);
+    }
+
+    let words = kept.into_iter().flatten().collect::<Vec<_>>();
+    OcrOutput {
+        text,
+        confidence: mean_confidence(&words),
+        words,
+    }
+}
+
+fn same_line(a: &OcrWord, b: &OcrWord) -> bool {
+    a.block_num == b.block_num && a.par_num == b.par_num && a.line_num == b.line_num
+}
+
+fn mean_confidence(words: &[OcrWord]) -> f32 {
+    if words.is_empty() {
+        return 0.0;
+    }
+    words.iter().map(|w| w.confidence).sum::<f32>() / words.len() as f32
+}
+
+// Heuristic for "could be a dictionary word, number or identifier" as opposed to the
+// strings tesseract produces for icons, borders and image content
+fn is_word_like(token: &str) -> bool {
+    let token = token.trim_matches(|c: char| !c.is_alphanumeric());
+    if token.is_empty() {
+        return false;
+    }
+
+    let chars = token.chars().collect::<Vec<_>>();
+    let letters = chars.iter().filter(|c| c.is_alphabetic()).count();
+    let digits = chars.iter().filter(|c| c.is_numeric()).count();
+    if letters == 0 {
+        return digits * 2 >= chars.len();
+    }
+    if (letters + digits) * 10 < chars.len() * 6 {
+        return false;
+    }
+
+    // Same character four or more times in a row, e.g. "lllll" from a border
+    if chars.windows(4).any(|w| w.iter().all(|c| *c == w[0])) {
+        return false;
+    }
+
+    // Vowel checks only make sense for latin script, and not for acronyms like HTTP or API
+    let is_acronym = chars
+        .iter()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+    let is_latin = chars
+        .iter()
+        .all(|c| !c.is_alphabetic() || c.is_ascii_alphabetic());
+    if is_latin && !is_acronym {
+        let is_vowel = |c: &char| "aeiouyAEIOUY".contains(*c);
+        if letters > 3 && !chars.iter().any(is_vowel) {
+            return false;
+        }
+        let mut consonant_run = 0;
+        for c in &chars {
+            if c.is_ascii_alphabetic() && !is_vowel(c) {
+                consonant_run += 1;
+                if consonant_run >= 6 {
+                    return false;
+                }
+            } else {
+                consonant_run = 0;
+            }
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str, confidence: f32, par_num: i32, line_num: i32) -> OcrWord {
+        OcrWord {
+            text: text.to_string(),
+            confidence,
+            block_num: 1,
+            par_num,
+            line_num,
+            left: 0,
+            top: 0,
+            width: 10,
+            height: 10,
+        }
+    }
+
+    #[test]
+    fn test_is_word_like() {
+        for token in [
+            "hello",
+            "Hello,",
+            "2024",
+            "snake_case",
+            "v1.2.3",
+            "(hi)",
+            "Привет",
+            "HTTP",
+            "HTML",
+            "API",
+            "MP4",
+        ] {
+            assert!(is_word_like(token), "{}", token);
+        }
+        for token in ["", "|", "~~~", "lllll", "xkcdfgh", "bcdf", "a%&*#b"] {
+            assert!(!is_word_like(token), "{}", token);
+        }
+    }
+
+    #[test]
+    fn test_filter_noise_drops_noisy_lines() {
+        let words = vec![
+            word("Quarterly", 91.0, 1, 1),
+            word("report", 88.0, 1, 1),
+            word("~~~", 30.0, 1, 2),
+            word("xkcdfgh", 60.0, 1, 2),
+            word("revenue", 85.0, 1, 3),
+            word("grew", 90.0, 2, 1),
+            word("blurry", 12.0, 2, 2),
+        ];
+        let output = filter_noise(words);
+        assert_eq!("Quarterly report\nrevenue\n\ngrew", output.text);
+        assert_eq!(4, output.words.len());
+        assert!(output.confidence > 85.0);
+    }
+}
@@ -0,0 +1,313 @@
+use super::db::OcrWord;
+
+// Passages are kept well under the 512 token limit of BERT models, OCR text of code or
+// odd fonts can tokenize to one token per two or three characters
+const MAX_PASSAGE_CHARS: usize = 800;
+// Paragraphs shorter than this are merged into the next passage
+const MIN_PASSAGE_CHARS: usize = 200;
+// Text repeated from the end of a passage at the start of the next when a paragraph is cut
+const OVERLAP_CHARS: usize = 150;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub left: i32,
+    pub top: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Region {
+    fn union(self, other: Region) -> Region {
+        let left = self.left.min(other.left);
+        let top = self.top.min(other.top);
+        let right = (self.left + self.width).max(other.left + other.width);
+        let bottom = (self.top + self.height).max(other.top + other.height);
+        Region {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+}
+
+// A piece of a frame's text that is embedded on its own. `start` and `end` are character
+// offsets into the frame's text, `region` is where it is on screen if the OCR layout is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    pub region: Option<Region>,
+}
+
+// A line of the text (or part of one, for very long lines), with byte offsets
+struct Unit {
+    start: usize,
+    end: usize,
+    // Whether a new paragraph or screen region starts here
+    break_before: bool,
+    region: Option<Region>,
+}
+
+// Splits a frame's text into overlapping passages. Passages end at paragraph breaks, or where
+// the OCR layout shows a gap or a jump to another part of the screen, once they're long
+// enough; a passage that gets too long is cut at a line and the next repeats its last lines.
+pub fn split_passages(text: &str, words: &[OcrWord]) -> Vec<Passage> {
+    let units = layout_units(text, words);
+    let mut spans = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+
+    for (index, unit) in units.iter().enumerate() {
+        if let Some(&first) = current.first() {
+            let length = units[*current.last().unwrap()].end - units[first].start;
+            let at_break = unit.break_before && length >= MIN_PASSAGE_CHARS;
+            if at_break || unit.end - units[first].start > MAX_PASSAGE_CHARS {
+                spans.push((units[first].start, units[*current.last().unwrap()].end));
+                if at_break {
+                    current.clear();
+                } else {
+                    current = overlap(&units, &current, unit);
+                }
+            }
+        }
+        current.push(index);
+    }
+    if let (Some(&first), Some(&last)) = (current.first(), current.last()) {
+        spans.push((units[first].start, units[last].end));
+    }
+
+    spans
+        .into_iter()
+        .map(|(start, end)| Passage {
+            text: text[start..end].to_string(),
+            start: text[..start].chars().count(),
+            end: text[..end].chars().count(),
+            region: region_of(&units, start, end),
+        })
+        .collect()
+}
+
+// Where a character span of a frame's text is on screen
+pub fn passage_region(text: &str, words: &[OcrWord], start: usize, end: usize) -> Option<Region> {
+    let byte_offset = |chars: usize| {
+        text.char_indices()
+            .nth(chars)
+            .map(|(i, _)| i)
+            .unwrap_or(text.len())
+    };
+    region_of(
+        &layout_units(text, words),
+        byte_offset(start),
+        byte_offset(end),
+    )
+}
+
+// Trailing units of the passage to repeat at the start of the next one
+fn overlap(units: &[Unit], current: &[usize], next: &Unit) -> Vec<usize> {
+    let end = units[*current.last().unwrap()].end;
+    let mut kept = current
+        .iter()
+        .skip(1)
+        .copied()
+        .skip_while(|&i| end - units[i].start > OVERLAP_CHARS)
+        .collect::<Vec<_>>();
+    while kept
+        .first()
+        .map(|&i| next.end - units[i].start > MAX_PASSAGE_CHARS)
+        .unwrap_or(false)
+    {
+        kept.remove(0);
+    }
+    kept
+}
+
+fn region_of(units: &[Unit], start: usize, end: usize) -> Option<Region> {
+    units
+        .iter()
+        .filter(|unit| unit.start < end && unit.end > start)
+        .filter_map(|unit| unit.region)
+        .reduce(Region::union)
+}
+
+fn layout_units(text: &str, words: &[OcrWord]) -> Vec<Unit> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    let mut after_blank = false;
+    for line in text.split('\n') {
+        let start = offset;
+        offset += line.len() + 1;
+        if line.trim().is_empty() {
+            after_blank = true;
+            continue;
+        }
+        lines.push((start, start + line.len(), after_blank));
+        after_blank = false;
+    }
+
+    let ocr_lines = ocr_lines(words);
+    // The layout can only be used if the OCR lines are the lines of the text
+    let aligned = ocr_lines.len() == lines.len()
+        && ocr_lines
+            .iter()
+            .zip(&lines)
+            .all(|(ocr_line, (start, end, _))| ocr_line.0 == text[*start..*end]);
+
+    let mut units = Vec::new();
+    for (index, (start, end, after_blank)) in lines.iter().copied().enumerate() {
+        let region = if aligned {
+            Some(ocr_lines[index].1)
+        } else {
+            None
+        };
+        let layout_break =
+            aligned && index > 0 && is_layout_break(ocr_lines[index - 1].1, ocr_lines[index].1);
+        let mut break_before = after_blank || layout_break;
+        for (piece_start, piece_end) in split_long_line(text, start, end) {
+            units.push(Unit {
+                start: piece_start,
+                end: piece_end,
+                break_before,
+                region,
+            });
+            break_before = false;
+        }
+    }
+    units
+}
+
+// The text and bounding box of each OCR line, in the order they appear in the text
+fn ocr_lines(words: &[OcrWord]) -> Vec<(String, Region)> {
+    let mut lines: Vec<(String, Region, &OcrWord)> = Vec::new();
+    for word in words {
+        let region = Region {
+            left: word.left,
+            top: word.top,
+            width: word.width,
+            height: word.height,
+        };
+        match lines.last_mut() {
+            Some((text, line_region, first))
+                if first.block_num == word.block_num
+                    && first.par_num == word.par_num
+                    && first.line_num == word.line_num =>
+            {
+                text.push(' ');
+                text.push_str(&word.text);
+                *line_region = line_region.union(region);
+            }
+            _ => lines.push((word.text.clone(), region, word)),
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(text, region, _)| (text, region))
+        .collect()
+}
+
+// A gap of more than a line's height, or moving back up the screen (another column or panel)
+fn is_layout_break(previous: Region, line: Region) -> bool {
+    let gap = line.top - (previous.top + previous.height);
+    gap > previous.height.max(line.height) || line.top + line.height <= previous.top
+}
+
+// Splits a line longer than a passage at spaces, or anywhere if it has none
+fn split_long_line(text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut pieces = Vec::new();
+    let mut piece_start = start;
+    while end - piece_start > MAX_PASSAGE_CHARS {
+        let mut limit = piece_start + MAX_PASSAGE_CHARS;
+        while !text.is_char_boundary(limit) {
+            limit -= 1;
+        }
+        let cut = text[piece_start..limit]
+            .rfind(' ')
+            .filter(|&i| i > 0)
+            .map(|i| piece_start + i)
+            .unwrap_or(limit);
+        pieces.push((piece_start, cut));
+        piece_start = cut + text[cut..].len() - text[cut..].trim_start().len();
+    }
+    pieces.push((piece_start, end));
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(text: &str, par_num: i32, line_num: i32, top: i32) -> OcrWord {
+        OcrWord {
+            text: text.to_string(),
+            confidence: 90.0,
+            block_num: 1,
+            par_num,
+            line_num,
+            left: 10,
+            top,
+            width: 100,
+            height: 10,
+        }
+    }
+
+    #[test]
+    fn test_long_text_is_split_with_overlap() {
+        let lines = (0..60)
+            .map(|i| format!("line {} of a long document about embeddings", i))
+            .collect::<Vec<_>>();
+        let text = lines.join("\n");
+        let passages = split_passages(&text, &[]);
+
+        assert!(passages.len() > 1);
+        assert_eq!(0, passages[0].start);
+        assert_eq!(text.chars().count(), passages.last().unwrap().end);
+        for passage in &passages {
+            assert!(passage.text.chars().count() <= MAX_PASSAGE_CHARS);
+            assert_eq!(
+                passage.text,
+                text.chars()
+                    .skip(passage.start)
+                    .take(passage.end - passage.start)
+                    .collect::<String>()
+            );
+        }
+        for pair in passages.windows(2) {
+            assert!(pair[1].start < pair[0].end, "passages should overlap");
+        }
+    }
+
+    #[test]
+    fn test_layout_gaps_start_new_passages() {
+        let header = "Inbox ".repeat(40);
+        let words = vec![
+            word(header.trim_end(), 1, 1, 0),
+            word("Meeting", 1, 2, 300),
+            word("notes", 1, 2, 300),
+        ];
+        let text = format!("{}\nMeeting notes", header.trim_end());
+        let passages = split_passages(&text, &words);
+
+        assert_eq!(2, passages.len());
+        assert_eq!("Meeting notes", passages[1].text);
+        assert_eq!(
+            Some(Region {
+                left: 10,
+                top: 300,
+                width: 100,
+                height: 10
+            }),
+            passages[1].region
+        );
+        assert_eq!(
+            passages[1].region,
+            passage_region(&text, &words, passages[1].start, passages[1].end)
+        );
+    }
+
+    #[test]
+    fn test_short_paragraphs_are_merged() {
+        let passages = split_passages("Title\n\nA short paragraph\n\nAnother one", &[]);
+        assert_eq!(1, passages.len());
+        assert_eq!(None, passages[0].region);
+    }
+}
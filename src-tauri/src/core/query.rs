@@ -0,0 +1,267 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone};
+use std::fmt;
+
+use super::dates::{parse_date, DATE_EXAMPLES};
+
+const APP: &str = "app";
+const AFTER: &str = "after";
+const BEFORE: &str = "before";
+
+// A search typed as words, "quoted phrases", -excluded words and filters, e.g.
+// `app:firefox after:2026-10-01 before:"last friday" "exact phrase" -slack`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchQuery {
+    // Words and phrases a frame's text must all contain
+    pub terms: Vec<String>,
+    // Words and phrases a frame's text must not contain
+    pub excluded: Vec<String>,
+    pub app: Option<String>,
+    // UTC, like the frame timestamps
+    pub after: Option<NaiveDateTime>,
+    pub before: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QueryError {
+    // Character position of the opening quote, counting from 1
+    UnterminatedQuote(usize),
+    NothingToExclude(usize),
+    MissingValue(&'static str),
+    InvalidDate { filter: &'static str, value: String },
+    RepeatedFilter(&'static str),
+    ExcludedFilter(&'static str),
+    EmptyRange,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::UnterminatedQuote(position) => write!(
+                f,
+                "The quote at character {} is never closed, end the phrase with another \"",
+                position
+            ),
+            QueryError::NothingToExclude(position) => write!(
+                f,
+                "Nothing to exclude after the - at character {}, put the word right after it",
+                position
+            ),
+            QueryError::MissingValue(filter) => write!(f, "{}: needs a value", filter),
+            QueryError::InvalidDate { filter, value } => write!(
+                f,
+                "\"{}\" isn't a date {}: understands, try {}",
+                value, filter, DATE_EXAMPLES
+            ),
+            QueryError::RepeatedFilter(filter) => {
+                write!(f, "{}: can only be used once", filter)
+            }
+            QueryError::ExcludedFilter(filter) => {
+                write!(f, "{}: can't be excluded with -", filter)
+            }
+            QueryError::EmptyRange => write!(f, "after: has to be earlier than before:"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl SearchQuery {
+    // The words and phrases to search for by meaning
+    pub fn text(&self) -> String {
+        self.terms.join(" ")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SearchQuery::default()
+    }
+}
+
+// A word or phrase, possibly excluded or the value of a filter
+struct Token {
+    excluded: bool,
+    filter: Option<&'static str>,
+    value: String,
+}
+
+// Parses a search, reading dates relative to `now` and in its time zone. Words that look
+// like filters but aren't one (e.g. `localhost:8080`) are searched for as they are.
+pub fn parse_query<Tz: TimeZone>(
+    input: &str,
+    now: &DateTime<Tz>,
+) -> Result<SearchQuery, QueryError> {
+    let mut query = SearchQuery::default();
+    for token in tokenize(input)? {
+        let filter = match token.filter {
+            Some(filter) => filter,
+            None => {
+                if token.value.is_empty() {
+                    // An empty phrase matches everything
+                } else if token.excluded {
+                    query.excluded.push(token.value);
+                } else {
+                    query.terms.push(token.value);
+                }
+                continue;
+            }
+        };
+        if token.excluded {
+            return Err(QueryError::ExcludedFilter(filter));
+        }
+        if token.value.is_empty() {
+            return Err(QueryError::MissingValue(filter));
+        }
+        let date = || {
+            parse_date(&token.value, now).ok_or_else(|| QueryError::InvalidDate {
+                filter,
+                value: token.value.clone(),
+            })
+        };
+        let repeated = match filter {
+            APP => query.app.replace(token.value.clone()).is_some(),
+            AFTER => query.after.replace(date()?).is_some(),
+            _ => query.before.replace(date()?).is_some(),
+        };
+        if repeated {
+            return Err(QueryError::RepeatedFilter(filter));
+        }
+    }
+    if let (Some(after), Some(before)) = (query.after, query.before) {
+        if after >= before {
+            return Err(QueryError::EmptyRange);
+        }
+    }
+    Ok(query)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, QueryError> {
+    let chars = input.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let position = i + 1;
+        let excluded = chars[i] == '-';
+        if excluded {
+            i += 1;
+        }
+
+        // A bare word runs up to whitespace or a quote
+        let start = i;
+        while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '"' {
+            i += 1;
+        }
+        let word = chars[start..i].iter().collect::<String>();
+        let (filter, mut value) = match word.split_once(':') {
+            Some((name, value)) => match [APP, AFTER, BEFORE]
+                .into_iter()
+                .find(|filter| filter.eq_ignore_ascii_case(name))
+            {
+                Some(filter) => (Some(filter), value.to_string()),
+                None => (None, word.clone()),
+            },
+            None => (None, word.clone()),
+        };
+
+        // A quoted phrase, on its own or as a filter's value
+        if i < chars.len() && chars[i] == '"' && (word.is_empty() || word.ends_with(':')) {
+            let quote = i + 1;
+            let end = chars[quote..]
+                .iter()
+                .position(|&c| c == '"')
+                .ok_or(QueryError::UnterminatedQuote(quote))?;
+            value = chars[quote..quote + end].iter().collect::<String>();
+            i = quote + end + 1;
+        } else if word.is_empty() {
+            return Err(QueryError::NothingToExclude(position));
+        }
+
+        tokens.push(Token {
+            excluded,
+            filter,
+            value: value.trim().to_string(),
+        });
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn parse(input: &str) -> Result<SearchQuery, QueryError> {
+        // Wednesday 2026-10-14
+        parse_query(
+            input,
+            &Utc.with_ymd_and_hms(2026, 10, 14, 9, 30, 0).unwrap(),
+        )
+    }
+
+    fn date(text: &str) -> Option<NaiveDateTime> {
+        Some(NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap())
+    }
+
+    #[test]
+    fn test_queries_are_split_into_terms_and_filters() {
+        let query = parse(
+            r#"app:firefox after:2026-10-01 before:"last friday" "exact phrase" -slack parseConfig"#,
+        )
+        .unwrap();
+        assert_eq!(
+            SearchQuery {
+                terms: vec!["exact phrase".to_string(), "parseConfig".to_string()],
+                excluded: vec!["slack".to_string()],
+                app: Some("firefox".to_string()),
+                after: date("2026-10-01 00:00"),
+                before: date("2026-10-09 00:00"),
+            },
+            query
+        );
+        assert_eq!("exact phrase parseConfig", query.text());
+
+        // Words that only look like filters, excluded phrases and filter names in any case
+        let query = parse(r#"localhost:8080 -"out of office" APP:"Google Chrome""#).unwrap();
+        assert_eq!(vec!["localhost:8080"], query.terms);
+        assert_eq!(vec!["out of office"], query.excluded);
+        assert_eq!(Some("Google Chrome".to_string()), query.app);
+
+        assert!(parse("  \"\" ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_queries_are_explained() {
+        assert_eq!(
+            Err(QueryError::UnterminatedQuote(7)),
+            parse(r#"error "unexpected token"#)
+        );
+        assert_eq!(Err(QueryError::NothingToExclude(7)), parse("error - slack"));
+        assert_eq!(Err(QueryError::MissingValue("app")), parse("app: firefox"));
+        assert_eq!(
+            Err(QueryError::MissingValue("before")),
+            parse(r#"before:"""#)
+        );
+        assert_eq!(
+            Err(QueryError::InvalidDate {
+                filter: "after",
+                value: "next week".to_string()
+            }),
+            parse(r#"after:"next week""#)
+        );
+        assert_eq!(
+            Err(QueryError::RepeatedFilter("app")),
+            parse("app:firefox app:slack")
+        );
+        assert_eq!(Err(QueryError::ExcludedFilter("app")), parse("-app:slack"));
+        assert_eq!(
+            Err(QueryError::EmptyRange),
+            parse("after:today before:yesterday")
+        );
+        assert!(parse(r#"after:"next week""#)
+            .unwrap_err()
+            .to_string()
+            .contains("last friday"));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::core::db::TopicRun;
+use crate::core::vector::{dot, mean, normalize};
+use crate::core::DbPool;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::thread;
+
+// Upper bound on the topics picked for a range when the caller doesn't say how many
+const MAX_TOPICS: usize = 12;
+const KMEANS_ITERATIONS: usize = 25;
+const KEYWORDS_PER_TOPIC: usize = 5;
+// Words too common on screen to say anything about a topic
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "you", "are", "with", "this", "that", "from", "your", "not", "but",
+    "have", "was", "can", "all", "will", "new", "has", "our", "out", "one", "more", "http",
+    "https", "www", "com",
+];
+
+// A group of frames that show similar content, and the words that set its text apart
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicCluster {
+    pub keywords: Vec<String>,
+    // The frame closest to the middle of the cluster
+    pub representative_frame_id: i64,
+    pub frame_ids: Vec<i64>,
+}
+
+// Clusters a time range's frames into topics in the background and stores them with the run,
+// marking it failed if anything goes wrong
+pub fn start_topic_job(db: DbPool, run: TopicRun, k: Option<usize>) {
+    thread::spawn(move || {
+        if let Err(e) = run_topic_job(&db, &run, k) {
+            println!("Failed to cluster topics: {:?}", e);
+            let (run_id, error) = (run.id, e.to_string());
+            if let Err(e) = db.write(move |db| db.fail_topic_run(run_id, &error)) {
+                println!("Failed to update topic run: {}", e);
+            }
+        }
+    });
+}
+
+fn run_topic_job(
+    db: &DbPool,
+    run: &TopicRun,
+    k: Option<usize>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let frames = db.read(|db| {
+        let mut frames = Vec::new();
+        for (frame_id, vector) in db.get_frame_vectors(&run.model_id, run.from, run.to)? {
+            let text = db.get_text_for_frame(frame_id)?.unwrap_or_default();
+            frames.push((frame_id, vector, text));
+        }
+        Ok::<_, rusqlite::Error>(frames)
+    });
+    let frames = match frames {
+        Some(frames) => frames?,
+        None => return Ok(()),
+    };
+
+    // Clustering can take a while for a long range, so the database isn't held meanwhile
+    let clusters = cluster_frames(&frames, k);
+
+    let run_id = run.id;
+    db.write(move |db| db.save_topics(run_id, &clusters))?;
+    Ok(())
+}
+
+// Groups (frame ID, embedding, text) by k-means over the embeddings and labels each group with
+// the TF-IDF keywords of its text. Without `k`, it grows with the square root of the frames.
+pub fn cluster_frames(frames: &[(i64, Vec<f32>, String)], k: Option<usize>) -> Vec<TopicCluster> {
+    if frames.is_empty() {
+        return vec![];
+    }
+    let k = k
+        .unwrap_or_else(|| ((frames.len() as f32 / 2.0).sqrt().round() as usize).min(MAX_TOPICS))
+        .clamp(1, frames.len());
+
+    let points = frames
+        .iter()
+        .map(|(_, vector, _)| normalize(vector))
+        .collect::<Vec<_>>();
+    let (assignments, centroids) = kmeans(&points, k);
+    let keywords = tfidf_keywords(
+        &frames
+            .iter()
+            .map(|(_, _, text)| text.as_str())
+            .collect::<Vec<_>>(),
+        &assignments,
+        k,
+    );
+
+    let mut clusters = Vec::new();
+    for (cluster, (centroid, keywords)) in centroids.iter().zip(keywords).enumerate() {
+        let members = (0..frames.len())
+            .filter(|&i| assignments[i] == cluster)
+            .collect::<Vec<_>>();
+        let representative = members
+            .iter()
+            .max_by(|&&a, &&b| dot(&points[a], centroid).total_cmp(&dot(&points[b], centroid)));
+        if let Some(&representative) = representative {
+            clusters.push(TopicCluster {
+                keywords,
+                representative_frame_id: frames[representative].0,
+                frame_ids: members.iter().map(|&i| frames[i].0).collect(),
+            });
+        }
+    }
+    // Biggest topics first
+    clusters.sort_by_key(|cluster| Reverse(cluster.frame_ids.len()));
+    clusters
+}
+
+// Spherical k-means over unit vectors, returns each point's cluster and the centroids.
+// Centroids start from points spread as far apart as possible, so runs are repeatable.
+fn kmeans(points: &[Vec<f32>], k: usize) -> (Vec<usize>, Vec<Vec<f32>>) {
+    let nearest = |point: &[f32], centroids: &[Vec<f32>]| {
+        centroids
+            .iter()
+            .enumerate()
+            .max_by(|a, b| dot(point, a.1).total_cmp(&dot(point, b.1)))
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    };
+
+    let mut centroids = vec![points[0].clone()];
+    while centroids.len() < k {
+        let farthest = points
+            .iter()
+            .min_by(|a, b| {
+                let closest =
+                    |p: &[f32]| centroids.iter().map(|c| dot(p, c)).fold(f32::MIN, f32::max);
+                closest(a).total_cmp(&closest(b))
+            })
+            .cloned()
+            .unwrap();
+        centroids.push(farthest);
+    }
+
+    let mut assignments = vec![usize::MAX; points.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut changed = false;
+        for (point, assignment) in points.iter().zip(assignments.iter_mut()) {
+            let cluster = nearest(point, &centroids);
+            if *assignment != cluster {
+                *assignment = cluster;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let members = points
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, &assignment)| assignment == cluster)
+                .map(|(point, _)| point.as_slice());
+            // A cluster that lost all its points keeps its centroid
+            if let Some(mean) = mean(members) {
+                *centroid = normalize(&mean);
+            }
+        }
+    }
+    (assignments, centroids)
+}
+
+// The words of each cluster's text that are frequent in it but rare in the others, scored
+// with class-based TF-IDF: each cluster's text is one document, and a word's weight falls
+// the more often it appears across all clusters
+fn tfidf_keywords(texts: &[&str], assignments: &[usize], k: usize) -> Vec<Vec<String>> {
+    let mut term_counts: Vec<HashMap<String, usize>> = vec![HashMap::new(); k];
+    let mut total_counts: HashMap<String, usize> = HashMap::new();
+    for (text, &cluster) in texts.iter().zip(assignments) {
+        for term in tokenize(text) {
+            *total_counts.entry(term.clone()).or_default() += 1;
+            *term_counts[cluster].entry(term).or_default() += 1;
+        }
+    }
+    let average_words = total_counts.values().sum::<usize>() as f32 / k as f32;
+
+    term_counts
+        .into_iter()
+        .map(|counts| {
+            let words = counts.values().sum::<usize>().max(1) as f32;
+            let mut scored = counts
+                .into_iter()
+                .map(|(term, count)| {
+                    let idf = (1.0 + average_words / total_counts[&term] as f32).ln();
+                    let score = count as f32 / words * idf;
+                    (term, score)
+                })
+                .collect::<Vec<_>>();
+            scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            scored
+                .into_iter()
+                .take(KEYWORDS_PER_TOPIC)
+                .map(|(term, _)| term)
+                .collect()
+        })
+        .collect()
+}
+
+// Lowercased words of three or more characters that aren't just digits or stop words
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3 && !word.chars().all(|c| c.is_numeric()))
+        .map(|word| word.to_lowercase())
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_are_grouped_and_labelled_by_topic() {
+        let frames = vec![
+            (
+                1,
+                vec![1.0, 0.1, 0.0],
+                "Invoice 2041 for the quarterly audit".to_string(),
+            ),
+            (
+                2,
+                vec![0.9, 0.2, 0.1],
+                "Invoice totals, audit notes".to_string(),
+            ),
+            (
+                3,
+                vec![0.0, 1.0, 0.1],
+                "kubectl get pods in the cluster".to_string(),
+            ),
+            (4, vec![0.1, 0.9, 0.0], "Cluster nodes and pods".to_string()),
+            (
+                5,
+                vec![0.1, 1.0, 0.2],
+                "Pods restarting in the cluster".to_string(),
+            ),
+        ];
+
+        let clusters = cluster_frames(&frames, Some(2));
+        assert_eq!(2, clusters.len());
+        assert_eq!(vec![3, 4, 5], clusters[0].frame_ids);
+        assert_eq!(vec![1, 2], clusters[1].frame_ids);
+        assert!(clusters[0]
+            .frame_ids
+            .contains(&clusters[0].representative_frame_id));
+        assert_eq!(vec!["cluster", "pods"], clusters[0].keywords[..2].to_vec());
+        assert_eq!(vec!["audit", "invoice"], clusters[1].keywords[..2].to_vec());
+
+        // Picked from the number of frames when not given
+        assert_eq!(2, cluster_frames(&frames, None).len());
+        assert!(cluster_frames(&[], None).is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+// Vector math shared by the embedding searches
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn norm(a: &[f32]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+// Scales to unit length, an all zeros vector stays as it is
+pub fn normalize(a: &[f32]) -> Vec<f32> {
+    let length = norm(a);
+    if length == 0.0 {
+        return a.to_vec();
+    }
+    a.iter().map(|x| x / length).collect()
+}
+
+// Element-wise mean of vectors with the first one's length, None when there are none
+pub fn mean<'a>(vectors: impl IntoIterator<Item = &'a [f32]>) -> Option<Vec<f32>> {
+    let mut vectors = vectors.into_iter().peekable();
+    let dimensions = vectors.peek()?.len();
+    let mut sum = vec![0.0; dimensions];
+    let mut count = 0;
+    for vector in vectors.filter(|v| v.len() == dimensions) {
+        for (total, value) in sum.iter_mut().zip(vector) {
+            *total += value;
+        }
+        count += 1;
+    }
+    Some(sum.into_iter().map(|total| total / count as f32).collect())
+}
+
+// Returns 0 when either vector is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        return 0.0;
+    }
+    dot(a, b) / denominator
+}
+
+// How stored vectors are encoded. Int8 keeps a scale and a byte per dimension, binary a
+// bit per dimension (its sign), so a 384 dimension vector takes 1536, 388 or 48 bytes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Quantization {
+    #[default]
+    Float32,
+    Int8,
+    Binary,
+}
+
+impl Quantization {
+    pub const ALL: [Quantization; 3] = [
+        Quantization::Float32,
+        Quantization::Int8,
+        Quantization::Binary,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quantization::Float32 => "float32",
+            Quantization::Int8 => "int8",
+            Quantization::Binary => "binary",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Quantization> {
+        Quantization::ALL
+            .into_iter()
+            .find(|q| q.as_str() == value.trim().to_lowercase())
+    }
+
+    // The encoding new vectors are stored with, from XREM_VECTOR_STORAGE
+    pub fn from_env() -> Quantization {
+        match std::env::var("XREM_VECTOR_STORAGE") {
+            Ok(value) => Quantization::parse(&value).unwrap_or_else(|| {
+                println!("Unknown XREM_VECTOR_STORAGE {}, storing float32", value);
+                Quantization::Float32
+            }),
+            Err(_) => Quantization::Float32,
+        }
+    }
+
+    pub fn encoded_size(&self, dimensions: usize) -> usize {
+        match self {
+            Quantization::Float32 => dimensions * 4,
+            Quantization::Int8 => 4 + dimensions,
+            Quantization::Binary => dimensions.div_ceil(8),
+        }
+    }
+
+    // Bytes per vector along with the float32 copy quantized vectors keep to rescore with
+    pub fn stored_size(&self, dimensions: usize) -> usize {
+        match self {
+            Quantization::Float32 => self.encoded_size(dimensions),
+            _ => self.encoded_size(dimensions) + Quantization::Float32.encoded_size(dimensions),
+        }
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        match self {
+            Quantization::Float32 => vector.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Quantization::Int8 => {
+                let (scale, codes) = quantize_int8(vector);
+                let mut blob = scale.to_le_bytes().to_vec();
+                blob.extend(codes.iter().map(|&c| c as u8));
+                blob
+            }
+            Quantization::Binary => {
+                let mut blob = vec![0u8; vector.len().div_ceil(8)];
+                for (i, v) in vector.iter().enumerate() {
+                    if *v > 0.0 {
+                        blob[i / 8] |= 1 << (i % 8);
+                    }
+                }
+                blob
+            }
+        }
+    }
+
+    // Binary vectors decode to +1 / -1 per dimension, which only keeps their direction
+    pub fn decode(&self, blob: &[u8], dimensions: usize) -> Vec<f32> {
+        match self {
+            Quantization::Float32 => blob
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+            Quantization::Int8 => {
+                let (scale, codes) = split_int8(blob);
+                codes.iter().map(|&c| c as i8 as f32 * scale).collect()
+            }
+            Quantization::Binary => (0..dimensions)
+                .map(|i| match blob.get(i / 8) {
+                    Some(byte) if byte & (1 << (i % 8)) != 0 => 1.0,
+                    _ => -1.0,
+                })
+                .collect(),
+        }
+    }
+}
+
+// A query vector prepared for cheap scoring against quantized vectors. Scores are only good
+// enough to pick candidates, which are then rescored with the float query against the decoded
+// vectors. That can't give back what a vector lost when it was quantized.
+pub struct QuantizedQuery<'a> {
+    vector: &'a [f32],
+    int8: Vec<i8>,
+    int8_norm: f32,
+    binary: Vec<u8>,
+}
+
+impl<'a> QuantizedQuery<'a> {
+    pub fn new(vector: &'a [f32]) -> QuantizedQuery<'a> {
+        let (_, int8) = quantize_int8(vector);
+        QuantizedQuery {
+            vector,
+            int8_norm: int8_norm(&int8),
+            int8,
+            binary: Quantization::Binary.encode(vector),
+        }
+    }
+
+    pub fn approximate_similarity(&self, quantization: Quantization, blob: &[u8]) -> f32 {
+        match quantization {
+            Quantization::Float32 => self.similarity(quantization, blob, self.vector.len()),
+            Quantization::Int8 => {
+                let (_, codes) = split_int8(blob);
+                let codes = codes.iter().map(|&c| c as i8).collect::<Vec<_>>();
+                let denominator = self.int8_norm * int8_norm(&codes);
+                if denominator == 0.0 {
+                    return 0.0;
+                }
+                let dot: i32 = self
+                    .int8
+                    .iter()
+                    .zip(&codes)
+                    .map(|(&a, &b)| a as i32 * b as i32)
+                    .sum();
+                dot as f32 / denominator
+            }
+            Quantization::Binary => {
+                let differing: u32 = self
+                    .binary
+                    .iter()
+                    .zip(blob)
+                    .map(|(a, b)| (a ^ b).count_ones())
+                    .sum();
+                1.0 - 2.0 * differing as f32 / self.vector.len().max(1) as f32
+            }
+        }
+    }
+
+    // Cosine similarity of the full-precision query and the decoded vector
+    pub fn similarity(&self, quantization: Quantization, blob: &[u8], dimensions: usize) -> f32 {
+        cosine_similarity(self.vector, &quantization.decode(blob, dimensions))
+    }
+}
+
+// Symmetric scalar quantization, the largest magnitude maps to 127
+fn quantize_int8(vector: &[f32]) -> (f32, Vec<i8>) {
+    let max = vector.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+    let scale = if max == 0.0 { 1.0 } else { max / 127.0 };
+    let codes = vector
+        .iter()
+        .map(|v| (v / scale).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    (scale, codes)
+}
+
+fn split_int8(blob: &[u8]) -> (f32, &[u8]) {
+    if blob.len() < 4 {
+        return (0.0, &[]);
+    }
+    let scale = f32::from_le_bytes([blob[0], blob[1], blob[2], blob[3]]);
+    (scale, &blob[4..])
+}
+
+fn int8_norm(codes: &[i8]) -> f32 {
+    (codes.iter().map(|&c| c as i32 * c as i32).sum::<i32>() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_similarity() {
+        assert!((cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 1.0], &[-1.0, -1.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(0.0, cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_quantized_vectors_keep_their_direction() {
+        let vector = [0.5, -0.25, 0.0, 0.125, -1.0, 0.75, 0.3, -0.6, 0.9];
+        assert_eq!(
+            vector.to_vec(),
+            Quantization::Float32.decode(&Quantization::Float32.encode(&vector), 9)
+        );
+
+        let query = QuantizedQuery::new(&vector);
+        for quantization in [Quantization::Int8, Quantization::Binary] {
+            let blob = quantization.encode(&vector);
+            assert_eq!(quantization.encoded_size(vector.len()), blob.len());
+            assert_eq!(vector.len(), quantization.decode(&blob, 9).len());
+            assert!(query.approximate_similarity(quantization, &blob) > 0.8);
+        }
+        let int8 = Quantization::Int8.encode(&vector);
+        assert!(query.similarity(Quantization::Int8, &int8, 9) > 0.999);
+        let opposite = vector.map(|v| -v);
+        let binary = Quantization::Binary.encode(&opposite);
+        assert!(query.approximate_similarity(Quantization::Binary, &binary) < -0.7);
+    }
+}
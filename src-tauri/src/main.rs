@@ -1,10 +1,14 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use crate::core::DatabaseManager;
+use crate::core::{
+    active_image_model_id, active_model_id, load_embedding_model, model_dirs, start_embed_worker,
+    DbPool, Governor, GovernorConfig, OcrWorker, Quantization,
+};
 use core::{start_recording, CaptureHandles};
 use std::{
     fs,
+    path::Path,
     sync::{Arc, Mutex},
 };
 use tauri::{
@@ -13,10 +17,11 @@ use tauri::{
 };
 use tokio::sync::oneshot;
 
+mod commands;
 mod core;
 mod server;
 
-fn start_server(local_data_dir: String, db: Arc<Mutex<Option<DatabaseManager>>>) {
+fn start_server(local_data_dir: String, db: DbPool) {
     println!("starting server...");
     let (tx, rx) = oneshot::channel();
     tokio::spawn(async move {
@@ -53,10 +58,21 @@ fn ensure_local_data_dir(app_handle: AppHandle) -> Result<String, ()> {
     Err(())
 }
 
-fn setup_db(local_data_dir: String, db: Arc<Mutex<Option<DatabaseManager>>>) {
-    let mut db = db.lock().unwrap();
-    let db_ = DatabaseManager::new(&format!("{}/db.sqlite", local_data_dir)).unwrap();
-    *db = Some(db_);
+fn setup_db(local_data_dir: String, db: DbPool) {
+    db.open(
+        &format!("{}/db.sqlite", local_data_dir),
+        Quantization::from_env(),
+    )
+    .unwrap();
+}
+
+// Loads the embedding models in the background, search and embeddings wait for them
+fn setup_embedding_model(app_handle: AppHandle, local_data_dir: String, db: DbPool) {
+    let dirs = model_dirs(
+        Path::new(&local_data_dir),
+        app_handle.path_resolver().resource_dir(),
+    );
+    load_embedding_model(db, dirs, active_model_id(), active_image_model_id());
 }
 
 #[tokio::main]
@@ -64,17 +80,26 @@ async fn main() {
     println!("starting app...");
     let is_capturing = Arc::new(Mutex::new(false));
     let handles: Arc<Mutex<Option<CaptureHandles>>> = Arc::new(Mutex::new(None));
-    let db: Arc<Mutex<Option<DatabaseManager>>> = Arc::new(Mutex::new(None));
+    let db = DbPool::default();
+    let governor = Governor::start(GovernorConfig::from_env());
+    let ocr_worker = OcrWorker::start(db.clone(), governor.clone());
+    start_embed_worker(db.clone(), governor.clone());
 
     let db_setup_ref = db.clone();
     let db_system_tray_ref = db.clone();
 
     tauri::Builder::default()
+        .manage(db.clone())
+        .invoke_handler(tauri::generate_handler![
+            commands::requeue_ocr,
+            commands::forget_frames
+        ])
         .setup(move |app| {
             let path = ensure_local_data_dir(app.app_handle()).unwrap_or_else(|_| {
                 panic!("Failed to create local data dir");
             });
             setup_db(path.clone(), db_setup_ref.clone());
+            setup_embedding_model(app.app_handle(), path.clone(), db_setup_ref.clone());
             start_server(path.clone(), db_setup_ref.clone());
             Ok(())
         })
@@ -97,6 +122,8 @@ async fn main() {
                             toggle_recording(
                                 app,
                                 db.clone(),
+                                ocr_worker.clone(),
+                                governor.clone(),
                                 is_capturing.clone(),
                                 handles.clone(),
                                 &item_handle,
@@ -200,7 +227,9 @@ fn stop_recording(
 
 fn toggle_recording(
     app: &AppHandle,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
+    db: DbPool,
+    ocr_worker: Arc<OcrWorker>,
+    governor: Arc<Governor>,
     is_capturing: Arc<Mutex<bool>>,
     handles: Arc<Mutex<Option<CaptureHandles>>>,
     item_handle: &SystemTrayMenuItemHandle,
@@ -215,7 +244,7 @@ fn toggle_recording(
 
             let mut is_capturing = is_capturing.lock().unwrap();
             let mut handles = handles.lock().unwrap();
-            *handles = Some(start_recording(path, db));
+            *handles = Some(start_recording(path, db, ocr_worker, governor));
             *is_capturing = true;
             item_handle.set_title("Stop Recording").unwrap();
         }
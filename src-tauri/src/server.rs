@@ -1,27 +1,66 @@
-use std::sync::Mutex;
-use std::{io::Cursor, sync::Arc};
+use std::collections::HashMap;
+use std::{io, sync::Arc};
 
 use axum::extract::Query;
 use axum::{
     body::Bytes,
     extract::{Path, State},
-    http::StatusCode,
-    routing::get,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Json, Router,
 };
-use chrono::Utc;
+use chrono::{Local, NaiveDateTime};
 
 use image::ImageOutputFormat;
 use serde::{Deserialize, Serialize};
 use tokio::sync::oneshot;
 use tower_http::cors::CorsLayer;
 
-use crate::core::{extract_frames_from_video, DatabaseManager};
+use crate::core::{
+    current_model_id, escape_like, extract_frames_from_video, generate_image_query_embedding,
+    generate_query_embedding, parse_query, start_topic_job, Cursor, DatabaseManager, DbPool,
+    EmbedError, HybridWeights, LowConfidence, Passage, QueryEmbedding, ResultCount, SearchFilters,
+    SearchOrder, SearchResult, UsagePeriod, WriteError, DATE_EXAMPLES,
+};
 
 #[derive(Clone)]
 struct AppState {
     local_data_dir: String,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
+    db: DbPool,
+}
+
+// Runs a query on one of the read-only connections. SQLite calls block, so they run on the
+// blocking thread pool instead of holding up the server's other requests.
+async fn read_db<F, R>(state: &AppState, f: F) -> R
+where
+    F: FnOnce(&DatabaseManager) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let db = state.db.clone();
+    tokio::task::spawn_blocking(move || db.read(f).expect("Database not open"))
+        .await
+        .expect("Database query panicked")
+}
+
+// Runs `f` on the writer and waits for it to be committed, off the async runtime too. Before
+// the database is open that's a 503, a write that failed a 500.
+async fn write_db<F, R>(state: &AppState, f: F) -> Result<R, StatusCode>
+where
+    F: FnOnce(&mut DatabaseManager) -> rusqlite::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    let db = state.db.clone();
+    tokio::task::spawn_blocking(move || db.write(f))
+        .await
+        .expect("Database write panicked")
+        .map_err(|e| match e {
+            WriteError::NotOpen => StatusCode::SERVICE_UNAVAILABLE,
+            e => {
+                println!("Failed to write to the database: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })
 }
 
 #[derive(Serialize)]
@@ -29,11 +68,70 @@ struct FrameInfo {
     max_frame: i64,
 }
 
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SearchMode {
+    Keyword,
+    Semantic,
+    #[default]
+    Hybrid,
+}
+
+// How keyword matches are ordered, `relevance` ranks the best text matches first
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SearchSort {
+    #[default]
+    Recent,
+    Relevance,
+}
+
+impl From<SearchSort> for SearchOrder {
+    fn from(sort: SearchSort) -> Self {
+        match sort {
+            SearchSort::Recent => SearchOrder::Recent,
+            SearchSort::Relevance => SearchOrder::Relevance,
+        }
+    }
+}
+
+// Page sizes when a request doesn't give one, and at most
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 200;
+
 #[derive(Deserialize)]
-struct Pagination {
+struct SearchRequest {
     search: Option<String>,
-    limit: i64,
-    offset: i64,
+    limit: Option<i64>,
+    // `next_cursor` of the previous page
+    cursor: Option<String>,
+    min_confidence: Option<f32>,
+    // Rank text below `min_confidence` last instead of leaving it out, keyword search only
+    down_rank: Option<bool>,
+    mode: Option<SearchMode>,
+    sort: Option<SearchSort>,
+    // Unix millis or a date like "yesterday afternoon", see `parse_time`
+    from: Option<String>,
+    to: Option<String>,
+    keyword_weight: Option<f32>,
+    semantic_weight: Option<f32>,
+}
+
+impl SearchRequest {
+    fn page_size(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+fn parse_cursor(cursor: Option<&str>) -> Result<Option<Cursor>, (StatusCode, String)> {
+    match cursor {
+        Some(cursor) => Cursor::decode(cursor)
+            .map(Some)
+            .ok_or((StatusCode::BAD_REQUEST, "Invalid cursor".to_string())),
+        None => Ok(None),
+    }
 }
 
 #[derive(Deserialize)]
@@ -49,20 +147,14 @@ async fn get_frame_handler(
     Query(query): Query<ImageParams>,
     State(state): State<Arc<AppState>>,
 ) -> (StatusCode, Bytes) {
-    let db_video_ref = state.db.clone();
-    let maybe_video_path = {
-        let mut db_clone = db_video_ref.lock().unwrap();
-        db_clone
-            .as_mut()
-            .unwrap()
-            .get_frame(frame_number)
-            .expect("Failed to get frame")
-    };
+    let maybe_video_path = read_db(&state, move |db| db.get_frame(frame_number))
+        .await
+        .expect("Failed to get frame");
     if let Some((offset_index, video_path)) = maybe_video_path {
         match extract_frames_from_video(&video_path, &[offset_index]) {
             Ok(frames) => {
                 if let Some(frame) = frames.into_iter().next() {
-                    let mut cursor = Cursor::new(Vec::new());
+                    let mut cursor = io::Cursor::new(Vec::new());
                     if query.thumbnail.unwrap_or(false) {
                         if frame
                             .thumbnail(800, 800)
@@ -82,16 +174,40 @@ async fn get_frame_handler(
     (StatusCode::NOT_FOUND, Bytes::new())
 }
 
+#[derive(Deserialize)]
+struct FrameAtQuery {
+    time: String,
+}
+
+#[derive(Serialize)]
+struct FrameAt {
+    frame_number: i64,
+    timestamp: i64,
+}
+
+// The frame recorded closest to a time, for jumping the timeline to e.g. "3:15pm yesterday"
+async fn get_frame_at_handler(
+    Query(query): Query<FrameAtQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<FrameAt>, (StatusCode, String)> {
+    let time = parse_time(Some(&query.time))?
+        .ok_or((StatusCode::BAD_REQUEST, "time is empty".to_string()))?;
+    let frame = read_db(&state, move |db| db.get_frame_near(time))
+        .await
+        .expect("Failed to get frame");
+    match frame {
+        Some((frame_number, timestamp)) => Ok(Json(FrameAt {
+            frame_number,
+            timestamp: timestamp.timestamp_millis(),
+        })),
+        None => Err((StatusCode::NOT_FOUND, "No frames recorded yet".to_string())),
+    }
+}
+
 async fn get_max_frame_handler(State(state): State<Arc<AppState>>) -> Json<FrameInfo> {
-    let db_video_ref = state.db.clone();
-    let max_frame = {
-        let mut db_clone = db_video_ref.lock().unwrap();
-        db_clone
-            .as_mut()
-            .unwrap()
-            .get_max_frame()
-            .expect("Failed to get max frame")
-    };
+    let max_frame = read_db(&state, |db| db.get_max_frame())
+        .await
+        .expect("Failed to get max frame");
     Json(FrameInfo { max_frame })
 }
 
@@ -99,59 +215,1019 @@ async fn get_max_frame_handler(State(state): State<Arc<AppState>>) -> Json<Frame
 struct Frame {
     frame_number: i64,
     timestamp: i64,
+    application_name: Option<String>,
+    chunk: ChunkInfo,
+    // Set for hybrid searches
+    score: Option<f32>,
+    keyword_rank: Option<usize>,
+    semantic_rank: Option<usize>,
+    similarity: Option<f32>,
+    passage: Option<PassageMatch>,
+    // Set for keyword matches, the matched text wrapped in <mark> tags
+    snippet: Option<String>,
+    highlight: Option<String>,
+    // Character offsets of the matches in the frame's text
+    match_offsets: Vec<(usize, usize)>,
+}
+
+// Where a frame is stored: the video file and the frame's index in it
+#[derive(Serialize)]
+struct ChunkInfo {
+    file_path: String,
+    offset_index: i64,
+}
+
+// The part of a frame's text a semantic hit matched
+#[derive(Serialize)]
+struct PassageMatch {
+    text: String,
+    // Character offsets into the frame's text
+    start: usize,
+    end: usize,
+    // Where the passage is on screen, in pixels of the captured frame
+    region: Option<ScreenRegion>,
+}
+
+#[derive(Serialize)]
+struct ScreenRegion {
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+}
+
+impl From<Passage> for PassageMatch {
+    fn from(passage: Passage) -> Self {
+        PassageMatch {
+            text: passage.text,
+            start: passage.start,
+            end: passage.end,
+            region: passage.region.map(|r| ScreenRegion {
+                left: r.left,
+                top: r.top,
+                width: r.width,
+                height: r.height,
+            }),
+        }
+    }
+}
+
+impl Frame {
+    fn from_result(result: SearchResult) -> Frame {
+        Frame {
+            frame_number: result.frame_id,
+            timestamp: result.timestamp.timestamp_millis(),
+            application_name: result.application_name,
+            chunk: ChunkInfo {
+                file_path: result.file_path,
+                offset_index: result.offset_index,
+            },
+            score: None,
+            keyword_rank: None,
+            semantic_rank: None,
+            similarity: None,
+            passage: None,
+            snippet: result.snippet,
+            highlight: result.highlight,
+            match_offsets: result.match_offsets,
+        }
+    }
 }
 
 #[derive(Serialize)]
 struct PaginatedFrames {
     data: Vec<Frame>,
+    // Pass as `cursor` to get the next page, None on the last page
+    next_cursor: Option<String>,
+    total: TotalCount,
+}
+
+// How many results there are in all, `exact` is false when it's an estimate
+#[derive(Serialize)]
+struct TotalCount {
+    count: i64,
+    exact: bool,
+}
+
+impl From<ResultCount> for TotalCount {
+    fn from(count: ResultCount) -> Self {
+        TotalCount {
+            count: count.count,
+            exact: count.exact,
+        }
+    }
 }
 
 async fn search_frames_handler(
-    Query(query): Query<Pagination>,
+    Query(query): Query<SearchRequest>,
     State(state): State<Arc<AppState>>,
-) -> Json<PaginatedFrames> {
-    let db_frames_ref = state.db.clone();
-    let results = {
-        let mut db_clone = db_frames_ref.lock().expect("Failed to acquire lock");
-
-        let search = query.search.unwrap_or("".to_string());
-        if search.is_empty() {
-            db_clone
-                .as_mut()
-                .unwrap()
-                .get_recent_results(query.limit, query.offset, None)
-                .expect("Failed to get recent results")
+) -> Result<Json<PaginatedFrames>, (StatusCode, String)> {
+    let limit = query.page_size();
+    let cursor = parse_cursor(query.cursor.as_deref())?;
+    let search = parse_query(query.search.as_deref().unwrap_or_default(), &Local::now())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    // Filters alone list the frames they match
+    let mode = if search.terms.is_empty() {
+        SearchMode::Keyword
+    } else {
+        query.mode.unwrap_or_default()
+    };
+    // Similarity scores have no notion of OCR confidence to rank by
+    if mode != SearchMode::Keyword && query.down_rank.unwrap_or(false) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "down_rank only applies to keyword search".to_string(),
+        ));
+    }
+
+    // Embed the query before taking the database lock, hybrid search without a model ranks by
+    // keywords alone
+    let query_embedding = match mode {
+        SearchMode::Semantic => {
+            Some(generate_query_embedding(&search.text()).map_err(embed_error_response)?)
+        }
+        SearchMode::Hybrid => generate_query_embedding(&search.text()).ok(),
+        SearchMode::Keyword => None,
+    };
+
+    // Both the query's after:/before: and the from/to parameters apply
+    let from = [search.after, parse_time(query.from.as_deref())?];
+    let to = [search.before, parse_time(query.to.as_deref())?];
+    let mut filters = SearchFilters {
+        app_name: search.app.as_deref().map(app_name_pattern),
+        from: from.into_iter().flatten().max(),
+        to: to.into_iter().flatten().min(),
+        exclude_terms: search.excluded.clone(),
+        min_confidence: query.min_confidence,
+        low_confidence: if query.down_rank.unwrap_or(false) {
+            LowConfidence::DownRank
         } else {
-            db_clone
-                .as_mut()
-                .unwrap()
-                .search(&search, query.limit, query.offset, None)
-                .expect("Failed to get search results")
+            LowConfidence::Ignore
+        },
+        order: query.sort.unwrap_or_default().into(),
+        ..Default::default()
+    };
+
+    // Results newest first page by the last one shown, ranked results by their position
+    let down_ranked =
+        filters.min_confidence.is_some() && filters.low_confidence == LowConfidence::DownRank;
+    let newest_first = search.is_empty()
+        || (mode == SearchMode::Keyword && filters.order == SearchOrder::Recent && !down_ranked);
+    let offset = match cursor {
+        Some(Cursor::After(key)) if newest_first => {
+            filters.older_than = Some(key);
+            0
+        }
+        Some(Cursor::Offset {
+            offset,
+            up_to_frame,
+        }) if !newest_first => {
+            filters.up_to_frame = Some(up_to_frame);
+            offset
+        }
+        None => 0,
+        Some(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Cursor is from a different search".to_string(),
+            ))
         }
     };
-    let mut data = Vec::new();
-    for frame in results {
-        let frame_number = frame.frame_id;
-        let timestamp = frame.timestamp;
-        data.push(Frame {
-            frame_number,
-            timestamp: timestamp.timestamp_millis(),
+
+    let (data, last_key, total, up_to_frame) = read_db(&state, move |db| {
+        // Ranked pages only count the frames there were when the first page was listed
+        if !newest_first && filters.up_to_frame.is_none() {
+            filters.up_to_frame = Some(db.get_max_frame().expect("Failed to get max frame"));
+        }
+        let up_to_frame = filters.up_to_frame.unwrap_or_default();
+        let (data, last_key, total) = if search.is_empty() {
+            let results = db
+                .get_recent_results(limit, offset, &filters)
+                .expect("Failed to get recent results");
+            let total = db.count_recent(&filters).expect("Failed to count results");
+            let last_key = results.last().map(SearchResult::page_key);
+            let data: Vec<Frame> = results.into_iter().map(Frame::from_result).collect();
+            (data, last_key, total)
+        } else {
+            match mode {
+                SearchMode::Keyword => {
+                    let results = db
+                        .search(&search.terms, limit, offset, &filters)
+                        .expect("Failed to get search results");
+                    let total = db
+                        .count_search(&search.terms, &filters)
+                        .expect("Failed to count results");
+                    let last_key = results.last().map(SearchResult::page_key);
+                    let data = results.into_iter().map(Frame::from_result).collect();
+                    (data, last_key, total)
+                }
+                SearchMode::Semantic => {
+                    let embedding = query_embedding
+                        .as_ref()
+                        .expect("Semantic search embeds the query");
+                    let data = db
+                        .semantic_search(
+                            &embedding.vector,
+                            &embedding.model_id,
+                            limit,
+                            offset,
+                            &filters,
+                        )
+                        .expect("Failed to get search results")
+                        .into_iter()
+                        .map(|hit| Frame {
+                            similarity: Some(hit.similarity),
+                            passage: hit.passage.map(PassageMatch::from),
+                            ..Frame::from_result(hit.result)
+                        })
+                        .collect();
+                    let total = db
+                        .count_embedded_frames(&embedding.model_id, &filters)
+                        .expect("Failed to count results");
+                    (data, None, total)
+                }
+                SearchMode::Hybrid => {
+                    let defaults = HybridWeights::default();
+                    let weights = HybridWeights {
+                        keyword: query.keyword_weight.unwrap_or(defaults.keyword),
+                        semantic: query.semantic_weight.unwrap_or(defaults.semantic),
+                        ..defaults
+                    };
+                    let data = db
+                        .hybrid_search(
+                            &search.terms,
+                            query_embedding
+                                .as_ref()
+                                .map(|e| (e.vector.as_slice(), e.model_id.as_str())),
+                            limit,
+                            offset,
+                            &filters,
+                            &weights,
+                        )
+                        .expect("Failed to get search results")
+                        .into_iter()
+                        .map(|hit| Frame {
+                            score: Some(hit.score),
+                            keyword_rank: hit.keyword_rank,
+                            semantic_rank: hit.semantic_rank,
+                            similarity: hit.similarity,
+                            passage: hit.passage.map(PassageMatch::from),
+                            ..Frame::from_result(hit.result)
+                        })
+                        .collect();
+                    let keyword_total = db
+                        .count_search(&search.terms, &filters)
+                        .expect("Failed to count results");
+                    // Hits of both kinds can be the same frame, so only the larger is certain
+                    let total = match &query_embedding {
+                        Some(embedding) => ResultCount {
+                            count: db
+                                .count_embedded_frames(&embedding.model_id, &filters)
+                                .expect("Failed to count results")
+                                .count
+                                .max(keyword_total.count),
+                            exact: false,
+                        },
+                        None => keyword_total,
+                    };
+                    (data, None, total)
+                }
+            }
+        };
+        (data, last_key, total, up_to_frame)
+    })
+    .await;
+
+    let next_cursor = match last_key {
+        _ if (data.len() as i64) < limit => None,
+        Some(key) if newest_first => Some(Cursor::After(key)),
+        _ => Some(Cursor::Offset {
+            offset: offset + limit,
+            up_to_frame,
+        }),
+    };
+    Ok(Json(PaginatedFrames {
+        data,
+        next_cursor: next_cursor.map(|cursor| cursor.encode()),
+        total: total.into(),
+    }))
+}
+
+#[derive(Serialize)]
+struct OcrQueueStatus {
+    counts: HashMap<String, i64>,
+}
+
+// The LIKE pattern for app names containing `app`
+fn app_name_pattern(app: &str) -> String {
+    format!("%{}%", escape_like(app))
+}
+
+// Reads a time parameter given as unix millis or as a date in the user's time zone
+fn parse_time(value: Option<&str>) -> Result<Option<NaiveDateTime>, (StatusCode, String)> {
+    let value = match value.map(str::trim) {
+        Some(value) if !value.is_empty() => value,
+        _ => return Ok(None),
+    };
+    crate::core::parse_time(value, &Local::now())
+        .map(Some)
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("\"{}\" isn't a time, try {}", value, DATE_EXAMPLES),
+            )
+        })
+}
+
+// Reads the `from` and `to` parameters of a time range that must be given
+fn parse_range(
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<(NaiveDateTime, NaiveDateTime), (StatusCode, String)> {
+    match (parse_time(from)?, parse_time(to)?) {
+        (Some(from), Some(to)) => Ok((from, to)),
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            "Both from and to are needed".to_string(),
+        )),
+    }
+}
+
+async fn get_ocr_status_handler(State(state): State<Arc<AppState>>) -> Json<OcrQueueStatus> {
+    let counts = read_db(&state, |db| db.get_ocr_queue_counts())
+        .await
+        .expect("Failed to get OCR queue counts");
+    Json(OcrQueueStatus {
+        counts: counts.into_iter().collect(),
+    })
+}
+
+#[derive(Serialize)]
+struct ReindexStatus {
+    model_id: Option<String>,
+    // The model searches use until the job finishes, None when no job is running
+    previous_model_id: Option<String>,
+    total: i64,
+    remaining: i64,
+}
+
+async fn get_reindex_status_handler(State(state): State<Arc<AppState>>) -> Json<ReindexStatus> {
+    let model_id = current_model_id();
+    let progress_model_id = model_id.clone();
+    let (job, progress) = read_db(&state, move |db| {
+        let job = db.get_reindex_job().expect("Failed to get re-index job");
+        let progress = progress_model_id.as_ref().map(|model_id| {
+            db.get_reindex_progress(model_id)
+                .expect("Failed to get re-index progress")
         });
+        (job, progress)
+    })
+    .await;
+    Json(ReindexStatus {
+        previous_model_id: job
+            .filter(|job| Some(&job.model_id) == model_id.as_ref())
+            .and_then(|job| job.previous_model_id),
+        model_id,
+        total: progress.map(|p| p.total).unwrap_or(0),
+        remaining: progress.map(|p| p.remaining).unwrap_or(0),
+    })
+}
+
+#[derive(Serialize)]
+struct ModelStorage {
+    model_id: String,
+    dimensions: usize,
+    embeddings: i64,
+    stored_bytes: i64,
+    index_bytes: u64,
+    estimated_bytes: HashMap<String, i64>,
+}
+
+#[derive(Serialize)]
+struct EmbeddingStorageReport {
+    quantization: String,
+    models: Vec<ModelStorage>,
+}
+
+async fn get_embedding_storage_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<EmbeddingStorageReport> {
+    let (quantization, storage) = read_db(&state, |db| {
+        let storage = db
+            .get_embedding_storage()
+            .expect("Failed to get embedding storage");
+        (db.quantization(), storage)
+    })
+    .await;
+    Json(EmbeddingStorageReport {
+        quantization: quantization.as_str().to_string(),
+        models: storage
+            .into_iter()
+            .map(|s| ModelStorage {
+                model_id: s.model_id,
+                dimensions: s.dimensions,
+                embeddings: s.embeddings,
+                stored_bytes: s.stored_bytes,
+                index_bytes: s.index_bytes,
+                estimated_bytes: s
+                    .estimated_bytes
+                    .into_iter()
+                    .map(|(q, bytes)| (q.as_str().to_string(), bytes))
+                    .collect(),
+            })
+            .collect(),
+    })
+}
+
+#[derive(Serialize)]
+struct EmbeddingCacheStatus {
+    memory_hits: u64,
+    database_hits: u64,
+    misses: u64,
+    hit_rate: f64,
+    memory_entries: usize,
+    database_entries: i64,
+}
+
+async fn get_embedding_cache_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<EmbeddingCacheStatus> {
+    let (database_entries, stats) = read_db(&state, |db| {
+        (db.get_embedding_cache_count(), db.embedding_cache().stats())
+    })
+    .await;
+    let database_entries = database_entries.expect("Failed to count cached embeddings");
+    Json(EmbeddingCacheStatus {
+        memory_hits: stats.memory_hits,
+        database_hits: stats.database_hits,
+        misses: stats.misses,
+        hit_rate: stats.hit_rate(),
+        memory_entries: stats.memory_entries,
+        database_entries,
+    })
+}
+
+#[derive(Deserialize)]
+struct SemanticQuery {
+    q: String,
+    limit: Option<i64>,
+    // `next_cursor` of the previous page
+    cursor: Option<String>,
+    app: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SemanticFrame {
+    frame_number: i64,
+    timestamp: i64,
+    application_name: Option<String>,
+    similarity: f32,
+    passage: Option<PassageMatch>,
+}
+
+#[derive(Serialize)]
+struct SemanticFrames {
+    data: Vec<SemanticFrame>,
+    next_cursor: Option<String>,
+    total: TotalCount,
+}
+
+async fn semantic_search_handler(
+    Query(query): Query<SemanticQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SemanticFrames>, (StatusCode, String)> {
+    let query_embedding = generate_query_embedding(&query.q).map_err(embed_error_response)?;
+    Ok(Json(
+        search_by_embedding(&state, query, query_embedding).await?,
+    ))
+}
+
+// Finds frames by what their screenshots show, with the query embedded by the image model
+async fn image_search_handler(
+    Query(query): Query<SemanticQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SemanticFrames>, (StatusCode, String)> {
+    let query_embedding = generate_image_query_embedding(&query.q).map_err(embed_error_response)?;
+    Ok(Json(
+        search_by_embedding(&state, query, query_embedding).await?,
+    ))
+}
+
+fn embed_error_response(e: EmbedError) -> (StatusCode, String) {
+    match e {
+        EmbedError::ModelNotInitialized => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
     }
-    Json(PaginatedFrames { data })
 }
 
-pub async fn start_frame_server(
-    tx: oneshot::Sender<()>,
-    local_data_dir: String,
-    db: Arc<Mutex<Option<DatabaseManager>>>,
-) {
+async fn search_by_embedding(
+    state: &AppState,
+    query: SemanticQuery,
+    query_embedding: QueryEmbedding,
+) -> Result<SemanticFrames, (StatusCode, String)> {
+    let mut filters = SearchFilters {
+        app_name: query.app.as_deref().map(app_name_pattern),
+        from: parse_time(query.from.as_deref())?,
+        to: parse_time(query.to.as_deref())?,
+        ..Default::default()
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    // Results are ranked by similarity, so pages are a number of results in
+    let offset = match parse_cursor(query.cursor.as_deref())? {
+        Some(Cursor::Offset {
+            offset,
+            up_to_frame,
+        }) => {
+            filters.up_to_frame = Some(up_to_frame);
+            offset
+        }
+        None => 0,
+        Some(Cursor::After(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Cursor is from a different search".to_string(),
+            ))
+        }
+    };
+    let (results, total, up_to_frame) = read_db(state, move |db| {
+        let up_to_frame = match filters.up_to_frame {
+            Some(up_to_frame) => up_to_frame,
+            None => db.get_max_frame().expect("Failed to get max frame"),
+        };
+        filters.up_to_frame = Some(up_to_frame);
+        let results = db
+            .semantic_search(
+                &query_embedding.vector,
+                &query_embedding.model_id,
+                limit,
+                offset,
+                &filters,
+            )
+            .expect("Failed to get semantic search results");
+        let total = db
+            .count_embedded_frames(&query_embedding.model_id, &filters)
+            .expect("Failed to count results");
+        (results, total, up_to_frame)
+    })
+    .await;
+    let next_cursor = if results.len() as i64 == limit {
+        Some(
+            Cursor::Offset {
+                offset: offset + limit,
+                up_to_frame,
+            }
+            .encode(),
+        )
+    } else {
+        None
+    };
+
+    let data = results
+        .into_iter()
+        .map(|r| SemanticFrame {
+            frame_number: r.result.frame_id,
+            timestamp: r.result.timestamp.timestamp_millis(),
+            application_name: r.result.application_name,
+            similarity: r.similarity,
+            passage: r.passage.map(PassageMatch::from),
+        })
+        .collect();
+    Ok(SemanticFrames {
+        data,
+        next_cursor,
+        total: total.into(),
+    })
+}
+
+// Related frames when a request doesn't give a limit, and at most
+const DEFAULT_RELATED_FRAMES: i64 = 20;
+const MAX_RELATED_FRAMES: i64 = 200;
+
+#[derive(Deserialize)]
+struct RelatedQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct RelatedFrameInfo {
+    frame_number: i64,
+    timestamp: i64,
+    application_name: Option<String>,
+    score: f32,
+    similarity: Option<f32>,
+    hash_distance: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct RelatedFrames {
+    data: Vec<RelatedFrameInfo>,
+}
+
+// Frames from other sessions that show something like this one, by text and by looks
+async fn related_frames_handler(
+    Path(frame_number): Path<i64>,
+    Query(query): Query<RelatedQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<RelatedFrames>, StatusCode> {
+    let model_id = current_model_id();
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_RELATED_FRAMES)
+        .clamp(1, MAX_RELATED_FRAMES);
+    let related = read_db(&state, move |db| {
+        if db
+            .get_session_bounds(frame_number)
+            .expect("Failed to get session")
+            .is_none()
+        {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Ok(db
+            .related_frames(frame_number, model_id.as_deref(), limit)
+            .expect("Failed to get related frames"))
+    })
+    .await?;
+
+    let data = related
+        .into_iter()
+        .map(|r| RelatedFrameInfo {
+            frame_number: r.result.frame_id,
+            timestamp: r.result.timestamp.timestamp_millis(),
+            application_name: r.result.application_name,
+            score: r.score,
+            similarity: r.similarity,
+            hash_distance: r.hash_distance,
+        })
+        .collect();
+    Ok(Json(RelatedFrames { data }))
+}
+
+#[derive(Deserialize)]
+struct TopicQuery {
+    // Unix millis or a date, see `parse_time`
+    from: Option<String>,
+    to: Option<String>,
+    k: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct TopicRunStarted {
+    run_id: i64,
+}
+
+#[derive(Serialize)]
+struct TopicSpan {
+    from: i64,
+    to: i64,
+}
+
+#[derive(Serialize)]
+struct TopicInfo {
+    keywords: Vec<String>,
+    representative_frame_number: i64,
+    frame_count: i64,
+    spans: Vec<TopicSpan>,
+}
+
+#[derive(Serialize)]
+struct Topics {
+    status: String,
+    error: Option<String>,
+    data: Vec<TopicInfo>,
+}
+
+// Starts clustering a time range's frames into topics, poll GET /topics for the result
+async fn start_topics_handler(
+    Query(query): Query<TopicQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<TopicRunStarted>, (StatusCode, String)> {
+    let (from, to) = parse_range(query.from.as_deref(), query.to.as_deref())?;
+    let model_id =
+        current_model_id().ok_or_else(|| embed_error_response(EmbedError::ModelNotInitialized))?;
+    let run = write_db(&state, move |db| db.start_topic_run(from, to, &model_id))
+        .await
+        .map_err(|status| (status, "Failed to start topics".to_string()))?;
+    let run_id = run.id;
+    start_topic_job(state.db.clone(), run, query.k);
+    Ok(Json(TopicRunStarted { run_id }))
+}
+
+async fn get_topics_handler(
+    Query(query): Query<TopicQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Topics>, (StatusCode, String)> {
+    let (from, to) = parse_range(query.from.as_deref(), query.to.as_deref())?;
+    let (run, topics) = read_db(&state, move |db| {
+        let run = db
+            .get_topic_run(from, to)
+            .expect("Failed to get topic run")
+            .ok_or((
+                StatusCode::NOT_FOUND,
+                "No topics for this range yet, POST /topics to find them".to_string(),
+            ))?;
+        let topics = db.get_topics(run.id).expect("Failed to get topics");
+        Ok::<_, (StatusCode, String)>((run, topics))
+    })
+    .await?;
+
+    let data = topics
+        .into_iter()
+        .map(|topic| TopicInfo {
+            keywords: topic.keywords,
+            representative_frame_number: topic.representative_frame_id,
+            frame_count: topic.frame_count,
+            spans: topic
+                .spans
+                .into_iter()
+                .map(|(from, to)| TopicSpan {
+                    from: from.timestamp_millis(),
+                    to: to.timestamp_millis(),
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(Json(Topics {
+        status: run.status.as_str().to_string(),
+        error: run.error,
+        data,
+    }))
+}
+
+#[derive(Deserialize)]
+struct SessionsQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    id: i64,
+    from: i64,
+    to: i64,
+    application_name: Option<String>,
+    window_title: Option<String>,
+    first_frame_number: i64,
+    last_frame_number: i64,
+    frame_count: i64,
+}
+
+#[derive(Serialize)]
+struct Sessions {
+    data: Vec<SessionInfo>,
+}
+
+// Sessions of frames overlapping a time range, oldest first, for the timeline's overview
+async fn get_sessions_handler(
+    Query(query): Query<SessionsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Sessions>, (StatusCode, String)> {
+    let from = parse_time(query.from.as_deref())?;
+    let to = parse_time(query.to.as_deref())?;
+    let sessions = read_db(&state, move |db| db.get_sessions(from, to))
+        .await
+        .expect("Failed to get sessions");
+    let data = sessions
+        .into_iter()
+        .map(|session| SessionInfo {
+            id: session.id,
+            from: session.start.timestamp_millis(),
+            to: session.end.timestamp_millis(),
+            application_name: session.application_name,
+            window_title: session.window_title,
+            first_frame_number: session.first_frame_id,
+            last_frame_number: session.last_frame_id,
+            frame_count: session.frame_count,
+        })
+        .collect();
+    Ok(Json(Sessions { data }))
+}
+
+// Window titles in /stats/titles when a request doesn't give a limit, and at most
+const DEFAULT_TOP_TITLES: i64 = 20;
+const MAX_TOP_TITLES: i64 = 500;
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum StatsFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    // Unix millis or a date like "last friday", see `parse_time`
+    from: Option<String>,
+    to: Option<String>,
+    period: Option<UsagePeriod>,
+    limit: Option<i64>,
+    format: Option<StatsFormat>,
+}
+
+#[derive(Serialize)]
+struct Stats<T> {
+    data: Vec<T>,
+}
+
+#[derive(Serialize)]
+struct AppUsageInfo {
+    // The day, or the Monday the week starts on, as YYYY-MM-DD
+    period: String,
+    application_name: Option<String>,
+    seconds: f64,
+    frame_count: i64,
+}
+
+#[derive(Serialize)]
+struct WindowTitleInfo {
+    application_name: Option<String>,
+    window_title: String,
+    seconds: f64,
+    frame_count: i64,
+}
+
+#[derive(Serialize)]
+struct HourUsageInfo {
+    // 0 is Monday
+    weekday: u32,
+    hour: u32,
+    seconds: f64,
+}
+
+// Seconds local time is ahead of UTC. It's today's offset, so days on the other side of a
+// daylight saving change are grouped an hour off.
+fn local_utc_offset() -> i32 {
+    Local::now().offset().local_minus_utc()
+}
+
+// Quotes a CSV field if it has a comma, quote or line break in it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Stats rows as JSON `{data: [...]}`, or as CSV with `columns` as its header
+fn stats_response<T, F>(format: StatsFormat, columns: &[&str], rows: Vec<T>, fields: F) -> Response
+where
+    T: Serialize,
+    F: Fn(&T) -> Vec<String>,
+{
+    match format {
+        StatsFormat::Json => Json(Stats { data: rows }).into_response(),
+        StatsFormat::Csv => {
+            let mut csv = columns.join(",") + "\r\n";
+            for row in &rows {
+                let fields = fields(row)
+                    .iter()
+                    .map(|field| csv_field(field))
+                    .collect::<Vec<_>>();
+                csv += &(fields.join(",") + "\r\n");
+            }
+            ([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], csv).into_response()
+        }
+    }
+}
+
+// Time on screen per application per day or week (`period=week`)
+async fn app_usage_handler(
+    Query(query): Query<StatsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, (StatusCode, String)> {
+    let from = parse_time(query.from.as_deref())?;
+    let to = parse_time(query.to.as_deref())?;
+    let period = query.period.unwrap_or_default();
+    let usage = read_db(&state, move |db| {
+        db.get_app_usage(period, from, to, local_utc_offset())
+    })
+    .await
+    .expect("Failed to get app usage");
+    let rows = usage
+        .into_iter()
+        .map(|usage| AppUsageInfo {
+            period: usage.period.to_string(),
+            application_name: usage.application_name,
+            seconds: usage.seconds,
+            frame_count: usage.frame_count,
+        })
+        .collect();
+    Ok(stats_response(
+        query.format.unwrap_or_default(),
+        &["period", "application_name", "seconds", "frame_count"],
+        rows,
+        |row: &AppUsageInfo| {
+            vec![
+                row.period.clone(),
+                row.application_name.clone().unwrap_or_default(),
+                row.seconds.to_string(),
+                row.frame_count.to_string(),
+            ]
+        },
+    ))
+}
+
+// The window titles on screen longest
+async fn window_titles_handler(
+    Query(query): Query<StatsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, (StatusCode, String)> {
+    let from = parse_time(query.from.as_deref())?;
+    let to = parse_time(query.to.as_deref())?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_TOP_TITLES)
+        .clamp(1, MAX_TOP_TITLES);
+    let titles = read_db(&state, move |db| db.get_top_window_titles(from, to, limit))
+        .await
+        .expect("Failed to get window titles");
+    let rows = titles
+        .into_iter()
+        .map(|title| WindowTitleInfo {
+            application_name: title.application_name,
+            window_title: title.window_title,
+            seconds: title.seconds,
+            frame_count: title.frame_count,
+        })
+        .collect();
+    Ok(stats_response(
+        query.format.unwrap_or_default(),
+        &["application_name", "window_title", "seconds", "frame_count"],
+        rows,
+        |row: &WindowTitleInfo| {
+            vec![
+                row.application_name.clone().unwrap_or_default(),
+                row.window_title.clone(),
+                row.seconds.to_string(),
+                row.frame_count.to_string(),
+            ]
+        },
+    ))
+}
+
+// Time on screen by day of the week and hour of the day, for a heatmap
+async fn hourly_usage_handler(
+    Query(query): Query<StatsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, (StatusCode, String)> {
+    let from = parse_time(query.from.as_deref())?;
+    let to = parse_time(query.to.as_deref())?;
+    let usage = read_db(&state, move |db| {
+        db.get_hourly_usage(from, to, local_utc_offset())
+    })
+    .await
+    .expect("Failed to get hourly usage");
+    let rows = usage
+        .into_iter()
+        .map(|usage| HourUsageInfo {
+            weekday: usage.weekday,
+            hour: usage.hour,
+            seconds: usage.seconds,
+        })
+        .collect();
+    Ok(stats_response(
+        query.format.unwrap_or_default(),
+        &["weekday", "hour", "seconds"],
+        rows,
+        |row: &HourUsageInfo| {
+            vec![
+                row.weekday.to_string(),
+                row.hour.to_string(),
+                row.seconds.to_string(),
+            ]
+        },
+    ))
+}
+
+pub async fn start_frame_server(tx: oneshot::Sender<()>, local_data_dir: String, db: DbPool) {
     let state = Arc::new(AppState { local_data_dir, db });
 
     let app = Router::new()
         .route("/frames", get(search_frames_handler))
         .route("/frames/max", get(get_max_frame_handler))
+        .route("/frames/at", get(get_frame_at_handler))
         .route("/frames/:frame_number", get(get_frame_handler))
+        .route("/frames/:frame_number/related", get(related_frames_handler))
+        .route("/search/semantic", get(semantic_search_handler))
+        .route("/search/image", get(image_search_handler))
+        .route(
+            "/topics",
+            get(get_topics_handler).post(start_topics_handler),
+        )
+        .route("/sessions", get(get_sessions_handler))
+        .route("/stats/apps", get(app_usage_handler))
+        .route("/stats/titles", get(window_titles_handler))
+        .route("/stats/hours", get(hourly_usage_handler))
+        .route("/ocr", get(get_ocr_status_handler))
+        .route("/embeddings/reindex", get(get_reindex_status_handler))
+        .route("/embeddings/storage", get(get_embedding_storage_handler))
+        .route("/embeddings/cache", get(get_embedding_cache_handler))
         .layer(CorsLayer::permissive())
         .with_state(state);
 